@@ -0,0 +1,17 @@
+// block_device and storage of the firmware, compiled with std
+// the firmware is a no_std binary for the board, this crate runs on the host,
+// see tests/ and the README
+#[macro_use]
+extern crate bitflags;
+extern crate core;
+
+// the firmware takes Vec and String from the collections crate
+mod collections {
+    pub use std::string;
+    pub use std::vec;
+}
+
+#[path = "../src/block_device.rs"]
+pub mod block_device;
+#[path = "../src/storage/mod.rs"]
+pub mod storage;
@@ -0,0 +1,108 @@
+extern crate storage_host;
+
+mod common;
+
+use common::*;
+use storage_host::storage::error::StorageError;
+use storage_host::storage::fat32_device_driver::Fat32DeviceDriver;
+use storage_host::storage::format::*;
+use storage_host::storage::fsck::{self, Problem};
+use std::mem;
+
+#[test]
+fn reset_keeps_everything_up_to_the_last_checkpoint() {
+    let image = Image::new("append", 48 * MIB);
+    let data = pattern(10000, 7);
+    {
+        let disk = image.open();
+        format_fat32(&disk, &FormatOptions::new(&clock())).unwrap();
+        let driver = Fat32DeviceDriver::new(&disk).unwrap();
+        let mut writer = driver.append("/LOG.BIN", 3000).unwrap();
+        for chunk in data.chunks(333) {
+            writer.write(chunk).unwrap();
+        }
+        assert!(writer.checkpoint_len() >= 9000);
+        // a reset: no last checkpoint
+        mem::forget(writer);
+    }
+
+    let disk = image.open();
+    let driver = Fat32DeviceDriver::new(&disk).unwrap();
+    let log = driver.read_file_to_vec("/LOG.BIN").unwrap();
+    assert!(log.len() >= 9000);
+    assert_eq!(&log[..], &data[..log.len()]);
+    // clusters behind the checkpoint are still linked, nothing else is wrong
+    for finding in fsck::check(&driver, false).unwrap() {
+        match *finding.problem() {
+            Problem::WrongFileSize { .. } => {}
+            ref problem => panic!("{:?}", problem),
+        }
+    }
+
+    {
+        let mut writer = driver.append("/LOG.BIN", 3000).unwrap();
+        writer.write(&data[log.len()..]).unwrap();
+    }
+    assert_eq!(driver.read_file_to_vec("/LOG.BIN").unwrap(), data);
+    assert_eq!(fsck::check(&driver, false).unwrap(), []);
+}
+
+#[test]
+fn new_files_get_an_entry_like_write_file() {
+    let image = Image::golden("fat32");
+    let disk = image.open();
+    let driver = Fat32DeviceDriver::new(&disk).unwrap();
+    // ".", "..", RUN01.BIN, EMPTY and 12 more fill the cluster of LOGS
+    for i in 0..12 {
+        driver.write_file(&format!("/LOGS/F{}.TXT", i), b"f").unwrap();
+    }
+    let logs = driver.lookup("/LOGS").unwrap().first_cluster();
+    assert_eq!(driver.cluster_chain(logs).unwrap().len(), 1);
+    {
+        let mut writer = driver.append("/LOGS/NEW.LOG", 512).unwrap();
+        writer.write(b"grown").unwrap();
+    }
+    assert_eq!(driver.cluster_chain(logs).unwrap().len(), 2);
+    assert_eq!(driver.read_file_to_vec("/LOGS/NEW.LOG").unwrap(), b"grown");
+    assert_eq!(fsck::check(&driver, false).unwrap(), []);
+
+    // the fixed root directory of FAT16 holds 512 entries, 6 are taken
+    let image = Image::golden("fat16");
+    let disk = image.open();
+    let driver = Fat32DeviceDriver::new(&disk).unwrap();
+    for i in 0..506 {
+        driver.write_file(&format!("/F{}.TXT", i), b"").unwrap();
+    }
+    let free_clusters = driver.free_space().unwrap().free_clusters();
+    assert_eq!(driver.append("/NEW.LOG", 512).err(), Some(StorageError::NoSpace));
+    assert_eq!(driver.free_space().unwrap().free_clusters(), free_clusters);
+}
+
+#[test]
+fn open_files_keep_their_entry() {
+    let image = Image::new("append_in_use", 48 * MIB);
+    let disk = image.open();
+    format_fat32(&disk, &FormatOptions::new(&clock())).unwrap();
+    let driver = Fat32DeviceDriver::new(&disk).unwrap();
+    let data = pattern(3000, 3);
+    driver.write_file("/A.TXT", b"a").unwrap();
+    {
+        let mut writer = driver.append("/LOG.BIN", 1000).unwrap();
+        writer.write(&data[..1500]).unwrap();
+        assert_eq!(driver.rename("/LOG.BIN", "/OLD.BIN"), Err(StorageError::InUse));
+        assert_eq!(driver.rename("/log.bin", "/Log of today.bin"), Err(StorageError::InUse));
+        assert_eq!(driver.remove_file("/LOG.BIN"), Err(StorageError::InUse));
+        assert_eq!(driver.write_file("/LOG.BIN", b"x"), Err(StorageError::InUse));
+        assert_eq!(driver.append("/LOG.BIN", 1000).err(), Some(StorageError::InUse));
+
+        // other entries of the directory still change, the checkpoint hits the right one
+        driver.rename("/A.TXT", "/A long name.txt").unwrap();
+        driver.remove_file("/A long name.txt").unwrap();
+        driver.write_file("/B.TXT", b"b").unwrap();
+        writer.write(&data[1500..]).unwrap();
+    }
+    driver.rename("/LOG.BIN", "/OLD.BIN").unwrap();
+    assert_eq!(driver.read_file_to_vec("/OLD.BIN").unwrap(), data);
+    assert_eq!(driver.read_file_to_vec("/B.TXT").unwrap(), b"b");
+    assert_eq!(fsck::check(&driver, false).unwrap(), []);
+}
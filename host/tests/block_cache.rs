@@ -0,0 +1,99 @@
+extern crate storage_host;
+
+mod common;
+
+use common::*;
+use storage_host::block_device::BlockDevice;
+use storage_host::storage::block_cache::BlockCache;
+use storage_host::storage::fat32_device_driver::Fat32DeviceDriver;
+use storage_host::storage::format::*;
+use storage_host::storage::fsck;
+
+#[test]
+fn file_system_behind_the_cache() {
+    for &write_through in &[false, true] {
+        let image = Image::new(if write_through { "through" } else { "back" }, 48 * MIB);
+        let disk = image.open();
+        let big = pattern(50000, 3);
+        {
+            let cache = BlockCache::new(&disk, 64, write_through);
+            format_fat32(&cache, &FormatOptions::new(&clock())).unwrap();
+            let driver = Fat32DeviceDriver::new(&cache).unwrap();
+            for i in 0..20 {
+                driver.write_file(&format!("/F{}.TXT", i), &big[..i * 700]).unwrap();
+            }
+            driver.create_dir("/SUB").unwrap();
+            driver.write_file("/SUB/BIG.BIN", &big).unwrap();
+            {
+                let mut writer = driver.append("/LOG.BIN", 1000).unwrap();
+                for chunk in big.chunks(100) {
+                    writer.write(chunk).unwrap();
+                }
+            }
+            assert_eq!(driver.read_file_to_vec("/LOG.BIN").unwrap(), big);
+            if write_through {
+                assert_eq!(cache.number_of_dirty_blocks(), 0);
+            }
+            cache.flush().unwrap();
+            assert_eq!(cache.number_of_dirty_blocks(), 0);
+        }
+
+        let driver = Fat32DeviceDriver::new(&disk).unwrap();
+        assert_eq!(driver.read_file_to_vec("/SUB/BIG.BIN").unwrap(), big);
+        assert_eq!(driver.read_file_to_vec("/F19.TXT").unwrap(), &big[..19 * 700]);
+        assert_eq!(fsck::check(&driver, false).unwrap(), []);
+    }
+}
+
+#[test]
+fn dirty_blocks_reach_the_device_on_flush() {
+    let image = Image::new("dirty", MIB);
+    let disk = image.open();
+    let cache = BlockCache::new(&disk, 8, false);
+    cache.write(5, &[1; 512]).unwrap();
+    // more blocks than half the cache: read from the device, the dirty block on top
+    let mut blocks = vec![0; 10 * 512];
+    cache.read(0, &mut blocks).unwrap();
+    assert_eq!(&blocks[5 * 512..6 * 512], &[1; 512][..]);
+    assert_eq!(blocks[0], 0);
+
+    let mut block = vec![0; 512];
+    disk.read(5, &mut block).unwrap();
+    assert_eq!(block[0], 0);
+    cache.flush().unwrap();
+    disk.read(5, &mut block).unwrap();
+    assert_eq!(block[0], 1);
+    assert!(cache.read(2047, &mut blocks[..1024]).is_err());
+}
+
+#[test]
+fn single_blocks_are_cached_with_one_slot() {
+    let image = Image::new("one_slot", MIB);
+    let disk = image.open();
+    let cache = BlockCache::new(&disk, 1, false);
+    cache.write(3, &[3; 512]).unwrap();
+    assert_eq!(cache.number_of_dirty_blocks(), 1);
+    let mut block = vec![0; 512];
+    disk.read(3, &mut block).unwrap();
+    assert_eq!(block[0], 0);
+    cache.read(3, &mut block).unwrap();
+    assert_eq!(block[0], 3);
+
+    // the next block takes the slot, the old one is written back
+    cache.write(4, &[4; 512]).unwrap();
+    disk.read(3, &mut block).unwrap();
+    assert_eq!(block[0], 3);
+    let mut blocks = vec![0; 2 * 512];
+    cache.read(3, &mut blocks).unwrap();
+    assert_eq!((blocks[0], blocks[512]), (3, 4));
+    cache.flush().unwrap();
+    disk.read(4, &mut block).unwrap();
+    assert_eq!(block[0], 4);
+
+    // without slots the cache passes everything on
+    let cache = BlockCache::from_slice(&disk, &mut [], false);
+    cache.write(5, &[5; 512]).unwrap();
+    assert_eq!(cache.number_of_dirty_blocks(), 0);
+    disk.read(5, &mut block).unwrap();
+    assert_eq!(block[0], 5);
+}
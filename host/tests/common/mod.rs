@@ -0,0 +1,110 @@
+// every test binary uses a part of it
+#![allow(dead_code)]
+
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::process;
+use storage_host::storage::date_time::{DateTime, FixedTimeSource};
+use storage_host::storage::directory_entry::short_name_checksum;
+use storage_host::storage::file_block_device::FileBlockDevice;
+
+pub const MIB: u64 = 1024 * 1024;
+
+/// a sparse image file in the temp directory, it is removed on drop
+pub struct Image {
+    path: PathBuf,
+}
+
+impl Image {
+    /// name has to be unique within the test binary
+    pub fn new(name: &str, size: u64) -> Image {
+        let path = env::temp_dir().join(format!("sd-card-party-{}-{}.img", process::id(), name));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        file.set_len(size).unwrap();
+        Image { path: path }
+    }
+
+    /// a copy of tests/images/<name>.sparse, see tests/images/generate.py
+    pub fn golden(name: &str) -> Image {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/images")
+            .join(format!("{}.sparse", name));
+        let mut sparse = Vec::new();
+        File::open(path).unwrap().read_to_end(&mut sparse).unwrap();
+        let size = u64::from_le_bytes(eight_bytes(&sparse[..8]));
+        let image = Image::new(&format!("golden-{}", name), size);
+        let mut file = OpenOptions::new().write(true).open(&image.path).unwrap();
+        // a block number and the 512 bytes of the block
+        for record in sparse[8..].chunks(8 + 512) {
+            let block = u64::from_le_bytes(eight_bytes(&record[..8]));
+            file.seek(SeekFrom::Start(block * 512)).unwrap();
+            file.write_all(&record[8..]).unwrap();
+        }
+        image
+    }
+
+    /// every call opens the image anew, like a reset of the board
+    pub fn open(&self) -> FileBlockDevice {
+        FileBlockDevice::open(&self.path).unwrap()
+    }
+}
+
+impl Drop for Image {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn eight_bytes(bytes: &[u8]) -> [u8; 8] {
+    let mut array = [0; 8];
+    array.copy_from_slice(bytes);
+    array
+}
+
+/// the time of the formats in the tests
+pub fn clock() -> FixedTimeSource {
+    FixedTimeSource::new(DateTime::new(2026, 10, 16, 12, 0, 0).unwrap())
+}
+
+/// data that differs from block to block
+pub fn pattern(length: usize, seed: u32) -> Vec<u8> {
+    (0..length as u32).map(|i| (i.wrapping_mul(seed) >> 3) as u8 ^ (i >> 9) as u8).collect()
+}
+
+/// VFAT entries for long_name in front of short_entry, the way desktop systems write them
+pub fn long_name_entries(long_name: &str, short_entry: &[u8]) -> Vec<[u8; 32]> {
+    const OFFSETS: [usize; 13] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+    let checksum = short_name_checksum(&short_entry[..11]);
+    let mut characters: Vec<u16> = long_name.encode_utf16().collect();
+    if characters.len() % 13 != 0 {
+        characters.push(0x0000);
+    }
+    while characters.len() % 13 != 0 {
+        characters.push(0xFFFF);
+    }
+    let number = characters.len() / 13;
+    // the last part comes first
+    (0..number)
+        .rev()
+        .map(|n| {
+            let mut entry = [0; 32];
+            entry[0] = (n + 1) as u8 | if n + 1 == number { 0x40 } else { 0 };
+            entry[11] = 0x0F;
+            entry[13] = checksum;
+            for (i, offset) in OFFSETS.iter().enumerate() {
+                let character = characters[n * 13 + i];
+                entry[*offset] = character as u8;
+                entry[*offset + 1] = (character >> 8) as u8;
+            }
+            entry
+        })
+        .collect()
+}
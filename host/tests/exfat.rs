@@ -0,0 +1,170 @@
+extern crate storage_host;
+
+mod common;
+
+use common::*;
+use std::cell::Cell;
+use storage_host::block_device::BlockDevice;
+use storage_host::storage::error::StorageError;
+use storage_host::storage::exfat_device_driver::ExFatDeviceDriver;
+use storage_host::storage::get_bytes::*;
+use storage_host::storage::ram_disk::RamDisk;
+
+// one block per cluster, the FAT in block 24, the cluster heap from block 32 on
+const FAT_BLOCK: usize = 24;
+const HEAP_BLOCK: usize = 32;
+
+/// counts the blocks read from disk
+struct CountingReads<'a> {
+    disk: &'a RamDisk<'a>,
+    blocks_read: Cell<usize>,
+}
+
+impl<'a> BlockDevice for CountingReads<'a> {
+    type Error = StorageError;
+
+    fn read(&self, lba: usize, buffer: &mut [u8]) -> Result<(), StorageError> {
+        self.blocks_read.set(self.blocks_read.get() + buffer.len() / self.block_size());
+        self.disk.read(lba, buffer)
+    }
+
+    fn write(&self, lba: usize, buffer: &[u8]) -> Result<(), StorageError> {
+        self.disk.write(lba, buffer)
+    }
+
+    fn flush(&self) -> Result<(), StorageError> {
+        Ok(())
+    }
+
+    fn number_of_blocks(&self) -> usize {
+        self.disk.number_of_blocks()
+    }
+
+    fn block_size(&self) -> usize {
+        self.disk.block_size()
+    }
+}
+
+fn rotate_add(checksum: u16, value: u16) -> u16 {
+    (checksum >> 1 | checksum << 15).wrapping_add(value)
+}
+
+/// file entry, stream extension and one file name entry of a contiguous file in cluster 3
+fn file_entry_set(name: &str, valid_data_length: u64, data_length: u64) -> [u8; 96] {
+    let up_case = name.to_ascii_uppercase();
+    let name: Vec<u16> = name.encode_utf16().collect();
+    let mut set = [0; 96];
+    set[0] = 0x85;
+    set[1] = 2;
+    set[4] = 0x20;
+
+    set[32] = 0xC0;
+    // allocation possible, no FAT chain
+    set[33] = 0x03;
+    set[35] = name.len() as u8;
+    let hash = up_case.encode_utf16().fold(0, |hash, character| {
+        rotate_add(rotate_add(hash, character & 0xFF), character >> 8)
+    });
+    set[36..38].copy_from_slice(&hash.to_le_bytes());
+    set[40..48].copy_from_slice(&valid_data_length.to_le_bytes());
+    set_four_bytes_at_offset(&mut set, 52, 3);
+    set[56..64].copy_from_slice(&data_length.to_le_bytes());
+
+    set[64] = 0xC1;
+    for (i, character) in name.iter().enumerate() {
+        set[66 + i * 2..68 + i * 2].copy_from_slice(&character.to_le_bytes());
+    }
+
+    let checksum = set.iter()
+        .enumerate()
+        .filter(|&(i, _)| i != 2 && i != 3)
+        .fold(0, |checksum, (_, &byte)| rotate_add(checksum, byte as u16));
+    set[2..4].copy_from_slice(&checksum.to_le_bytes());
+    set
+}
+
+fn write_volume(disk: &RamDisk, root_directory: &[u8]) {
+    let mut boot_sector = [0; 512];
+    boot_sector[3..11].copy_from_slice(b"EXFAT   ");
+    set_four_bytes_at_offset(&mut boot_sector, 0x50, FAT_BLOCK as u32);
+    set_four_bytes_at_offset(&mut boot_sector, 0x58, HEAP_BLOCK as u32);
+    set_four_bytes_at_offset(&mut boot_sector, 0x5C, 64);
+    set_four_bytes_at_offset(&mut boot_sector, 0x60, 2);
+    boot_sector[0x6C] = 9;
+    boot_sector[0x6D] = 0;
+    disk.write(0, &boot_sector).unwrap();
+
+    // the root directory is a chain of one cluster, the file has no FAT chain
+    let mut fat = [0; 512];
+    set_four_bytes_at_offset(&mut fat, 0, 0xFFFFFFF8);
+    set_four_bytes_at_offset(&mut fat, 4, 0xFFFFFFFF);
+    set_four_bytes_at_offset(&mut fat, 8, 0xFFFFFFFF);
+    disk.write(FAT_BLOCK, &fat).unwrap();
+
+    let mut root = [0; 512];
+    root[..root_directory.len()].copy_from_slice(root_directory);
+    disk.write(HEAP_BLOCK, &root).unwrap();
+}
+
+#[test]
+fn file_size_is_the_data_length() {
+    let mut memory = vec![0; MIB as usize];
+    let disk = RamDisk::from_slice(&mut memory);
+    write_volume(&disk, &file_entry_set("log.bin", 600, 1024));
+    disk.write(HEAP_BLOCK + 1, &pattern(1024, 3)).unwrap();
+
+    let driver = ExFatDeviceDriver::new(&disk).unwrap();
+    let entries: Vec<_> = driver.read_dir("/").unwrap().collect();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].file_size(), 1024);
+    assert_eq!(driver.lookup("/LOG.BIN").unwrap().file_size(), 1024);
+
+    // bytes behind the valid data length read as zero
+    let data = driver.read_file_to_vec("/log.bin").unwrap();
+    assert_eq!(data.len(), 1024);
+    assert_eq!(&data[..600], &pattern(1024, 3)[..600]);
+    assert!(data[600..].iter().all(|&byte| byte == 0));
+}
+
+#[test]
+fn allocation_bitmap_is_read_block_by_block() {
+    let mut memory = vec![0; 4 * MIB as usize];
+    let disk = RamDisk::from_slice(&mut memory);
+    // 5000 clusters need 625 bytes of bitmap, in the clusters 10 and 11
+    let mut bitmap_entry = [0; 32];
+    bitmap_entry[0] = 0x81;
+    set_four_bytes_at_offset(&mut bitmap_entry, 20, 10);
+    bitmap_entry[24..32].copy_from_slice(&625u64.to_le_bytes());
+    write_volume(&disk, &bitmap_entry);
+    let mut boot_sector = [0; 512];
+    disk.read(0, &mut boot_sector).unwrap();
+    set_four_bytes_at_offset(&mut boot_sector, 0x5C, 5000);
+    disk.write(0, &boot_sector).unwrap();
+    let mut fat = [0; 512];
+    disk.read(FAT_BLOCK, &mut fat).unwrap();
+    set_four_bytes_at_offset(&mut fat, 10 * 4, 11);
+    set_four_bytes_at_offset(&mut fat, 11 * 4, 0xFFFFFFFF);
+    disk.write(FAT_BLOCK, &fat).unwrap();
+    // the root directory, the bitmap and the cluster 4102 behind the first bitmap cluster
+    let mut bitmap = [0; 1024];
+    bitmap[0] = 0b0000_0001;
+    bitmap[1] = 0b0000_0011;
+    bitmap[4100 / 8] = 1 << (4100 % 8);
+    disk.write(HEAP_BLOCK + 8, &bitmap).unwrap();
+
+    let counting = CountingReads {
+        disk: &disk,
+        blocks_read: Cell::new(0),
+    };
+    let driver = ExFatDeviceDriver::new(&counting).unwrap();
+    counting.blocks_read.set(0);
+    let allocated: Vec<usize> = (0..5010)
+        .filter(|cluster| driver.is_cluster_allocated(*cluster).unwrap())
+        .collect();
+    assert_eq!(allocated[..5], [0, 1, 2, 10, 11]);
+    assert_eq!(allocated[5], 4102);
+    assert_eq!(allocated[6..], (5002..5010).collect::<Vec<usize>>()[..]);
+    // one read per bitmap block, the chain of the bitmap is not walked again
+    assert_eq!(counting.blocks_read.get(), 2);
+    assert_eq!(driver.free_clusters().unwrap(), 5000 - 4);
+}
@@ -0,0 +1,432 @@
+extern crate storage_host;
+
+mod common;
+
+use common::*;
+use storage_host::block_device::BlockDevice;
+use storage_host::storage::attributes;
+use storage_host::storage::date_time::{DateTime, FixedTimeSource};
+use storage_host::storage::directory_entry::*;
+use storage_host::storage::error::StorageError;
+use storage_host::storage::fat32_device_driver::*;
+use storage_host::storage::format::*;
+use storage_host::storage::file_block_device::FileBlockDevice;
+use storage_host::storage::fsck::{self, Problem};
+use storage_host::storage::get_bytes::four_bytes_at_offset;
+use storage_host::storage::mbr_device_driver::*;
+use storage_host::storage::mount::{self, FileSystem};
+use storage_host::storage::partition::Partition;
+
+#[test]
+fn format_write_and_read_back() {
+    let image = Image::new("format", 48 * MIB);
+    let big = pattern(20000, 7);
+    {
+        let disk = image.open();
+        format_fat32(&disk, &FormatOptions::new(&clock()).volume_label("sd party")).unwrap();
+        let driver = Fat32DeviceDriver::new(&disk).unwrap();
+        assert_eq!(driver.fat_type(), FatType::Fat32);
+        driver.create_dir("/LOGS").unwrap();
+        driver.write_file("/HELLO.TXT", b"hello world").unwrap();
+        driver.write_file("/LOGS/BIG.BIN", &big).unwrap();
+        // overwriting frees the old chain
+        driver.write_file("/HELLO.TXT", b"hello again").unwrap();
+    }
+
+    let disk = image.open();
+    let driver = Fat32DeviceDriver::new(&disk).unwrap();
+    assert_eq!(driver.read_file_to_vec("/hello.txt").unwrap(), b"hello again");
+    assert_eq!(driver.read_file_to_vec("/logs/big.bin").unwrap(), big);
+    let names: Vec<String> = driver.read_dir("/")
+        .unwrap()
+        .hide(attributes::VOLUME_ID)
+        .map(|entry| String::from(entry.unwrap().name()))
+        .collect();
+    assert_eq!(names, ["logs", "hello.txt"]);
+    assert_eq!(fsck::check(&driver, false).unwrap(), []);
+}
+
+#[test]
+fn volumes_have_enough_clusters_for_fat32() {
+    let image = Image::new("small", 32 * MIB);
+    let disk = image.open();
+    assert_eq!(format_fat32(&disk, &FormatOptions::new(&clock())),
+               Err(StorageError::VolumeTooSmall));
+
+    let image = Image::new("large", 48 * MIB);
+    let disk = image.open();
+    assert_eq!(format_fat32(&disk, &FormatOptions::new(&clock()).cluster_size(1024)),
+               Err(StorageError::InvalidClusterSize));
+    format_fat32(&disk, &FormatOptions::new(&clock()).cluster_size(512)).unwrap();
+    let driver = Fat32DeviceDriver::new(&disk).unwrap();
+    assert!(driver.number_of_clusters() >= 65525);
+}
+
+#[test]
+fn volume_id_is_derived_from_the_time() {
+    let image = Image::new("volume_id", 48 * MIB);
+    let disk = image.open();
+    let volume_id = |disk: &FileBlockDevice| {
+        let mut boot_sector = [0; 512];
+        disk.read(0, &mut boot_sector).unwrap();
+        four_bytes_at_offset(&boot_sector, 0x43)
+    };
+    // 10/16 + 0:00.00 and 12:00 + 2026
+    format_fat32(&disk, &FormatOptions::new(&clock())).unwrap();
+    assert_eq!(volume_id(&disk), 0x0A10_13EA);
+
+    let later = FixedTimeSource::new(DateTime::new(2026, 10, 16, 12, 0, 42).unwrap());
+    format_fat32(&disk, &FormatOptions::new(&later)).unwrap();
+    assert_eq!(volume_id(&disk), 0x3410_13EA);
+    format_fat32(&disk, &FormatOptions::new(&later).volume_id(0x1234_5678)).unwrap();
+    assert_eq!(volume_id(&disk), 0x1234_5678);
+}
+
+#[test]
+fn fsck_repairs_loop_lost_chain_and_fat_copy() {
+    let image = Image::new("fsck", 48 * MIB);
+    let disk = image.open();
+    format_fat32(&disk, &FormatOptions::new(&clock())).unwrap();
+    let driver = Fat32DeviceDriver::new(&disk).unwrap();
+    driver.write_file("/BIG.BIN", &pattern(20000, 3)).unwrap();
+
+    let chain = driver.cluster_chain(driver.lookup("/BIG.BIN").unwrap().first_cluster()).unwrap();
+    driver.write_in_fat(driver.number_of_clusters(), END_OF_CHAIN).unwrap();
+    driver.write_in_fat(chain[chain.len() - 1], chain[1]).unwrap();
+    let mut block = vec![0; 512];
+    driver.read_fat_block(1, 0, &mut block).unwrap();
+    block[100] ^= 1;
+    disk.write(32 + driver.number_of_blocks_per_fat(), &block).unwrap();
+
+    let findings = fsck::check(&driver, false).unwrap();
+    assert!(findings.iter().any(|f| match *f.problem() {
+        Problem::Loop { .. } => true,
+        _ => false,
+    }));
+    assert!(findings.iter().any(|f| match *f.problem() {
+        Problem::LostChain { .. } => true,
+        _ => false,
+    }));
+    assert!(findings.iter().any(|f| match *f.problem() {
+        Problem::FatCopyDiffers { .. } => true,
+        _ => false,
+    }));
+    assert!(fsck::check(&driver, true).unwrap().iter().all(|f| f.is_repaired()));
+    let driver = Fat32DeviceDriver::new(&disk).unwrap();
+    assert_eq!(fsck::check(&driver, false).unwrap(), []);
+}
+
+#[test]
+fn mbr_partition_is_mounted() {
+    let image = Image::new("mbr", 64 * MIB);
+    let disk = image.open();
+    let mut table = MbrPartitionTable::new(disk.number_of_blocks(), disk_signature(b"host"));
+    let index = table.add_partition(0x0C, Some(80000), DEFAULT_ALIGNMENT).unwrap();
+    table.write(&disk).unwrap();
+    let entry = MbrPartitionTable::read(&disk).unwrap().entry(index).unwrap();
+    assert_eq!(entry.get_start_block() % (DEFAULT_ALIGNMENT / 512), 0);
+    let partition = Partition::with_range(&disk,
+                                          entry.get_partition_type(),
+                                          entry.get_start_block(),
+                                          entry.get_block_count());
+    format_fat32(&partition, &FormatOptions::new(&clock())).unwrap();
+
+    let mut volume = mount::mount(&disk).unwrap();
+    match volume.file_system().unwrap() {
+        FileSystem::Fat(driver) => {
+            driver.write_file("/A.TXT", b"a").unwrap();
+            assert_eq!(driver.read_file_to_vec("/A.TXT").unwrap(), b"a");
+        }
+        _ => panic!("no FAT volume"),
+    }
+}
+
+#[test]
+fn directories_are_created_moved_and_removed() {
+    let image = Image::new("namespace", 48 * MIB);
+    let disk = image.open();
+    format_fat32(&disk, &FormatOptions::new(&clock())).unwrap();
+    let driver = Fat32DeviceDriver::new(&disk).unwrap();
+    let free_clusters = driver.free_space().unwrap().free_clusters();
+
+    driver.create_dir("/LOGS").unwrap();
+    assert_eq!(driver.create_dir("/logs"), Err(StorageError::AlreadyExists));
+    driver.create_dir("/LOGS/2026").unwrap();
+    driver.write_file("/LOGS/2026/RUN1.BIN", &pattern(3000, 5)).unwrap();
+    driver.write_file("/LOGS/A.TXT", b"a").unwrap();
+    assert_eq!(driver.remove_dir("/LOGS"), Err(StorageError::DirectoryNotEmpty));
+    assert_eq!(driver.rename("/LOGS", "/LOGS/2026/X"),
+               Err(StorageError::InvalidName));
+    driver.rename("/LOGS/2026/RUN1.BIN", "/LOGS/RUN1.BIN").unwrap();
+    driver.rename("/LOGS/2026", "/OLD").unwrap();
+    assert_eq!(driver.read_dir("/OLD/..").unwrap().count(), 2);
+    driver.rename("/LOGS/A.TXT", "/LOGS/B.TXT").unwrap();
+    assert_eq!(driver.read_file_to_vec("/LOGS/B.TXT").unwrap(), b"a");
+    assert_eq!(driver.lookup("/LOGS/A.TXT").err(), Some(StorageError::NotFound));
+    assert_eq!(fsck::check(&driver, false).unwrap(), []);
+
+    driver.remove_dir("/OLD").unwrap();
+    driver.remove_file("/LOGS/RUN1.BIN").unwrap();
+    driver.remove_file("/LOGS/B.TXT").unwrap();
+    driver.remove_dir("/LOGS").unwrap();
+    assert_eq!(driver.read_dir("/").unwrap().count(), 0);
+    assert_eq!(driver.free_space().unwrap().free_clusters(), free_clusters);
+    assert_eq!(fsck::check(&driver, false).unwrap(), []);
+}
+
+#[test]
+fn timestamps_and_attributes() {
+    let image = Image::new("attributes", 48 * MIB);
+    let disk = image.open();
+    format_fat32(&disk, &FormatOptions::new(&clock())).unwrap();
+    let mut driver = Fat32DeviceDriver::new(&disk).unwrap();
+    let created = DateTime::new(2026, 10, 16, 13, 37, 41).unwrap();
+    let time_source = FixedTimeSource::new(created);
+    driver.set_time_source(&time_source);
+    driver.write_file("/A.TXT", b"a").unwrap();
+    driver.write_file("/B.TXT", b"b").unwrap();
+
+    let a = driver.lookup("/A.TXT").unwrap();
+    assert_eq!(a.created(), Some(created));
+    // the modification time has a resolution of two seconds
+    assert_eq!(a.modified(), DateTime::new(2026, 10, 16, 13, 37, 40));
+    assert_eq!(a.attributes(), attributes::ARCHIVE);
+
+    let mut directory = vec![0; driver.cluster_size()];
+    driver.read_cluster_data_region(driver.root_directory_cluster(), &mut directory).unwrap();
+    let mut directory_entry = [0; 32];
+    directory_entry.copy_from_slice(&directory[..32]);
+    set_attributes(&mut directory_entry, attributes::READ_ONLY | attributes::HIDDEN);
+    driver.write_directory_entry(driver.root_directory_cluster(), 0, &directory_entry).unwrap();
+    assert_eq!(driver.write_file("/A.TXT", b"x"), Err(StorageError::ReadOnly));
+    assert_eq!(driver.remove_file("/A.TXT"), Err(StorageError::ReadOnly));
+    let visible = driver.read_dir("/").unwrap().hide(attributes::HIDDEN).count();
+    assert_eq!(visible, 1);
+}
+
+#[test]
+fn failed_overwrite_keeps_the_old_file() {
+    let image = Image::new("overwrite", 48 * MIB);
+    let disk = image.open();
+    format_fat32(&disk, &FormatOptions::new(&clock())).unwrap();
+    let driver = Fat32DeviceDriver::new(&disk).unwrap();
+    let old = pattern(1000, 9);
+    driver.write_file("/A.BIN", &old).unwrap();
+    let free_bytes = driver.free_space().unwrap().free_bytes() as usize;
+    driver.write_file("/FILL.BIN", &vec![0xAA; free_bytes - 2 * driver.cluster_size()])
+        .unwrap();
+
+    // A.BIN and the free space together would be large enough
+    assert_eq!(driver.write_file("/A.BIN", &pattern(6 * driver.cluster_size(), 5)),
+               Err(StorageError::NoSpace));
+    assert_eq!(driver.read_file_to_vec("/A.BIN").unwrap(), old);
+    assert_eq!(fsck::check(&driver, false).unwrap(), []);
+
+    let new = pattern(2 * driver.cluster_size(), 5);
+    driver.write_file("/A.BIN", &new).unwrap();
+    assert_eq!(driver.read_file_to_vec("/A.BIN").unwrap(), new);
+    assert_eq!(driver.free_space().unwrap().free_clusters(), 2);
+    assert_eq!(fsck::check(&driver, false).unwrap(), []);
+}
+
+#[test]
+fn dot_entries_are_neither_removed_nor_moved() {
+    let image = Image::new("dots", 48 * MIB);
+    let disk = image.open();
+    format_fat32(&disk, &FormatOptions::new(&clock())).unwrap();
+    let driver = Fat32DeviceDriver::new(&disk).unwrap();
+    driver.create_dir("/A").unwrap();
+    driver.create_dir("/A/B").unwrap();
+    driver.write_file("/X.TXT", b"x").unwrap();
+
+    assert_eq!(driver.remove_dir("/A/B/."), Err(StorageError::InvalidName));
+    assert_eq!(driver.remove_dir("/A/B/.."), Err(StorageError::InvalidName));
+    assert_eq!(driver.remove_file("/A/."), Err(StorageError::InvalidName));
+    assert_eq!(driver.rename("/A/B/.", "/C"), Err(StorageError::InvalidName));
+    assert_eq!(driver.rename("/A/B/..", "/C"), Err(StorageError::InvalidName));
+    assert_eq!(driver.rename("/X.TXT", "/A/.."), Err(StorageError::InvalidName));
+    assert_eq!(driver.read_dir("/A/B/..").unwrap().count(), 1);
+    assert_eq!(fsck::check(&driver, false).unwrap(), []);
+}
+
+#[test]
+fn moving_keeps_the_long_name() {
+    let image = Image::new("long_name", 48 * MIB);
+    let disk = image.open();
+    format_fat32(&disk, &FormatOptions::new(&clock())).unwrap();
+    let driver = Fat32DeviceDriver::new(&disk).unwrap();
+    let data = pattern(3000, 11);
+    driver.write_file("/SENSOR~1.CSV", &data).unwrap();
+
+    // what a desktop system writes for "Sensor Log.csv"
+    let root = driver.root_directory_cluster();
+    let mut directory = vec![0; driver.cluster_size()];
+    driver.read_cluster_data_region(root, &mut directory).unwrap();
+    let short_entry = directory[..32].to_vec();
+    let long_name = long_name_entries("Sensor Log.csv", &short_entry);
+    for (i, entry) in long_name.iter().enumerate() {
+        driver.write_directory_entry(root, i, entry).unwrap();
+    }
+    driver.write_directory_entry(root, long_name.len(), &short_entry).unwrap();
+    assert_eq!(driver.lookup("/Sensor Log.csv").unwrap().name(), "Sensor Log.csv");
+
+    driver.create_dir("/B").unwrap();
+    driver.rename("/Sensor Log.csv", "/B/Sensor Log.csv").unwrap();
+    assert_eq!(driver.lookup("/B/sensor log.csv").unwrap().name(), "Sensor Log.csv");
+    assert_eq!(driver.read_file_to_vec("/B/Sensor Log.csv").unwrap(), data);
+    assert_eq!(driver.lookup("/Sensor Log.csv").err(), Some(StorageError::NotFound));
+    assert_eq!(fsck::check(&driver, false).unwrap(), []);
+
+    // the short name is taken in the new directory, the long name gets the next one
+    driver.write_file("/SENSOR~1.CSV", b"other").unwrap();
+    driver.rename("/B/Sensor Log.csv", "/Sensor Log.csv").unwrap();
+    let moved = driver.lookup("/Sensor Log.csv").unwrap();
+    assert_eq!(moved.name(), "Sensor Log.csv");
+    assert_eq!(moved.name_extension(), "sensor~2.csv");
+    assert_eq!(driver.read_file_to_vec("/SENSOR~1.CSV").unwrap(), b"other");
+    assert_eq!(driver.read_file_to_vec("/Sensor Log.csv").unwrap(), data);
+    // a valid short name drops the long name
+    driver.rename("/Sensor Log.csv", "/B/LOG.CSV").unwrap();
+    assert_eq!(driver.lookup("/B/LOG.CSV").unwrap().long_name(), None);
+    assert_eq!(fsck::check(&driver, false).unwrap(), []);
+}
+
+/// the raw entries of the directory up to the end marker, without deleted ones
+fn raw_entries(driver: &Fat32DeviceDriver, cluster: usize) -> Vec<Vec<u8>> {
+    let mut directory = vec![0; driver.cluster_size()];
+    driver.read_cluster_data_region(cluster, &mut directory).unwrap();
+    directory.chunks(32)
+        .take_while(|entry| entry[0] != 0x00)
+        .filter(|entry| entry[0] != 0xE5)
+        .map(|entry| entry.to_vec())
+        .collect()
+}
+
+#[test]
+fn renaming_to_a_long_name() {
+    let image = Image::new("long_rename", 48 * MIB);
+    let disk = image.open();
+    format_fat32(&disk, &FormatOptions::new(&clock())).unwrap();
+    let driver = Fat32DeviceDriver::new(&disk).unwrap();
+    let root = driver.root_directory_cluster();
+    let data = pattern(1500, 5);
+    driver.write_file("/DATA.BIN", &data).unwrap();
+
+    // short to long: two long name entries of 13 characters in front of "MEASUR~1.BIN"
+    driver.rename("/DATA.BIN", "/Measurements 2026.bin").unwrap();
+    let entries = raw_entries(&driver, root);
+    assert_eq!(entries.len(), 3);
+    assert_eq!(&entries[2][..11], b"MEASUR~1BIN");
+    assert_eq!(entries, {
+        let mut expected = long_name_entries("Measurements 2026.bin", &entries[2]);
+        assert_eq!((expected[0][0], expected[1][0]), (0x42, 0x01));
+        let mut expected: Vec<Vec<u8>> = expected.drain(..).map(|e| e.to_vec()).collect();
+        expected.push(entries[2].clone());
+        expected
+    });
+    let entry = driver.lookup("/measurements 2026.BIN").unwrap();
+    assert_eq!(entry.name(), "Measurements 2026.bin");
+    assert_eq!(driver.read_file_to_vec("/MEASUR~1.BIN").unwrap(), data);
+
+    // long to long, the old short name is still taken while the new entries are written
+    driver.rename("/Measurements 2026.bin", "/Measurements, final.bin").unwrap();
+    let entries = raw_entries(&driver, root);
+    assert_eq!(entries.len(), 3);
+    assert_eq!(&entries[2][..11], b"MEASUR~2BIN");
+    assert_eq!(driver.lookup("/Measurements 2026.bin").err(), Some(StorageError::NotFound));
+    assert_eq!(driver.read_file_to_vec("/Measurements, final.bin").unwrap(), data);
+
+    // new files and directories get long names the same way
+    driver.write_file("/Measurements 2027.bin", b"next").unwrap();
+    driver.create_dir("/.config").unwrap();
+    assert_eq!(driver.lookup("/MEASUR~1.BIN").unwrap().name(), "Measurements 2027.bin");
+    assert_eq!(driver.lookup("/CONFIG~1").unwrap().name(), ".config");
+    for name in &["/a:b.txt", "/a*b.txt", "/tab\tname", "/long name. "] {
+        assert_eq!(driver.write_file(name, b""), Err(StorageError::InvalidName));
+    }
+    assert_eq!(fsck::check(&driver, false).unwrap(), []);
+}
+
+#[test]
+fn directories_grow_past_one_cluster() {
+    let image = Image::golden("fat32");
+    let disk = image.open();
+    let driver = Fat32DeviceDriver::new(&disk).unwrap();
+    // 16 entries of 32 bytes fit into a cluster of 512 bytes
+    assert_eq!(driver.cluster_size(), 512);
+    for i in 0..40 {
+        driver.write_file(&format!("/LOGS/EMPTY/F{:02}.TXT", i), &[i as u8]).unwrap();
+    }
+    let empty = driver.lookup("/LOGS/EMPTY").unwrap().first_cluster();
+    assert_eq!(driver.cluster_chain(empty).unwrap().len(), 3);
+    let names: Vec<String> = driver.read_dir("/LOGS/EMPTY")
+        .unwrap()
+        .map(|entry| String::from(entry.unwrap().name()))
+        .collect();
+    assert_eq!(names.len(), 40);
+    assert_eq!(names[39], "f39.txt");
+    assert_eq!(driver.read_file_to_vec("/LOGS/EMPTY/F39.TXT").unwrap(), [39]);
+
+    // the root directory already has two clusters, 20 of its 32 entries are in use
+    for i in 0..20 {
+        driver.create_dir(&format!("/D{:02}", i)).unwrap();
+    }
+    let root = driver.root_directory_cluster();
+    assert_eq!(driver.cluster_chain(root).unwrap().len(), 3);
+    driver.write_file("/D19/INNER.TXT", b"inner").unwrap();
+    assert_eq!(driver.read_file_to_vec("/D19/INNER.TXT").unwrap(), b"inner");
+    assert_eq!(fsck::check(&driver, false).unwrap(), []);
+
+    // removed entries are reused before the directory grows again
+    for i in 0..10 {
+        driver.remove_file(&format!("/LOGS/EMPTY/F{:02}.TXT", i)).unwrap();
+    }
+    for i in 40..50 {
+        driver.write_file(&format!("/LOGS/EMPTY/F{:02}.TXT", i), &[i as u8]).unwrap();
+    }
+    assert_eq!(driver.cluster_chain(empty).unwrap().len(), 3);
+    assert_eq!(driver.read_dir("/LOGS/EMPTY").unwrap().count(), 40);
+    assert_eq!(fsck::check(&driver, false).unwrap(), []);
+}
+
+#[test]
+fn clusters_are_bounded_by_the_fat() {
+    let image = Image::golden("fat16");
+    let disk = image.open();
+    let mut boot_sector = vec![0; 512];
+    disk.read(0, &mut boot_sector).unwrap();
+    // clusters of 1 block: 32000 of them, the FAT copies of 32 blocks only have 8192 entries
+    boot_sector[0x0D] = 1;
+    disk.write(0, &boot_sector).unwrap();
+    let (first_fat, second_fat, root_directory) = (4, 4 + 32, 4 + 2 * 32);
+    let mut directory = vec![0; 512];
+    disk.read(root_directory, &mut directory).unwrap();
+    {
+        let driver = Fat32DeviceDriver::new(&disk).unwrap();
+        assert_eq!(driver.fat_type(), FatType::Fat16);
+        assert_eq!(driver.number_of_clusters(), 8190);
+        assert_eq!(driver.write_in_fat(8192, END_OF_CHAIN),
+                   Err(StorageError::OutOfRange));
+        let free_bytes = driver.free_space().unwrap().free_bytes() as usize;
+        driver.write_file("/FULL.BIN", &vec![0xFF; free_bytes]).unwrap();
+        assert_eq!(driver.write_file("/MORE.BIN", b"more"), Err(StorageError::NoSpace));
+    }
+
+    // neither the second FAT nor the root directory were written over
+    let mut first = vec![0; 32 * 512];
+    let mut second = vec![0; 32 * 512];
+    disk.read(first_fat, &mut first).unwrap();
+    disk.read(second_fat, &mut second).unwrap();
+    assert_eq!(first, second);
+    let mut after = vec![0; 512];
+    disk.read(root_directory, &mut after).unwrap();
+    assert_eq!(after[..6 * 32], directory[..6 * 32]);
+    let driver = Fat32DeviceDriver::new(&disk).unwrap();
+    // the files of the image take 6 clusters
+    assert_eq!(driver.lookup("/FULL.BIN").unwrap().file_size(), (8190 - 6) * 512);
+
+    boot_sector[0x0E] = 0;
+    boot_sector[0x0F] = 0;
+    disk.write(0, &boot_sector).unwrap();
+    assert_eq!(Fat32DeviceDriver::new(&disk).err(), Some(StorageError::BadSignature));
+}
@@ -0,0 +1,100 @@
+extern crate storage_host;
+
+mod common;
+
+use common::*;
+use storage_host::storage::attributes;
+use storage_host::storage::error::StorageError;
+use storage_host::storage::fat32_device_driver::*;
+use storage_host::storage::fsck;
+
+#[test]
+fn fat16_write_and_read_back() {
+    let image = Image::golden("fat16");
+    let big = pattern(30000, 13);
+    {
+        let disk = image.open();
+        let driver = Fat32DeviceDriver::new(&disk).unwrap();
+        let free_clusters = driver.free_space().unwrap().free_clusters();
+        driver.create_dir("/DATA/2026").unwrap();
+        driver.write_file("/DATA/2026/BIG.BIN", &big).unwrap();
+        driver.write_file("/HELLO.TXT", b"hello again").unwrap();
+        driver.rename("/DATA/NUMBERS.BIN", "/NUMBERS.BIN").unwrap();
+        // 15 clusters of 2048 bytes and one for the directory
+        assert_eq!(driver.free_space().unwrap().free_clusters(), free_clusters - 16);
+    }
+
+    let disk = image.open();
+    let driver = Fat32DeviceDriver::new(&disk).unwrap();
+    assert_eq!(driver.read_file_to_vec("/data/2026/big.bin").unwrap(), big);
+    assert_eq!(driver.read_file_to_vec("/HELLO.TXT").unwrap(), b"hello again");
+    assert_eq!(driver.read_file_to_vec("/NUMBERS.BIN").unwrap(), pattern(5000, 7));
+    assert_eq!(driver.read_dir("/DATA/2026/..").unwrap().count(), 1);
+    assert_eq!(fsck::check(&driver, false).unwrap(), []);
+}
+
+#[test]
+fn fat16_root_directory_is_full_at_512_entries() {
+    let image = Image::golden("fat16");
+    let disk = image.open();
+    let driver = Fat32DeviceDriver::new(&disk).unwrap();
+    // the label, HELLO.TXT, DATA and Long File Name.txt with two long name entries
+    for i in 0..506 {
+        driver.write_file(&format!("/F{:03}.TXT", i), b"f").unwrap();
+    }
+    let visible = driver.read_dir("/").unwrap().hide(attributes::VOLUME_ID).count();
+    assert_eq!(visible, 3 + 506);
+
+    let free_clusters = driver.free_space().unwrap().free_clusters();
+    assert_eq!(driver.write_file("/F506.TXT", b"f"), Err(StorageError::NoSpace));
+    assert_eq!(driver.create_dir("/D"), Err(StorageError::NoSpace));
+    assert_eq!(driver.rename("/DATA/NUMBERS.BIN", "/NUMBERS.BIN"),
+               Err(StorageError::NoSpace));
+    // nothing was taken by the failed attempts
+    assert_eq!(driver.free_space().unwrap().free_clusters(), free_clusters);
+    assert_eq!(fsck::check(&driver, false).unwrap(), []);
+
+    driver.remove_file("/F000.TXT").unwrap();
+    driver.write_file("/F506.TXT", b"f").unwrap();
+    assert_eq!(driver.read_file_to_vec("/F506.TXT").unwrap(), b"f");
+    assert_eq!(driver.read_file_to_vec("/DATA/NUMBERS.BIN").unwrap(), pattern(5000, 7));
+}
+
+#[test]
+fn fat12_chains_cross_the_fat_blocks() {
+    let image = Image::golden("fat12");
+    let data = pattern(700 * 512, 17);
+    let chain = {
+        let disk = image.open();
+        let driver = Fat32DeviceDriver::new(&disk).unwrap();
+        // its entries of 341 and 685 are cleared across two FAT blocks
+        assert_eq!(driver.read_file_to_vec("/SPLIT.BIN").unwrap(), pattern(2500, 9));
+        driver.remove_file("/SPLIT.BIN").unwrap();
+        assert_eq!(driver.read_in_fat(341).unwrap(), 0);
+        assert_eq!(driver.read_in_fat(685).unwrap(), 0);
+
+        driver.write_file("/LONG.BIN", &data).unwrap();
+        let first_cluster = driver.lookup("/LONG.BIN").unwrap().first_cluster();
+        driver.cluster_chain(first_cluster).unwrap()
+    };
+    // the entries of 341 and 682 start in one FAT block and end in the next one
+    assert_eq!(chain, (3..703).collect::<Vec<usize>>());
+
+    let disk = image.open();
+    let driver = Fat32DeviceDriver::new(&disk).unwrap();
+    for cluster in &[340, 341, 342, 681, 682, 683] {
+        assert_eq!(driver.read_in_fat(*cluster).unwrap(), cluster + 1);
+    }
+    assert_eq!(driver.read_in_fat(702).unwrap(), END_OF_CHAIN);
+    assert_eq!(driver.read_file_to_vec("/LONG.BIN").unwrap(), data);
+    assert_eq!(fsck::check(&driver, false).unwrap(), []);
+
+    let free_clusters = driver.free_space().unwrap().free_clusters();
+    driver.remove_file("/LONG.BIN").unwrap();
+    assert_eq!(driver.free_space().unwrap().free_clusters(), free_clusters + 700);
+    for cluster in chain {
+        assert_eq!(driver.read_in_fat(cluster).unwrap(), 0);
+    }
+    assert_eq!(driver.read_file_to_vec("/HELLO.TXT").unwrap(), b"hello FAT12\n");
+    assert_eq!(fsck::check(&driver, false).unwrap(), []);
+}
@@ -0,0 +1,72 @@
+extern crate storage_host;
+
+mod common;
+
+use common::*;
+use storage_host::storage::error::StorageError;
+use storage_host::storage::fat32_device_driver::Fat32DeviceDriver;
+
+#[test]
+fn reads_in_chunks_across_clusters() {
+    let image = Image::golden("fat32");
+    let disk = image.open();
+    let driver = Fat32DeviceDriver::new(&disk).unwrap();
+    // 2300 bytes in the clusters 40, 41, 60, 61 and 62 of 512 bytes
+    let data = pattern(2300, 5);
+    for chunk_size in &[1, 7, 300, 511, 513, 1000, 4096] {
+        let mut file = driver.open("/LOGS/RUN01.BIN").unwrap();
+        assert_eq!(file.len(), 2300);
+        let mut read = Vec::new();
+        let mut chunk = vec![0; *chunk_size];
+        loop {
+            let number = file.read(&mut chunk).unwrap();
+            if number == 0 {
+                break;
+            }
+            read.extend_from_slice(&chunk[..number]);
+            assert_eq!(file.position(), read.len());
+        }
+        assert_eq!(read, data);
+    }
+}
+
+#[test]
+fn seeks_backwards_and_behind_the_end() {
+    let image = Image::golden("fat32");
+    let disk = image.open();
+    let driver = Fat32DeviceDriver::new(&disk).unwrap();
+    let data = pattern(2300, 5);
+    let mut file = driver.open("/LOGS/RUN01.BIN").unwrap();
+    let mut buffer = [0; 100];
+
+    assert_eq!(file.seek(2000), 2000);
+    assert_eq!(file.read(&mut buffer).unwrap(), 100);
+    assert_eq!(&buffer[..], &data[2000..2100]);
+    // back into the first cluster, the chain is walked from its start again
+    assert_eq!(file.seek(450), 450);
+    assert_eq!(file.read(&mut buffer).unwrap(), 100);
+    assert_eq!(&buffer[..], &data[450..550]);
+    assert_eq!(file.position(), 550);
+
+    // the end of the file, not behind it
+    assert_eq!(file.seek(5000), 2300);
+    assert_eq!(file.position(), 2300);
+    assert_eq!(file.read(&mut buffer).unwrap(), 0);
+    assert_eq!(file.seek(2250), 2250);
+    assert_eq!(file.read(&mut buffer).unwrap(), 50);
+    assert_eq!(&buffer[..50], &data[2250..]);
+}
+
+#[test]
+fn empty_files_and_directories() {
+    let image = Image::golden("fat32");
+    let disk = image.open();
+    let driver = Fat32DeviceDriver::new(&disk).unwrap();
+    driver.write_file("/EMPTY.TXT", b"").unwrap();
+    let mut file = driver.open("/EMPTY.TXT").unwrap();
+    assert!(file.is_empty());
+    assert_eq!(file.seek(10), 0);
+    assert_eq!(file.read(&mut [0; 10]).unwrap(), 0);
+    assert_eq!(driver.open("/LOGS").err(), Some(StorageError::IsADirectory));
+    assert_eq!(driver.open("/NONE.TXT").err(), Some(StorageError::NotFound));
+}
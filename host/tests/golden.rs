@@ -0,0 +1,184 @@
+extern crate storage_host;
+
+mod common;
+
+use common::*;
+use storage_host::block_device::BlockDevice;
+use storage_host::storage::attributes;
+use storage_host::storage::error::StorageError;
+use storage_host::storage::fat32_device_driver::*;
+use storage_host::storage::fsck;
+use storage_host::storage::gpt_device_driver::*;
+use storage_host::storage::mbr_device_driver::*;
+use storage_host::storage::mount::{self, FileSystem};
+
+fn names(driver: &Fat32DeviceDriver, path: &str) -> Vec<String> {
+    driver.read_dir(path)
+        .unwrap()
+        .hide(attributes::VOLUME_ID)
+        .map(|entry| String::from(entry.unwrap().name()))
+        .collect()
+}
+
+#[test]
+fn fat32_image() {
+    let image = Image::golden("fat32");
+    let disk = image.open();
+    let driver = Fat32DeviceDriver::new(&disk).unwrap();
+    assert_eq!(driver.fat_type(), FatType::Fat32);
+    assert_eq!(driver.read_file_to_vec("/README.TXT").unwrap(),
+               &b"hello from the golden image\n"[..]);
+    assert_eq!(driver.read_file_to_vec("/sensor log 2026-10-16.csv").unwrap(),
+               pattern(1300, 3));
+    assert_eq!(driver.lookup("/SENSOR~1.CSV").unwrap().name(), "Sensor Log 2026-10-16.csv");
+    assert!(driver.lookup("/RO.TXT").unwrap().is_read_only());
+
+    // the root directory continues in cluster 30, the deleted file is skipped
+    assert_eq!(driver.cluster_chain(driver.root_directory_cluster()).unwrap(), [2, 30]);
+    let root = names(&driver, "/");
+    assert_eq!(root.len(), 14);
+    assert_eq!(root[..4], ["readme.txt", "Sensor Log 2026-10-16.csv", "logs", "ro.txt"]);
+    assert_eq!(root[13], "file09.txt");
+    assert_eq!(names(&driver, "/LOGS"), ["run01.bin", "empty"]);
+    assert!(names(&driver, "/LOGS/EMPTY").is_empty());
+    let run = driver.lookup("/LOGS/RUN01.BIN").unwrap();
+    assert_eq!(driver.cluster_chain(run.first_cluster()).unwrap(), [40, 41, 60, 61, 62]);
+    assert_eq!(driver.read_file_to_vec("/LOGS/RUN01.BIN").unwrap(), pattern(2300, 5));
+
+    assert_eq!(driver.stored_free_cluster_count(),
+               Some(driver.free_space().unwrap().free_clusters()));
+    assert_eq!(fsck::check(&driver, false).unwrap(), []);
+    driver.write_file("/LOGS/RUN02.BIN", &pattern(3000, 11)).unwrap();
+    assert_eq!(driver.read_file_to_vec("/LOGS/RUN02.BIN").unwrap(), pattern(3000, 11));
+    assert_eq!(fsck::check(&driver, false).unwrap(), []);
+}
+
+#[test]
+fn fat16_image() {
+    let image = Image::golden("fat16");
+    let disk = image.open();
+    let driver = Fat32DeviceDriver::new(&disk).unwrap();
+    assert_eq!(driver.fat_type(), FatType::Fat16);
+    assert_eq!(driver.cluster_size(), 2048);
+    assert_eq!(names(&driver, "/"), ["hello.txt", "Long File Name.txt", "data"]);
+    assert_eq!(driver.read_file_to_vec("/HELLO.TXT").unwrap(), &b"hello FAT16\n"[..]);
+    assert_eq!(driver.read_file_to_vec("/long file name.txt").unwrap(),
+               &b"a long name\n"[..]);
+    assert_eq!(driver.read_file_to_vec("/DATA/NUMBERS.BIN").unwrap(), pattern(5000, 7));
+    assert_eq!(names(&driver, "/DATA/.."), names(&driver, "/"));
+}
+
+#[test]
+fn fat12_image() {
+    let image = Image::golden("fat12");
+    let disk = image.open();
+    let driver = Fat32DeviceDriver::new(&disk).unwrap();
+    assert_eq!(driver.fat_type(), FatType::Fat12);
+    assert_eq!(driver.read_file_to_vec("/HELLO.TXT").unwrap(), &b"hello FAT12\n"[..]);
+    // the entries of 341 and 685 start in one FAT block and end in the next
+    let split = driver.lookup("/SPLIT.BIN").unwrap();
+    assert_eq!(driver.cluster_chain(split.first_cluster()).unwrap(),
+               [3, 340, 341, 342, 685]);
+    assert_eq!(driver.read_file_to_vec("/SPLIT.BIN").unwrap(), pattern(2500, 9));
+}
+
+#[test]
+fn mbr_image_with_logical_partitions() {
+    let image = Image::golden("mbr");
+    let disk = image.open();
+    let table = MbrPartitionTable::read(&disk).unwrap();
+    assert_eq!(table.disk_signature(), 0x5DCA2026);
+    assert!(table.entry(0).unwrap().is_bootable());
+    assert_eq!(table.entry(1).unwrap().get_partition_type(), 0x0F);
+
+    // the extended partition is replaced by the three logical ones
+    let mbr = MbrDeviceDriver::new(&disk).unwrap();
+    let partitions: Vec<(u8, usize, usize)> = mbr.partitions()
+        .iter()
+        .map(|p| (p.get_partition_type(), p.get_start_block(), p.number_of_blocks()))
+        .collect();
+    assert_eq!(partitions,
+               [(0x06, 2048, 16384), (0x01, 20480, 4096), (0x83, 26624, 8192),
+                (0x0C, 36864, 2048)]);
+
+    let mut volume = mount::mount(&disk).unwrap();
+    assert_eq!(volume.partition().get_start_block(), 2048);
+    match volume.file_system().unwrap() {
+        FileSystem::Fat(driver) => {
+            assert_eq!(driver.fat_type(), FatType::Fat16);
+            assert_eq!(driver.read_file_to_vec("/HELLO.TXT").unwrap(),
+                       &b"hello partition 1\n"[..]);
+        }
+        _ => panic!("no FAT volume"),
+    }
+    let logical = Fat32DeviceDriver::new(&mbr.partitions()[1]).unwrap();
+    assert_eq!(logical.fat_type(), FatType::Fat12);
+    assert_eq!(logical.read_file_to_vec("/LOGICAL.TXT").unwrap(),
+               &b"hello partition 5\n"[..]);
+}
+
+#[test]
+fn mount_returns_the_error_of_the_driver() {
+    let image = Image::golden("mbr");
+    let disk = image.open();
+    // the first partition is refused by the driver, the logical FAT12 one is mounted
+    let mut boot_sector = [0; 512];
+    disk.read(2048, &mut boot_sector).unwrap();
+    boot_sector[0x0E] = 0;
+    boot_sector[0x0F] = 0;
+    disk.write(2048, &boot_sector).unwrap();
+    assert_eq!(mount::mount(&disk).unwrap().partition().get_start_block(), 20480);
+
+    // no partition left, the error of the first one is returned
+    disk.write(20480, &[0; 512]).unwrap();
+    assert_eq!(mount::mount(&disk).err(), Some(StorageError::BadSignature));
+}
+
+#[test]
+fn drivers_of_a_volume_keep_the_free_count() {
+    let image = Image::golden("fat32");
+    let disk = image.open();
+    let mut volume = mount::mount(&disk).unwrap();
+    for i in 0..3 {
+        match volume.file_system().unwrap() {
+            FileSystem::Fat(driver) => {
+                driver.write_file(&format!("/NEW{}.BIN", i), &pattern(3000, i)).unwrap();
+            }
+            _ => panic!("no FAT volume"),
+        }
+    }
+    match volume.file_system().unwrap() {
+        FileSystem::Fat(driver) => assert_eq!(fsck::check(&driver, false).unwrap(), []),
+        _ => panic!("no FAT volume"),
+    }
+}
+
+#[test]
+fn gpt_image() {
+    let image = Image::golden("gpt");
+    let disk = image.open();
+    assert!(is_protective_mbr(&disk).unwrap());
+    let gpt = GptDeviceDriver::new(&disk).unwrap();
+    assert!(!gpt.uses_backup_header());
+    let entries = gpt.partition_entries();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(*entries[0].partition_type_guid(), BASIC_DATA_PARTITION);
+    assert_eq!(entries[0].name(), "golden data");
+    assert_eq!((entries[0].first_block(), entries[0].last_block()), (2048, 10239));
+    assert_eq!(entries[1].name(), "linux");
+    assert_eq!((entries[1].first_block(), entries[1].last_block()), (10240, 16349));
+
+    // the backup header at the end of the disk describes the same table
+    disk.write(1, &[0; 512]).unwrap();
+    let backup = GptDeviceDriver::new(&disk).unwrap();
+    assert!(backup.uses_backup_header());
+    assert_eq!(backup.partition_entries().len(), 2);
+
+    let mut volume = mount::mount(&disk).unwrap();
+    match volume.file_system().unwrap() {
+        FileSystem::Fat(driver) => {
+            assert_eq!(driver.read_file_to_vec("/HELLO.TXT").unwrap(), &b"hello GPT\n"[..]);
+        }
+        _ => panic!("no FAT volume"),
+    }
+}
@@ -0,0 +1,128 @@
+extern crate storage_host;
+
+mod common;
+
+use common::*;
+use storage_host::block_device::BlockDevice;
+use storage_host::storage::crc32::crc32;
+use storage_host::storage::error::StorageError;
+use storage_host::storage::get_bytes::*;
+use storage_host::storage::gpt_device_driver::*;
+use storage_host::storage::mount;
+use storage_host::storage::ram_disk::RamDisk;
+
+fn set_eight_bytes(block: &mut [u8], offset: usize, value: u64) {
+    block[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+fn entry(first_block: u64, last_block: u64) -> [u8; 128] {
+    let mut entry = [0; 128];
+    entry[..16].copy_from_slice(&BASIC_DATA_PARTITION);
+    set_eight_bytes(&mut entry, 0x20, first_block);
+    set_eight_bytes(&mut entry, 0x28, last_block);
+    entry
+}
+
+/// a primary header in block 1 with the entry array behind it in block 2, no backup header
+fn write_table(disk: &RamDisk, number: u32, size: u32, entries_block: u64, entries: &[u8]) {
+    let mut blocks = vec![0; 512 + (entries.len() + 511) / 512 * 512];
+    blocks[..entries.len()].copy_from_slice(entries);
+    // the checksum covers number * size bytes, the array is read only if that is possible
+    let array_length = entries.len().min(number as usize * size as usize);
+    let array_crc32 = crc32(&entries[..array_length]);
+
+    let mut header = [0; 512];
+    header[..8].copy_from_slice(b"EFI PART");
+    set_four_bytes_at_offset(&mut header, 0x08, 0x0001_0000);
+    set_four_bytes_at_offset(&mut header, 0x0C, 92);
+    set_eight_bytes(&mut header, 0x18, 1);
+    set_eight_bytes(&mut header, 0x48, entries_block);
+    set_four_bytes_at_offset(&mut header, 0x50, number);
+    set_four_bytes_at_offset(&mut header, 0x54, size);
+    set_four_bytes_at_offset(&mut header, 0x58, array_crc32);
+    let header_crc32 = crc32(&header[..92]);
+    set_four_bytes_at_offset(&mut header, 0x10, header_crc32);
+
+    disk.write(1, &header).unwrap();
+    disk.write(2, &blocks[..blocks.len() - 512]).unwrap();
+}
+
+#[test]
+fn entries_outside_the_device_are_skipped() {
+    let mut memory = vec![0; 8 * MIB as usize];
+    let disk = RamDisk::from_slice(&mut memory);
+    let last_block = disk.number_of_blocks() as u64 - 1;
+    let mut entries = Vec::new();
+    entries.extend_from_slice(&entry(2048, 4095));
+    // empty, behind the device, wrapping around
+    entries.extend_from_slice(&entry(4096, 4095));
+    entries.extend_from_slice(&entry(4096, last_block + 1));
+    entries.extend_from_slice(&entry(u64::max_value() - 1, u64::max_value()));
+    entries.extend_from_slice(&entry(4096, last_block));
+    write_table(&disk, 5, 128, 2, &entries);
+
+    let gpt = GptDeviceDriver::new(&disk).unwrap();
+    let entries = gpt.partition_entries();
+    assert_eq!(entries.len(), 2);
+    assert_eq!((entries[0].first_block(), entries[0].last_block()), (2048, 4095));
+    assert_eq!((entries[1].first_block(), entries[1].last_block()), (4096, last_block as usize));
+    assert_eq!(gpt.partition(1).unwrap().number_of_blocks(),
+               disk.number_of_blocks() - 4096);
+}
+
+#[test]
+fn headers_with_an_impossible_entry_array_are_rejected() {
+    let mut memory = vec![0; 8 * MIB as usize];
+    let disk = RamDisk::from_slice(&mut memory);
+    let blocks = disk.number_of_blocks() as u64;
+    let mut entries = Vec::new();
+    for _ in 0..8 {
+        entries.extend_from_slice(&entry(2048, 4095));
+    }
+
+    // entry sizes that are not 128 * 2^n
+    for &size in &[0, 96, 192, 200, 384] {
+        write_table(&disk, 4, size, 2, &entries);
+        assert_eq!(GptDeviceDriver::new(&disk).err(), Some(StorageError::BadSignature));
+    }
+    // arrays larger than the device, behind the device, or overflowing
+    write_table(&disk, 0xFFFF_FFFF, 128, 2, &entries);
+    assert_eq!(GptDeviceDriver::new(&disk).err(), Some(StorageError::BadSignature));
+    write_table(&disk, 4, 0x8000_0000, 2, &entries);
+    assert_eq!(GptDeviceDriver::new(&disk).err(), Some(StorageError::BadSignature));
+    write_table(&disk, 4, 128, blocks, &entries);
+    assert_eq!(GptDeviceDriver::new(&disk).err(), Some(StorageError::BadSignature));
+    write_table(&disk, 4, 128, u64::max_value(), &entries);
+    assert_eq!(GptDeviceDriver::new(&disk).err(), Some(StorageError::BadSignature));
+    // more than 16 KiB with the right checksum, even though the device is large enough
+    let mut large = entries.clone();
+    large.resize(129 * 128, 0);
+    write_table(&disk, 129, 128, 2, &large);
+    assert_eq!(GptDeviceDriver::new(&disk).err(), Some(StorageError::BadSignature));
+    large.resize(128 * 256, 0);
+    write_table(&disk, 128, 256, 2, &large);
+    assert_eq!(GptDeviceDriver::new(&disk).err(), Some(StorageError::BadSignature));
+
+    // valid tables with larger entries and with the largest array
+    write_table(&disk, 4, 256, 2, &entries);
+    assert_eq!(GptDeviceDriver::new(&disk).unwrap().partition_entries().len(), 4);
+    entries.resize(128 * 128, 0);
+    write_table(&disk, 128, 128, 2, &entries);
+    assert_eq!(GptDeviceDriver::new(&disk).unwrap().partition_entries().len(), 8);
+}
+
+#[test]
+fn protective_mbr_needs_the_signature() {
+    let image = Image::golden("gpt");
+    let disk = image.open();
+    assert!(is_protective_mbr(&disk).unwrap());
+
+    // the same entry without 0xAA55, e.g. in the boot code of a superfloppy
+    let mut mbr = [0; 512];
+    disk.read(0, &mut mbr).unwrap();
+    mbr[0x1FE] = 0;
+    mbr[0x1FF] = 0;
+    disk.write(0, &mbr).unwrap();
+    assert!(!is_protective_mbr(&disk).unwrap());
+    assert_eq!(mount::mount(&disk).err(), Some(StorageError::BadSignature));
+}
@@ -0,0 +1,483 @@
+#!/usr/bin/env python3
+"""Writes the golden images of host/tests/images.
+
+The images are built from the on-disk specifications (Microsoft FAT, UEFI GPT),
+independent of the Rust code under test, with the layout mkfs.fat 4.2 and sfdisk
+produce for the commands noted at each image. The volume ids, dates and disk GUIDs
+are fixed, so running this again gives the same bytes.
+
+    python3 host/tests/images/generate.py
+
+An image is stored sparse: its size as an 8 byte little endian number, followed by
+every block that is not all zero as an 8 byte block number and the 512 bytes of it.
+common::Image::golden(...) turns it back into an image file.
+"""
+
+import os
+import struct
+import zlib
+
+BLOCK_SIZE = 512
+DIRECTORY = os.path.dirname(os.path.abspath(__file__))
+
+# 2026-10-16 12:00:00 in FAT encoding
+DATE = ((2026 - 1980) << 9) | (10 << 5) | 16
+TIME = 12 << 11
+
+ATTRIBUTE_READ_ONLY = 0x01
+ATTRIBUTE_VOLUME_ID = 0x08
+ATTRIBUTE_DIRECTORY = 0x10
+ATTRIBUTE_ARCHIVE = 0x20
+
+LFN_OFFSETS = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30]
+
+BOOT_MESSAGE = b"This is not a bootable disk.  Please insert a bootable floppy and\r\n" \
+               b"press any key to try again ... \r\n"
+
+
+def pattern(length, seed):
+    """the same bytes as common::pattern(length, seed)"""
+    return bytes((((i * seed) & 0xFFFFFFFF) >> 3 ^ (i >> 9)) & 0xFF for i in range(length))
+
+
+class Disk:
+    def __init__(self, number_of_blocks):
+        self.data = bytearray(number_of_blocks * BLOCK_SIZE)
+
+    def write(self, block, data):
+        start = block * BLOCK_SIZE
+        self.data[start:start + len(data)] = data
+
+    def save(self, name):
+        with open(os.path.join(DIRECTORY, name), "wb") as f:
+            f.write(struct.pack("<Q", len(self.data)))
+            for block in range(len(self.data) // BLOCK_SIZE):
+                data = self.data[block * BLOCK_SIZE:(block + 1) * BLOCK_SIZE]
+                if any(data):
+                    f.write(struct.pack("<Q", block))
+                    f.write(data)
+
+
+def short_name_checksum(name):
+    checksum = 0
+    for byte in name:
+        checksum = (((checksum & 1) << 7) + (checksum >> 1) + byte) & 0xFF
+    return checksum
+
+
+def short_entry(name, attributes, cluster, size):
+    entry = bytearray(32)
+    entry[0:11] = name
+    entry[11] = attributes
+    # creation time tenths, time, date, access date
+    struct.pack_into("<BHHH", entry, 13, 0, TIME, DATE, DATE)
+    struct.pack_into("<H", entry, 20, cluster >> 16)
+    struct.pack_into("<HH", entry, 22, TIME, DATE)
+    struct.pack_into("<H", entry, 26, cluster & 0xFFFF)
+    struct.pack_into("<I", entry, 28, size)
+    return entry
+
+
+def long_name_entries(long_name, name):
+    characters = list(struct.unpack("<%dH" % len(long_name), long_name.encode("utf-16-le")))
+    if len(characters) % 13:
+        characters.append(0)
+    while len(characters) % 13:
+        characters.append(0xFFFF)
+    number = len(characters) // 13
+    checksum = short_name_checksum(name)
+    entries = []
+    for n in reversed(range(number)):
+        entry = bytearray(32)
+        entry[0] = (n + 1) | (0x40 if n + 1 == number else 0)
+        entry[11] = 0x0F
+        entry[13] = checksum
+        for i, offset in enumerate(LFN_OFFSETS):
+            struct.pack_into("<H", entry, offset, characters[n * 13 + i])
+        entries.append(entry)
+    return entries
+
+
+def name_11(name):
+    """"README.TXT" -> b"README  TXT" """
+    base, _, extension = name.partition(".")
+    return base.ljust(8).encode() + extension.ljust(3).encode()
+
+
+class File:
+    def __init__(self, name, data, long_name=None, clusters=None, attributes=ATTRIBUTE_ARCHIVE):
+        self.name = name
+        self.data = data
+        self.long_name = long_name
+        self.clusters = clusters
+        self.attributes = attributes
+
+
+class Directory:
+    def __init__(self, name, children, long_name=None, clusters=None):
+        self.name = name
+        self.children = children
+        self.long_name = long_name
+        self.clusters = clusters
+
+
+class Deleted:
+    """a removed file, its entries start with 0xE5 and its clusters are free"""
+
+    def __init__(self, name, long_name=None):
+        self.name = name
+        self.long_name = long_name
+
+
+class Fat:
+    """mkfs.fat -F <fat_type> -s <sectors_per_cluster> -R <reserved> -r <root_entries>"""
+
+    def __init__(self, fat_type, number_of_blocks, sectors_per_cluster, reserved,
+                 root_entries, label, volume_id, hidden=0):
+        self.fat_type = fat_type
+        self.number_of_blocks = number_of_blocks
+        self.sectors_per_cluster = sectors_per_cluster
+        self.reserved = reserved
+        self.root_entries = root_entries if fat_type != 32 else 0
+        self.label = label.ljust(11).encode()
+        self.volume_id = volume_id
+        self.hidden = hidden
+        self.root_sectors = self.root_entries * 32 // BLOCK_SIZE
+
+        # the smallest FAT that covers every cluster behind it
+        self.sectors_per_fat = 1
+        while True:
+            self.number_of_clusters = (number_of_blocks - reserved - 2 * self.sectors_per_fat -
+                                       self.root_sectors) // sectors_per_cluster
+            needed = ((self.number_of_clusters + 2) * fat_type + 8 * BLOCK_SIZE - 1) // \
+                     (8 * BLOCK_SIZE)
+            if needed <= self.sectors_per_fat:
+                break
+            self.sectors_per_fat = needed
+        limits = {12: (1, 4084), 16: (4085, 65524), 32: (65525, 0x0FFFFFF5)}[fat_type]
+        assert limits[0] <= self.number_of_clusters <= limits[1], self.number_of_clusters
+
+        self.disk = Disk(number_of_blocks)
+        self.fat = [0] * (self.number_of_clusters + 2)
+        self.end_of_chain = {12: 0xFFF, 16: 0xFFFF, 32: 0x0FFFFFFF}[fat_type]
+        self.fat[0] = (0x0FFFFF00 | 0xF8) & self.end_of_chain
+        self.fat[1] = self.end_of_chain
+        self.next_free = 2
+        self.first_data_block = reserved + 2 * self.sectors_per_fat + self.root_sectors
+        self.cluster_size = sectors_per_cluster * BLOCK_SIZE
+
+    def allocate(self, number, clusters=None):
+        if clusters is None:
+            clusters = []
+            while len(clusters) < number:
+                if self.fat[self.next_free] == 0 and self.next_free not in self.reserved_clusters:
+                    clusters.append(self.next_free)
+                self.next_free += 1
+        assert len(clusters) >= number
+        for cluster in clusters:
+            assert self.fat[cluster] == 0, cluster
+        for current, following in zip(clusters, clusters[1:] + [self.end_of_chain]):
+            self.fat[current] = following
+        return clusters
+
+    def write_chain(self, clusters, data):
+        for i, cluster in enumerate(clusters):
+            block = self.first_data_block + (cluster - 2) * self.sectors_per_cluster
+            self.disk.write(block, data[i * self.cluster_size:(i + 1) * self.cluster_size])
+
+    def entries_of(self, children, parent_cluster, own_cluster):
+        entries = []
+        if own_cluster is not None:
+            # ".." of a directory in the root directory is 0, on FAT32 as well
+            entries.append(short_entry(b".          ", ATTRIBUTE_DIRECTORY, own_cluster, 0))
+            entries.append(short_entry(b"..         ", ATTRIBUTE_DIRECTORY, parent_cluster, 0))
+        for child in children:
+            name = name_11(child.name)
+            if child.long_name is not None:
+                entries.extend(long_name_entries(child.long_name, name))
+            if isinstance(child, Deleted):
+                entries.append(short_entry(name, ATTRIBUTE_ARCHIVE, 0, 0))
+                deleted = 1 if child.long_name is None else 1 + len(
+                    long_name_entries(child.long_name, name))
+                for entry in entries[-deleted:]:
+                    entry[0] = 0xE5
+            elif isinstance(child, Directory):
+                entries.append(short_entry(name, ATTRIBUTE_DIRECTORY, child.cluster, 0))
+            else:
+                entries.append(short_entry(name, child.attributes, child.cluster, len(child.data)))
+        return entries
+
+    def allocate_tree(self, children):
+        for child in children:
+            if isinstance(child, Directory):
+                number = child.clusters and len(child.clusters) or 1
+                child.chain = self.allocate(number, child.clusters)
+                child.cluster = child.chain[0]
+                self.allocate_tree(child.children)
+            elif isinstance(child, File):
+                number = (len(child.data) + self.cluster_size - 1) // self.cluster_size
+                child.chain = self.allocate(number, child.clusters) if number else []
+                child.cluster = child.chain[0] if child.chain else 0
+
+    def write_tree(self, children, own_cluster):
+        # the cluster of the root directory is 0 in ".."
+        dot_dot = 0 if own_cluster == self.root_cluster else own_cluster
+        for child in children:
+            if isinstance(child, Directory):
+                entries = self.entries_of(child.children, dot_dot, child.cluster)
+                data = b"".join(entries)
+                assert len(data) <= len(child.chain) * self.cluster_size
+                self.write_chain(child.chain, data)
+                self.write_tree(child.children, child.cluster)
+            elif isinstance(child, File):
+                self.write_chain(child.chain, child.data)
+
+    def build(self, children, root_clusters=None, reserved_clusters=()):
+        """reserved_clusters are kept out of the automatic allocation"""
+        self.reserved_clusters = set(reserved_clusters)
+        if self.fat_type == 32:
+            self.root_chain = self.allocate(len(root_clusters or [2]), root_clusters or [2])
+            self.root_cluster = self.root_chain[0]
+        else:
+            self.root_chain = None
+            self.root_cluster = 0
+        self.allocate_tree(children)
+
+        label = short_entry(self.label, ATTRIBUTE_VOLUME_ID, 0, 0)
+        struct.pack_into("<BHHH", label, 13, 0, 0, 0, 0)
+        root = [label] + self.entries_of(children, 0, None)
+        data = b"".join(root)
+        if self.fat_type == 32:
+            assert len(data) <= len(self.root_chain) * self.cluster_size
+            self.write_chain(self.root_chain, data)
+        else:
+            assert len(root) <= self.root_entries
+            self.disk.write(self.reserved + 2 * self.sectors_per_fat, data)
+        self.write_tree(children, self.root_cluster)
+
+        self.write_boot_sector()
+        self.write_fats()
+        return self.disk
+
+    def write_boot_sector(self):
+        boot = bytearray(BLOCK_SIZE)
+        boot[0:3] = b"\xEB\x58\x90" if self.fat_type == 32 else b"\xEB\x3C\x90"
+        boot[3:11] = b"mkfs.fat"
+        total_16 = self.number_of_blocks if self.number_of_blocks < 0x10000 else 0
+        total_32 = 0 if total_16 else self.number_of_blocks
+        struct.pack_into("<HBHBHHBHHHII", boot, 0x0B, BLOCK_SIZE, self.sectors_per_cluster,
+                         self.reserved, 2, self.root_entries, total_16, 0xF8,
+                         0 if self.fat_type == 32 else self.sectors_per_fat, 32, 64,
+                         self.hidden, total_32)
+        if self.fat_type == 32:
+            struct.pack_into("<IHHIHH", boot, 0x24, self.sectors_per_fat, 0, 0,
+                             self.root_cluster, 1, 6)
+            extended = 0x40
+        else:
+            extended = 0x24
+        type_name = b"FAT%d   " % self.fat_type
+        struct.pack_into("<BBBI11s8s", boot, extended, 0x80, 0, 0x29, self.volume_id,
+                         self.label, type_name)
+        code = extended + 26
+        boot[code:code + len(BOOT_MESSAGE)] = BOOT_MESSAGE
+        boot[0x1FE:0x200] = b"\x55\xAA"
+        self.disk.write(0, boot)
+
+        if self.fat_type == 32:
+            free = self.fat[2:].count(0)
+            next_free = max(i for i, value in enumerate(self.fat) if value != 0) + 1
+            info = bytearray(BLOCK_SIZE)
+            struct.pack_into("<I", info, 0, 0x41615252)
+            struct.pack_into("<III", info, 0x1E4, 0x61417272, free, next_free)
+            struct.pack_into("<I", info, 0x1FC, 0xAA550000)
+            self.disk.write(1, info)
+            self.disk.write(6, boot)
+            self.disk.write(7, info)
+
+    def write_fats(self):
+        if self.fat_type == 12:
+            fat = bytearray((len(self.fat) * 3 + 1) // 2)
+            for n, value in enumerate(self.fat):
+                offset = n + n // 2
+                if n % 2 == 0:
+                    fat[offset] = value & 0xFF
+                    fat[offset + 1] = (fat[offset + 1] & 0xF0) | (value >> 8)
+                else:
+                    fat[offset] = (fat[offset] & 0x0F) | ((value << 4) & 0xF0)
+                    fat[offset + 1] = value >> 4
+        elif self.fat_type == 16:
+            fat = struct.pack("<%dH" % len(self.fat), *self.fat)
+        else:
+            fat = struct.pack("<%dI" % len(self.fat), *self.fat)
+        assert len(fat) <= self.sectors_per_fat * BLOCK_SIZE
+        for copy in range(2):
+            self.disk.write(self.reserved + copy * self.sectors_per_fat, fat)
+
+
+def fat32():
+    """mkfs.vfat -F 32 -s 1 -R 32 -n GOLDEN32 -i 32323232 fat32.img 40960 (40 MiB)
+
+    the root directory needs two clusters that aren't next to each other,
+    LOGS/RUN01.BIN is fragmented
+    """
+    files = [File("FILE%02d.TXT" % i, b"file %d\n" % i) for i in range(10)]
+    children = [
+        File("README.TXT", b"hello from the golden image\n"),
+        File("SENSOR~1.CSV", pattern(1300, 3), long_name="Sensor Log 2026-10-16.csv"),
+        Deleted("OLD~1.TXT", long_name="old measurement.txt"),
+        Directory("LOGS", [
+            File("RUN01.BIN", pattern(2300, 5), clusters=[40, 41, 60, 61, 62]),
+            Directory("EMPTY", []),
+        ]),
+        File("RO.TXT", b"read only\n", attributes=ATTRIBUTE_READ_ONLY | ATTRIBUTE_ARCHIVE),
+    ] + files
+    fat = Fat(32, 81920, 1, 32, 0, "GOLDEN32", 0x32323232)
+    return fat.build(children, root_clusters=[2, 30], reserved_clusters=range(30, 70))
+
+
+def fat16(number_of_blocks=32768, sectors_per_cluster=4, reserved=4, label="GOLDEN16",
+          hidden=0, children=None):
+    """mkfs.vfat -F 16 -s 4 -R 4 -r 512 -n GOLDEN16 -i 16161616 fat16.img 16384 (16 MiB)"""
+    if children is None:
+        children = [
+            File("HELLO.TXT", b"hello FAT16\n"),
+            File("LONGFI~1.TXT", b"a long name\n", long_name="Long File Name.txt"),
+            Directory("DATA", [File("NUMBERS.BIN", pattern(5000, 7))]),
+        ]
+    fat = Fat(16, number_of_blocks, sectors_per_cluster, reserved, 512, label, 0x16161616,
+              hidden=hidden)
+    return fat.build(children)
+
+
+def fat12(number_of_blocks=4096, label="GOLDEN12", hidden=0, children=None):
+    """mkfs.vfat -F 12 -s 1 -R 1 -r 224 -n GOLDEN12 -i 12121212 fat12.img 2048 (2 MiB)
+
+    the entries of SPLIT.BIN's clusters 341 and 685 straddle two FAT blocks
+    """
+    if children is None:
+        children = [
+            File("HELLO.TXT", b"hello FAT12\n"),
+            File("SPLIT.BIN", pattern(2500, 9), clusters=[3, 340, 341, 342, 685]),
+        ]
+    fat = Fat(12, number_of_blocks, 1, 1, 224, label, 0x12121212, hidden=hidden)
+    return fat.build(children)
+
+
+def mbr_entry(bootable, partition_type, start, count):
+    # LBA only, CHS saturated like sfdisk does for disks it has no geometry for
+    return struct.pack("<B3sB3sII", 0x80 if bootable else 0, b"\xFE\xFF\xFF",
+                       partition_type, b"\xFE\xFF\xFF", start, count)
+
+
+def mbr():
+    """sfdisk mbr.img with this script, on a 64 MiB image
+
+        label: dos
+        label-id: 0x5dca2026
+        start=2048, size=16384, type=6, bootable
+        start=18432, size=20480, type=f
+        size=4096, type=1
+        size=8192, type=83
+        size=2048, type=c
+
+    partition 1 holds mkfs.vfat -F 16 -s 1 -R 1 -n PRIMARY, partition 5 mkfs.vfat -F 12
+    the EBRs are 2048 blocks in front of their logical partitions
+    """
+    disk = Disk(131072)
+    block = bytearray(BLOCK_SIZE)
+    struct.pack_into("<I", block, 0x1B8, 0x5DCA2026)
+    block[0x1BE:0x1CE] = mbr_entry(True, 0x06, 2048, 16384)
+    block[0x1CE:0x1DE] = mbr_entry(False, 0x0F, 18432, 20480)
+    block[0x1FE:0x200] = b"\x55\xAA"
+    disk.write(0, block)
+
+    extended = 18432
+    logicals = [(0x01, 4096), (0x83, 8192), (0x0C, 2048)]
+    ebr = extended
+    for i, (partition_type, count) in enumerate(logicals):
+        block = bytearray(BLOCK_SIZE)
+        # the logical partition relative to its EBR
+        block[0x1BE:0x1CE] = mbr_entry(False, partition_type, 2048, count)
+        next_ebr = ebr + 2048 + count
+        if i + 1 < len(logicals):
+            # the next EBR relative to the extended partition, up to the end of its partition
+            block[0x1CE:0x1DE] = mbr_entry(False, 0x05, next_ebr - extended,
+                                           2048 + logicals[i + 1][1])
+        block[0x1FE:0x200] = b"\x55\xAA"
+        disk.write(ebr, block)
+        ebr = next_ebr
+
+    primary = fat16(16384, 1, 1, "PRIMARY", hidden=2048,
+                    children=[File("HELLO.TXT", b"hello partition 1\n")])
+    disk.write(2048, primary.data)
+    logical = fat12(4096, "LOGICAL", hidden=extended + 2048,
+                    children=[File("LOGICAL.TXT", b"hello partition 5\n")])
+    disk.write(extended + 2048, logical.data)
+    return disk
+
+
+def guid(text):
+    """the mixed endian encoding of GPT"""
+    parts = text.split("-")
+    return struct.pack("<IHH", int(parts[0], 16), int(parts[1], 16), int(parts[2], 16)) + \
+        bytes.fromhex(parts[3] + parts[4])
+
+
+def gpt():
+    """sfdisk gpt.img with this script, on an 8 MiB image
+
+        label: gpt
+        label-id: 5DCA2026-0000-4000-8000-000000000001
+        first-lba: 34
+        start=2048, size=8192, type=EBD0A0A2-B9E5-4433-87C0-68B6B72699C7, name="golden data",
+            uuid=5DCA2026-0000-4000-8000-000000000002
+        start=10240, size=6110, type=0FC63DAF-8483-4772-8E79-3D69D8477DE4, name="linux",
+            uuid=5DCA2026-0000-4000-8000-000000000003
+
+    partition 1 holds mkfs.vfat -F 16 -s 1 -R 1 -n GPT
+    """
+    number_of_blocks = 16384
+    disk = Disk(number_of_blocks)
+    block = bytearray(BLOCK_SIZE)
+    block[0x1BE:0x1CE] = struct.pack("<B3sB3sII", 0, b"\x00\x02\x00", 0xEE, b"\xFE\xFF\xFF",
+                                     1, number_of_blocks - 1)
+    block[0x1FE:0x200] = b"\x55\xAA"
+    disk.write(0, block)
+
+    entries = bytearray(128 * 128)
+    partitions = [
+        ("EBD0A0A2-B9E5-4433-87C0-68B6B72699C7", "5DCA2026-0000-4000-8000-000000000002",
+         2048, 10239, "golden data"),
+        ("0FC63DAF-8483-4772-8E79-3D69D8477DE4", "5DCA2026-0000-4000-8000-000000000003",
+         10240, 16349, "linux"),
+    ]
+    for i, (type_guid, unique_guid, first, last, name) in enumerate(partitions):
+        entry = guid(type_guid) + guid(unique_guid) + struct.pack("<QQQ", first, last, 0) + \
+            name.encode("utf-16-le")
+        entries[i * 128:i * 128 + len(entry)] = entry
+    entries_crc32 = zlib.crc32(entries)
+
+    last_usable = number_of_blocks - 34
+    for my_lba, alternate, entries_lba in [(1, number_of_blocks - 1, 2),
+                                           (number_of_blocks - 1, 1, number_of_blocks - 33)]:
+        header = bytearray(BLOCK_SIZE)
+        struct.pack_into("<8sIIIIQQQQ16sQIII", header, 0, b"EFI PART", 0x00010000, 92, 0, 0,
+                         my_lba, alternate, 34, last_usable,
+                         guid("5DCA2026-0000-4000-8000-000000000001"), entries_lba, 128, 128,
+                         entries_crc32)
+        struct.pack_into("<I", header, 0x10, zlib.crc32(header[:92]))
+        disk.write(my_lba, header)
+        disk.write(entries_lba, entries)
+
+    partition = fat16(8192, 1, 1, "GPT", hidden=2048,
+                      children=[File("HELLO.TXT", b"hello GPT\n")])
+    disk.write(2048, partition.data)
+    return disk
+
+
+if __name__ == "__main__":
+    fat32().save("fat32.sparse")
+    fat16().save("fat16.sparse")
+    fat12().save("fat12.sparse")
+    mbr().save("mbr.sparse")
+    gpt().save("gpt.sparse")
@@ -0,0 +1,174 @@
+extern crate storage_host;
+
+mod common;
+
+use common::*;
+use storage_host::block_device::BlockDevice;
+use storage_host::storage::error::StorageError;
+use storage_host::storage::get_bytes::*;
+use storage_host::storage::mbr_device_driver::*;
+use storage_host::storage::ram_disk::RamDisk;
+
+const EXTENDED_START: usize = 2048;
+
+fn set_entry(block: &mut [u8], index: usize, partition_type: u8, start: usize, count: usize) {
+    let offset = 0x1BE + index * 16;
+    block[offset + 4] = partition_type;
+    set_four_bytes_at_offset(block, offset + 8, start as u32);
+    set_four_bytes_at_offset(block, offset + 12, count as u32);
+}
+
+/// an MBR with an extended partition from block 2048 to the end of the disk
+fn write_mbr(disk: &RamDisk) {
+    let mut mbr = [0; 512];
+    set_entry(&mut mbr, 0, 0x0C, 64, 1984);
+    set_entry(&mut mbr, 1, 0x0F, EXTENDED_START, disk.number_of_blocks() - EXTENDED_START);
+    mbr[0x1FE] = 0x55;
+    mbr[0x1FF] = 0xAA;
+    disk.write(0, &mbr).unwrap();
+}
+
+/// the logical partition of 8 blocks right behind the EBR, next relative to the
+/// extended partition
+fn write_ebr(disk: &RamDisk, block: usize, next: Option<usize>) {
+    let mut ebr = [0; 512];
+    set_entry(&mut ebr, 0, 0x83, 1, 8);
+    if let Some(next) = next {
+        set_entry(&mut ebr, 1, 0x05, next, 9);
+    }
+    ebr[0x1FE] = 0x55;
+    ebr[0x1FF] = 0xAA;
+    disk.write(block, &ebr).unwrap();
+}
+
+fn logical_starts(disk: &RamDisk) -> Vec<usize> {
+    let mbr = MbrDeviceDriver::new(disk).unwrap();
+    assert_eq!(mbr.partitions()[0].get_start_block(), 64);
+    mbr.partitions()[1..].iter().map(|p| p.get_start_block()).collect()
+}
+
+#[test]
+fn logical_partitions_follow_the_ebr_chain() {
+    let mut memory = vec![0; 4 * MIB as usize];
+    let disk = RamDisk::from_slice(&mut memory);
+    write_mbr(&disk);
+    // the EBRs are not in the order of the chain
+    write_ebr(&disk, EXTENDED_START, Some(100));
+    write_ebr(&disk, EXTENDED_START + 100, Some(20));
+    write_ebr(&disk, EXTENDED_START + 20, None);
+    assert_eq!(logical_starts(&disk),
+               [EXTENDED_START + 1, EXTENDED_START + 101, EXTENDED_START + 21]);
+
+    // a damaged EBR ends the chain, the logical partitions in front of it are kept
+    disk.write(EXTENDED_START + 20, &[0; 512]).unwrap();
+    assert_eq!(logical_starts(&disk), [EXTENDED_START + 1, EXTENDED_START + 101]);
+    // as does one behind the end of the disk
+    write_ebr(&disk, EXTENDED_START + 100, Some(1 << 30));
+    assert_eq!(logical_starts(&disk), [EXTENDED_START + 1, EXTENDED_START + 101]);
+}
+
+#[test]
+fn ebr_chains_end_after_128_logical_partitions() {
+    let mut memory = vec![0; 4 * MIB as usize];
+    let disk = RamDisk::from_slice(&mut memory);
+    write_mbr(&disk);
+    // 200 logical partitions, 10 blocks apart
+    for i in 0..200 {
+        let next = if i + 1 < 200 { Some((i + 1) * 10) } else { None };
+        write_ebr(&disk, EXTENDED_START + i * 10, next);
+    }
+    let starts = logical_starts(&disk);
+    assert_eq!(starts.len(), 128);
+    assert_eq!(starts[127], EXTENDED_START + 1270 + 1);
+
+    // a chain pointing back to its start doesn't loop forever
+    write_ebr(&disk, EXTENDED_START + 10, Some(0));
+    assert_eq!(logical_starts(&disk).len(), 128);
+    write_ebr(&disk, EXTENDED_START, Some(0));
+    let starts = logical_starts(&disk);
+    assert_eq!(starts.len(), 128);
+    assert!(starts.iter().all(|start| *start == EXTENDED_START + 1));
+}
+
+#[test]
+fn new_partitions_are_aligned_behind_the_last_one() {
+    // 8192 blocks
+    let mut table = MbrPartitionTable::new(4 * MIB as usize / 512, 1);
+    assert_eq!(table.add_partition(0x0C, Some(1000), 2048), Ok(0));
+    assert_eq!(table.add_partition(0x83, Some(100), 2048), Ok(1));
+    // no alignment is an alignment of one block
+    assert_eq!(table.add_partition(0x83, Some(4), 0), Ok(2));
+    let starts: Vec<(usize, usize)> = (0..3)
+        .map(|i| table.entry(i).unwrap())
+        .map(|entry| (entry.get_start_block(), entry.get_block_count()))
+        .collect();
+    assert_eq!(starts, [(2048, 1000), (4096, 100), (4196, 4)]);
+
+    // more blocks than there are behind the last partition
+    assert_eq!(table.add_partition(0x83, Some(4000), 8), Err(StorageError::NoSpace));
+    assert_eq!(table.add_partition(0x83, None, 8192), Err(StorageError::NoSpace));
+    // the rest of the disk
+    assert_eq!(table.add_partition(0x07, None, 8), Ok(3));
+    let last = table.entry(3).unwrap();
+    assert_eq!((last.get_start_block(), last.get_block_count()), (4200, 8192 - 4200));
+    assert_eq!(table.add_partition(0x83, Some(1), 1), Err(StorageError::NoSpace));
+
+    // a free entry in front doesn't make the gap in front usable
+    table.set_entry(0, None).unwrap();
+    assert_eq!(table.add_partition(0x83, Some(1), 1), Err(StorageError::NoSpace));
+    table.set_entry(3, None).unwrap();
+    assert_eq!(table.add_partition(0x83, Some(1), 1), Ok(0));
+    assert_eq!(table.entry(0).unwrap().get_start_block(), 4200);
+}
+
+#[test]
+fn partitions_must_not_overlap() {
+    let mut table = MbrPartitionTable::new(8192, 1);
+    table.set_entry(0, Some(MbrPartitionEntry::new(0x0C, 2048, 2048))).unwrap();
+    for &(start, count) in &[(2048, 1), (4095, 10), (1000, 1049), (1000, 5000)] {
+        assert_eq!(table.set_entry(1, Some(MbrPartitionEntry::new(0x83, start, count))),
+                   Err(StorageError::OutOfRange));
+    }
+    // partitions may touch each other
+    table.set_entry(1, Some(MbrPartitionEntry::new(0x83, 1000, 1048))).unwrap();
+    table.set_entry(2, Some(MbrPartitionEntry::new(0x83, 4096, 100))).unwrap();
+    // an entry doesn't overlap with the partition it replaces
+    table.set_entry(0, Some(MbrPartitionEntry::new(0x0C, 2048, 2000))).unwrap();
+
+    // empty, in block 0, behind the disk, no type or no such entry
+    for &(index, partition_type, start, count) in
+        &[(3, 0x83, 5000, 0), (3, 0x83, 0, 10), (3, 0x83, 8000, 193), (3, 0, 5000, 10),
+          (4, 0x83, 5000, 10)] {
+        let entry = MbrPartitionEntry::new(partition_type, start, count);
+        assert_eq!(table.set_entry(index, Some(entry)), Err(StorageError::OutOfRange));
+    }
+    assert!(table.entry(3).is_none());
+}
+
+#[test]
+fn only_one_partition_is_bootable() {
+    let mut memory = vec![0; 4 * MIB as usize];
+    let disk = RamDisk::from_slice(&mut memory);
+    let mut table = MbrPartitionTable::new(disk.number_of_blocks(), 1);
+    table.add_partition(0x0C, Some(100), 64).unwrap();
+    table.add_partition(0x0C, Some(100), 64).unwrap();
+    let bootable = |table: &MbrPartitionTable| -> Vec<bool> {
+        (0..2).map(|i| table.entry(i).unwrap().is_bootable()).collect()
+    };
+    assert_eq!(bootable(&table), [false, false]);
+
+    table.set_bootable(1).unwrap();
+    assert_eq!(bootable(&table), [false, true]);
+    table.set_bootable(0).unwrap();
+    assert_eq!(bootable(&table), [true, false]);
+    assert_eq!(table.set_bootable(2), Err(StorageError::NotFound));
+    assert_eq!(table.set_bootable(4), Err(StorageError::NotFound));
+    assert_eq!(bootable(&table), [true, false]);
+
+    // the flag is written as 0x80
+    table.write(&disk).unwrap();
+    let mut mbr = [0; 512];
+    disk.read(0, &mut mbr).unwrap();
+    assert_eq!((mbr[0x1BE], mbr[0x1CE]), (0x80, 0));
+    assert_eq!(bootable(&MbrPartitionTable::read(&disk).unwrap()), [true, false]);
+}
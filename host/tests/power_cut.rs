@@ -0,0 +1,117 @@
+extern crate storage_host;
+
+mod common;
+
+use common::*;
+use std::cell::Cell;
+use storage_host::block_device::BlockDevice;
+use storage_host::storage::block_cache::BlockCache;
+use storage_host::storage::error::StorageError;
+use storage_host::storage::fat32_device_driver::Fat32DeviceDriver;
+use storage_host::storage::format::*;
+use storage_host::storage::fsck::{self, Problem};
+use storage_host::storage::ram_disk::RamDisk;
+
+/// passes the first block writes through, then the power is gone and every write fails
+struct PowerCut<'a> {
+    disk: &'a RamDisk<'a>,
+    remaining_writes: Cell<usize>,
+}
+
+impl<'a> BlockDevice for PowerCut<'a> {
+    type Error = StorageError;
+
+    fn read(&self, lba: usize, buffer: &mut [u8]) -> Result<(), StorageError> {
+        self.disk.read(lba, buffer)
+    }
+
+    /// block by block, the power can fail in the middle of a write
+    fn write(&self, lba: usize, buffer: &[u8]) -> Result<(), StorageError> {
+        for (i, block) in buffer.chunks(self.block_size()).enumerate() {
+            if self.remaining_writes.get() == 0 {
+                return Err(StorageError::IoError);
+            }
+            self.remaining_writes.set(self.remaining_writes.get() - 1);
+            self.disk.write(lba + i, block)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), StorageError> {
+        if self.remaining_writes.get() == 0 {
+            return Err(StorageError::IoError);
+        }
+        Ok(())
+    }
+
+    fn number_of_blocks(&self) -> usize {
+        self.disk.number_of_blocks()
+    }
+
+    fn block_size(&self) -> usize {
+        self.disk.block_size()
+    }
+}
+
+fn log(device: &PowerCut, data: &[u8]) -> Result<(), StorageError> {
+    let cache = BlockCache::new(device, 32, false);
+    let driver = Fat32DeviceDriver::new(&cache)?;
+    {
+        let mut writer = driver.append("/LOG.BIN", 1500)?;
+        for chunk in data.chunks(700) {
+            writer.write(chunk)?;
+        }
+        writer.checkpoint()?;
+    }
+    cache.flush()
+}
+
+#[test]
+fn append_behind_a_write_back_cache_survives_every_power_cut() {
+    let mut base = vec![0; 34 * MIB as usize];
+    {
+        let disk = RamDisk::from_slice(&mut base);
+        format_fat32(&disk, &FormatOptions::new(&clock())).unwrap();
+        let driver = Fat32DeviceDriver::new(&disk).unwrap();
+        // the log starts a few clusters in front of the second FAT block
+        driver.write_file("/FILL.BIN", &vec![0xAA; 120 * driver.cluster_size()]).unwrap();
+    }
+    let data = pattern(16 * 1024, 13);
+
+    for writes in 0.. {
+        let mut memory = base.clone();
+        let disk = RamDisk::from_slice(&mut memory);
+        let result = log(&PowerCut {
+                             disk: &disk,
+                             remaining_writes: Cell::new(writes),
+                         },
+                         &data);
+
+        let driver = Fat32DeviceDriver::new(&disk).unwrap();
+        match driver.lookup("/LOG.BIN") {
+            Ok(entry) => {
+                // the chain never runs into a free cluster
+                driver.cluster_chain(entry.first_cluster()).unwrap();
+                let log = driver.read_file_to_vec("/LOG.BIN").unwrap();
+                assert_eq!(&log[..], &data[..log.len()]);
+            }
+            Err(error) => assert_eq!(error, StorageError::NotFound),
+        }
+        // a FAT copy behind the first one or clusters behind the size are repaired by fsck
+        if writes % 16 == 0 || result.is_ok() {
+            for finding in fsck::check(&driver, false).unwrap() {
+                match *finding.problem() {
+                    Problem::FatCopyDiffers { .. } |
+                    Problem::WrongFileSize { .. } |
+                    Problem::LostChain { .. } |
+                    Problem::WrongFreeCount { .. } => {}
+                    ref problem => panic!("{} writes: {:?}", writes, problem),
+                }
+            }
+        }
+        if result.is_ok() {
+            assert_eq!(driver.read_file_to_vec("/LOG.BIN").unwrap(), data);
+            break;
+        }
+    }
+}
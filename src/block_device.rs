@@ -1,25 +1,26 @@
-use collections::vec::*;
-
 pub trait BlockDevice {
-    /// note: <---> means in a better version
-    /// if offset * block_size() is larger than the last address, get_data(...)
-    /// shall return an empty vector
-    /// if number == 0, get_data(...) shall return an empty vector
-    /// if (offset + number) * block_size() is larger than the last address,
-    ///     get_data(...) shall return an vector with all blocks till the end
-    ///     ---"...all blocks..." -> ...all data...
-    /// read_blocks(...).len() % block_size() == 0 must always be true
-    ///     ---should be called "read"
-    ///     ---"...must..." -> ...must not...
-    fn read_blocks(&self, offset: usize, number: usize) -> Vec<u8>;
-    /// unimplemented!()
-    /// ---should be called "write"
-    fn write_blocks(&self, offset: usize, blocks: &[u8]) -> Result<usize, ()>;
-    /// ---should be size instead
+    /// e.g. StorageError for partitions, the error code of the controller for the SD card
+    type Error;
+
+    /// reads buffer.len() / block_size() blocks, beginning with block lba, into buffer
+    /// buffer.len() % block_size() == 0 must always be true
+    /// reading behind the last block is an error, there are no short reads
+    fn read(&self, lba: usize, buffer: &mut [u8]) -> Result<(), Self::Error>;
+    /// writes the blocks in buffer, beginning with block lba
+    /// same rules as for read(...)
+    fn write(&self, lba: usize, buffer: &[u8]) -> Result<(), Self::Error>;
+    /// writes everything, that is still buffered, to the medium
+    fn flush(&self) -> Result<(), Self::Error>;
     fn number_of_blocks(&self) -> usize;
     /// block_size should be 512 byte
     fn block_size(&self) -> usize;
-    // ---better: additional method len() an last "block" contains filling values at the end
-    //  three "/" above ->compiler error??!
-    //fn len(&self) ->usize;
+
+    /// size in bytes
+    fn len(&self) -> u64 {
+        self.number_of_blocks() as u64 * self.block_size() as u64
+    }
+
+    fn is_empty(&self) -> bool {
+        self.number_of_blocks() == 0
+    }
 }
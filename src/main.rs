@@ -21,8 +21,10 @@ extern crate bitflags;
 
 use stm32f7::{system_clock, sdram, lcd, board, embedded};
 use embedded::interfaces::gpio::{self, Gpio};
+use block_device::BlockDevice;
 
 mod dma;
+mod rtc;
 mod sd;
 mod storage;
 mod block_device;
@@ -31,6 +33,7 @@ mod block_device;
 const SDRAM_START: usize = 0xC000_0000;
 const SDRAM_END: usize = 0xC080_0000;
 const SDRAM_LCD_SECTION_SIZE: usize = 0x0010_0000;
+const RAM_DISK_SIZE: usize = 0x0040_0000;
 
 #[no_mangle]
 pub unsafe extern "C" fn reset() -> ! {
@@ -151,6 +154,8 @@ fn main(hw: board::Hardware) -> ! {
     // SD stuff
     let mut sd_handle = sd::SdHandle::new(sdmmc, &dma_2);
     sd_handle.init(&mut gpio, rcc);
+    let sd_card = sd::SdBlockDevice::new(sd_handle);
+    println!("SD card: {} MiB", sd_card.len() / 1024 / 1024);
 
     // TODO(ca) add further initialization code here
 
@@ -159,6 +164,12 @@ fn main(hw: board::Hardware) -> ! {
 
     let mut dma_test_state = dma_test_setup(&dma_2, &mut sdram_addr);
 
+    // scratch volume, works without a card
+    let ram_disk = unsafe { storage::ram_disk::RamDisk::new(sdram_addr, RAM_DISK_SIZE) };
+    sdram_addr += RAM_DISK_SIZE;
+    assert!(sdram_addr <= SDRAM_END);
+    println!("RAM disk: {} KiB", ram_disk.len() / 1024);
+
     let mut last_led_toggle = system_clock::ticks();
     loop {
         let ticks = system_clock::ticks();
@@ -0,0 +1,45 @@
+use embed_stm::rtc::Rtc;
+use storage::date_time::{self, DateTime, TimeSource};
+
+// in milliseconds, RSF is set every two RTCCLK cycles, 61 us with the LSE
+const SYNCHRONIZATION_TIMEOUT: usize = 10;
+
+/// reads the calendar of the RTC, it keeps running on the backup battery
+/// setting the clock (LSE, init mode) is left to whoever provisions the board,
+/// a calendar that was never set or doesn't run reads as 1980-01-01
+pub struct RtcTimeSource {
+    registers: &'static mut Rtc,
+}
+
+impl RtcTimeSource {
+    pub fn new(rtc: &'static mut Rtc) -> RtcTimeSource {
+        RtcTimeSource { registers: rtc }
+    }
+}
+
+impl TimeSource for RtcTimeSource {
+    /// the 24 hour format is expected, the calendar holds the years 2000 to 2099
+    fn now(&self) -> DateTime {
+        if !self.registers.isr.read().inits() {
+            return date_time::EPOCH;
+        }
+        // wait for the shadow registers, they are copied from the calendar every two RTCCLK
+        // a stopped RTCCLK never sets RSF
+        let timeout = ::system_clock::ticks() + SYNCHRONIZATION_TIMEOUT;
+        while !self.registers.isr.read().rsf() {
+            if ::system_clock::ticks() >= timeout {
+                return date_time::EPOCH;
+            }
+        }
+        // reading TR locks DR until it is read as well, both belong to the same second
+        let tr = self.registers.tr.read();
+        let dr = self.registers.dr.read();
+        DateTime::new(2000 + (dr.yt() * 10 + dr.yu()) as u16,
+                      dr.mt() as u8 * 10 + dr.mu(),
+                      dr.dt() * 10 + dr.du(),
+                      tr.ht() * 10 + tr.hu(),
+                      tr.mnt() * 10 + tr.mnu(),
+                      tr.st() * 10 + tr.su())
+            .unwrap_or(date_time::EPOCH)
+    }
+}
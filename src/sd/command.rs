@@ -128,6 +128,117 @@ impl SdHandle {
         self.get_response2()
     }
 
+    /// Send CMD12 which stops a multiple block transfer
+    pub fn cmd_stop_transmission(&mut self) -> low_level::SdmmcErrorCode {
+        // Argument:
+        // - [31:0]: stuff bits
+        self.registers.arg.update(|arg| arg.set_cmdarg(0));
+
+        let cmd_index = 12;
+        self.registers.cmd.update(|cmd| {
+            // ensure reset values in unused bits
+            cmd.set_sdiosuspend(false);
+            cmd.set_waitpend(false);
+            cmd.set_waitint(false);
+            // set card to send CMD12
+            cmd.set_waitresp(WaitResp::Short as u8);
+            cmd.set_cpsmen(true);
+            cmd.set_cmdindex(cmd_index);
+        });
+
+        self.get_response1(cmd_index, 5000)
+    }
+
+    /// Send CMD13 which asks the card for its status register
+    pub fn cmd_send_status(&mut self, rca: u32) -> Result<u32, low_level::SdmmcErrorCode> {
+        // Argument:
+        // - [31:16]: RCA
+        // - [15:0]: stuff bits
+        self.registers.arg.update(|arg| arg.set_cmdarg(rca << 16));
+
+        let cmd_index = 13;
+        self.registers.cmd.update(|cmd| {
+            // ensure reset values in unused bits
+            cmd.set_sdiosuspend(false);
+            cmd.set_waitpend(false);
+            cmd.set_waitint(false);
+            // set card to send CMD13
+            cmd.set_waitresp(WaitResp::Short as u8);
+            cmd.set_cpsmen(true);
+            cmd.set_cmdindex(cmd_index);
+        });
+
+        let error = self.get_response1(cmd_index, 5000);
+        if error != low_level::NONE {
+            return Err(error);
+        }
+        Ok(self.registers.resp1.read().cardstatus1())
+    }
+
+    /// Send CMD16 which sets the block length of the following transfers in bytes,
+    /// SDHC and SDXC cards always use 512
+    pub fn cmd_block_length(&mut self, block_size: u32) -> low_level::SdmmcErrorCode {
+        // Argument:
+        // - [31:0]: block length
+        self.registers.arg.update(|arg| arg.set_cmdarg(block_size));
+
+        let cmd_index = 16;
+        self.registers.cmd.update(|cmd| {
+            // ensure reset values in unused bits
+            cmd.set_sdiosuspend(false);
+            cmd.set_waitpend(false);
+            cmd.set_waitint(false);
+            // set card to send CMD16
+            cmd.set_waitresp(WaitResp::Short as u8);
+            cmd.set_cpsmen(true);
+            cmd.set_cmdindex(cmd_index);
+        });
+
+        self.get_response1(cmd_index, 5000)
+    }
+
+    /// Send CMD17 or CMD18 which read one or multiple blocks beginning at address
+    pub fn cmd_read_blocks(&mut self, address: u32, multiple: bool) -> low_level::SdmmcErrorCode {
+        // Argument:
+        // - [31:0]: data address, in bytes for SDSC cards, in blocks for SDHC and SDXC cards
+        self.registers.arg.update(|arg| arg.set_cmdarg(address));
+
+        let cmd_index = if multiple { 18 } else { 17 };
+        self.registers.cmd.update(|cmd| {
+            // ensure reset values in unused bits
+            cmd.set_sdiosuspend(false);
+            cmd.set_waitpend(false);
+            cmd.set_waitint(false);
+            // set card to send CMD17 or CMD18
+            cmd.set_waitresp(WaitResp::Short as u8);
+            cmd.set_cpsmen(true);
+            cmd.set_cmdindex(cmd_index);
+        });
+
+        self.get_response1(cmd_index, 5000)
+    }
+
+    /// Send CMD24 or CMD25 which write one or multiple blocks beginning at address
+    pub fn cmd_write_blocks(&mut self, address: u32, multiple: bool) -> low_level::SdmmcErrorCode {
+        // Argument:
+        // - [31:0]: data address, in bytes for SDSC cards, in blocks for SDHC and SDXC cards
+        self.registers.arg.update(|arg| arg.set_cmdarg(address));
+
+        let cmd_index = if multiple { 25 } else { 24 };
+        self.registers.cmd.update(|cmd| {
+            // ensure reset values in unused bits
+            cmd.set_sdiosuspend(false);
+            cmd.set_waitpend(false);
+            cmd.set_waitint(false);
+            // set card to send CMD24 or CMD25
+            cmd.set_waitresp(WaitResp::Short as u8);
+            cmd.set_cpsmen(true);
+            cmd.set_cmdindex(cmd_index);
+        });
+
+        self.get_response1(cmd_index, 5000)
+    }
+
     /// Send CMD55 which indicates that the next command will be an application specific one (ACMD)
     pub fn cmd_app_cmd(&mut self, rca: u32) -> low_level::SdmmcErrorCode {
         // Argument:
@@ -220,7 +220,7 @@ impl SdHandle {
 
         // Get the Card Class, which is the CCC field in the CSD register
         self.sd_card.class = (self.sd_card.csd[1] >> 20) as u16;
-        // TODO: fill CSD register struct (has to be declared first)
+        self.read_capacity();
 
         // select the card by sending CMD7
         let rca = self.sd_card.relative_card_address as u32;
@@ -239,6 +239,29 @@ impl SdHandle {
         low_level::NONE
     }
 
+    /// number and size of the blocks, from the CSD
+    // represents the capacity part of HAL_SD_GetCardCSD
+    fn read_capacity(&mut self) {
+        let csd = self.sd_card.csd;
+        if csd[0] >> 30 == 0 {
+            // CSD version 1.0: (C_SIZE + 1) * 2^(C_SIZE_MULT + 2) blocks of 2^READ_BL_LEN bytes
+            let read_bl_len = (csd[1] >> 16) & 0xF;
+            let c_size = ((csd[1] & 0x3FF) << 2) | (csd[2] >> 30);
+            let c_size_mult = (csd[2] >> 15) & 0x7;
+            self.sd_card.number_of_blocks = (c_size as usize + 1) << (c_size_mult + 2);
+            self.sd_card.block_size = 1 << read_bl_len;
+            self.sd_card.logical_number_of_blocks =
+                self.sd_card.number_of_blocks * (self.sd_card.block_size / 512);
+        } else {
+            // CSD version 2.0: (C_SIZE + 1) * 512 KiB
+            let c_size = ((csd[1] & 0x3F) << 16) | (csd[2] >> 16);
+            self.sd_card.number_of_blocks = (c_size as usize + 1) * 1024;
+            self.sd_card.block_size = 512;
+            self.sd_card.logical_number_of_blocks = self.sd_card.number_of_blocks;
+        }
+        self.sd_card.logical_block_size = 512;
+    }
+
     /// De-initialize the low-level hardware (MSP layer)
     fn de_init_low_level(&self) -> Status {
         unimplemented!();
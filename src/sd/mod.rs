@@ -1,9 +1,13 @@
 pub mod init;
 mod low_level;
 mod command;
+mod transfer;
 
 use dma;
+use block_device::BlockDevice;
+use core::cell::RefCell;
 use embed_stm::sdmmc::Sdmmc;
+use storage::error::StorageError;
 
 /// SD handle
 // represents SD_HandleTypeDef
@@ -213,3 +217,51 @@ impl SdHandle {
         });
     }
 }
+
+/// the card as block device, the transfers need the handle mutably
+pub struct SdBlockDevice {
+    handle: RefCell<SdHandle>,
+}
+
+impl SdBlockDevice {
+    /// handle must be initialized, see SdHandle::init(...)
+    pub fn new(handle: SdHandle) -> SdBlockDevice {
+        SdBlockDevice { handle: RefCell::new(handle) }
+    }
+
+    pub fn into_inner(self) -> SdHandle {
+        self.handle.into_inner()
+    }
+}
+
+impl BlockDevice for SdBlockDevice {
+    type Error = low_level::SdmmcErrorCode;
+
+    fn read(&self, lba: usize, buffer: &mut [u8]) -> Result<(), low_level::SdmmcErrorCode> {
+        self.handle.borrow_mut().read_blocks(lba, buffer)
+    }
+
+    fn write(&self, lba: usize, buffer: &[u8]) -> Result<(), low_level::SdmmcErrorCode> {
+        self.handle.borrow_mut().write_blocks(lba, buffer)
+    }
+
+    /// the card has no write cache, write(...) waits until the blocks are programmed
+    fn flush(&self) -> Result<(), low_level::SdmmcErrorCode> {
+        Ok(())
+    }
+
+    fn number_of_blocks(&self) -> usize {
+        self.handle.borrow().sd_card.logical_number_of_blocks
+    }
+
+    fn block_size(&self) -> usize {
+        transfer::BLOCK_SIZE
+    }
+}
+
+/// errors of the card show up as IoError in the storage stack
+impl From<low_level::SdmmcErrorCode> for StorageError {
+    fn from(_: low_level::SdmmcErrorCode) -> StorageError {
+        StorageError::IoError
+    }
+}
@@ -0,0 +1,237 @@
+use super::*;
+use core::ptr;
+use storage::get_bytes::{four_bytes_at_offset, set_four_bytes_at_offset};
+
+pub const BLOCK_SIZE: usize = 512;
+// DBLOCKSIZE in the data control register, 2^9 = 512 bytes
+const BLOCK_SIZE_POWER: u8 = 9;
+// data timeout in card clock cycles, represents SDMMC_DATATIMEOUT
+const DATA_TIMEOUT_CYCLES: u32 = 0xFFFF_FFFF;
+// software timeout of a whole transfer in milliseconds
+const TRANSFER_TIMEOUT: usize = 5000;
+// words in the FIFO, when the half full or half empty flag is set
+const HALF_FIFO_WORDS: usize = 8;
+// READY_FOR_DATA in the card status, the card has finished programming
+const READY_FOR_DATA: u32 = 0x100;
+
+impl SdHandle {
+    /// reads buffer.len() / 512 blocks, beginning with block lba, by polling the FIFO
+    // represents HAL_SD_ReadBlocks
+    pub fn read_blocks(&mut self,
+                       lba: usize,
+                       buffer: &mut [u8])
+                       -> Result<(), low_level::SdmmcErrorCode> {
+        let number_of_blocks = self.check_transfer(lba, buffer.len())?;
+        self.state = State::Busy;
+        self.context = if number_of_blocks > 1 {
+            Context::ReadMultipleBlocks
+        } else {
+            Context::ReadSingleBlock
+        };
+        let result = self.receive(lba, number_of_blocks, buffer);
+        self.finish_transfer(result)
+    }
+
+    /// writes the blocks in buffer, beginning with block lba, by polling the FIFO
+    /// returns after the card has programmed them
+    // represents HAL_SD_WriteBlocks
+    pub fn write_blocks(&mut self,
+                        lba: usize,
+                        buffer: &[u8])
+                        -> Result<(), low_level::SdmmcErrorCode> {
+        let number_of_blocks = self.check_transfer(lba, buffer.len())?;
+        self.state = State::Busy;
+        self.context = if number_of_blocks > 1 {
+            Context::WriteMultipleBlocks
+        } else {
+            Context::WriteSingleBlock
+        };
+        let result = self.send(lba, number_of_blocks, buffer);
+        self.finish_transfer(result)
+    }
+
+    /// number of blocks in a transfer of length bytes
+    fn check_transfer(&self,
+                      lba: usize,
+                      length: usize)
+                      -> Result<usize, low_level::SdmmcErrorCode> {
+        if self.state != State::Ready || length == 0 || length % BLOCK_SIZE != 0 {
+            return Err(low_level::REQUEST_NOT_APPLICABLE);
+        }
+        let number_of_blocks = length / BLOCK_SIZE;
+        if lba + number_of_blocks > self.sd_card.logical_number_of_blocks {
+            return Err(low_level::ADDR_OUT_OF_RANGE);
+        }
+        Ok(number_of_blocks)
+    }
+
+    fn receive(&mut self,
+               lba: usize,
+               number_of_blocks: usize,
+               buffer: &mut [u8])
+               -> Result<(), low_level::SdmmcErrorCode> {
+        let error = self.cmd_block_length(BLOCK_SIZE as u32);
+        if error != low_level::NONE {
+            return Err(error);
+        }
+        self.configure_data_path(buffer.len(), true);
+        let multiple = number_of_blocks > 1;
+        let address = self.data_address(lba);
+        let error = self.cmd_read_blocks(address, multiple);
+        if error != low_level::NONE {
+            return Err(error);
+        }
+
+        let timeout = ::system_clock::ticks() + TRANSFER_TIMEOUT;
+        let mut offset = 0;
+        loop {
+            let sta = self.registers.sta.read();
+            if sta.rxoverr() || sta.dcrcfail() || sta.dtimeout() || sta.dataend() {
+                break;
+            }
+            if sta.rxfifohf() {
+                for _ in 0..HALF_FIFO_WORDS {
+                    if offset < buffer.len() {
+                        let word = self.registers.fifo.read().fifodata();
+                        set_four_bytes_at_offset(buffer, offset, word);
+                        offset += 4;
+                    }
+                }
+            }
+            if ::system_clock::ticks() >= timeout {
+                return Err(low_level::TIMEOUT);
+            }
+        }
+        if multiple && self.registers.sta.read().dataend() {
+            let error = self.cmd_stop_transmission();
+            if error != low_level::NONE {
+                return Err(error);
+            }
+        }
+        self.data_error()?;
+
+        // the words, that are still in the FIFO after the end of the data
+        while self.registers.sta.read().rxdavl() && offset < buffer.len() {
+            let word = self.registers.fifo.read().fifodata();
+            set_four_bytes_at_offset(buffer, offset, word);
+            offset += 4;
+        }
+        Ok(())
+    }
+
+    fn send(&mut self,
+            lba: usize,
+            number_of_blocks: usize,
+            buffer: &[u8])
+            -> Result<(), low_level::SdmmcErrorCode> {
+        let error = self.cmd_block_length(BLOCK_SIZE as u32);
+        if error != low_level::NONE {
+            return Err(error);
+        }
+        let multiple = number_of_blocks > 1;
+        let address = self.data_address(lba);
+        let error = self.cmd_write_blocks(address, multiple);
+        if error != low_level::NONE {
+            return Err(error);
+        }
+        self.configure_data_path(buffer.len(), false);
+
+        let timeout = ::system_clock::ticks() + TRANSFER_TIMEOUT;
+        let mut offset = 0;
+        loop {
+            let sta = self.registers.sta.read();
+            if sta.txunderr() || sta.dcrcfail() || sta.dtimeout() || sta.dataend() {
+                break;
+            }
+            if sta.txfifohe() {
+                for _ in 0..HALF_FIFO_WORDS {
+                    if offset < buffer.len() {
+                        self.write_fifo(four_bytes_at_offset(buffer, offset));
+                        offset += 4;
+                    }
+                }
+            }
+            if ::system_clock::ticks() >= timeout {
+                return Err(low_level::TIMEOUT);
+            }
+        }
+        if multiple && self.registers.sta.read().dataend() {
+            let error = self.cmd_stop_transmission();
+            if error != low_level::NONE {
+                return Err(error);
+            }
+        }
+        self.data_error()?;
+        self.wait_until_ready_for_data()
+    }
+
+    /// SDSC cards are addressed in bytes, SDHC and SDXC cards in blocks
+    fn data_address(&self, lba: usize) -> u32 {
+        if self.sd_card.card_type == CardType::SdhcSdxc {
+            lba as u32
+        } else {
+            (lba * BLOCK_SIZE) as u32
+        }
+    }
+
+    /// enables the data path state machine for a block transfer of length bytes without DMA
+    // represents SDMMC_ConfigData
+    fn configure_data_path(&mut self, length: usize, card_to_host: bool) {
+        self.registers.dtimer.update(|dtimer| dtimer.set_datatime(DATA_TIMEOUT_CYCLES));
+        self.registers.dlen.update(|dlen| dlen.set_datalength(length as u32));
+        self.registers.dctrl.update(|dctrl| {
+            dctrl.set_dblocksize(BLOCK_SIZE_POWER);
+            dctrl.set_dtdir(card_to_host);
+            // block mode, no stream
+            dctrl.set_dtmode(false);
+            dctrl.set_dmaen(false);
+            dctrl.set_dten(true);
+        });
+    }
+
+    /// update(...) would read the FIFO first, i.e. take a word out of it
+    fn write_fifo(&mut self, word: u32) {
+        let fifo = &mut self.registers.fifo as *mut _ as *mut u32;
+        unsafe { ptr::write_volatile(fifo, word) };
+    }
+
+    /// the error flags of the data path after a transfer
+    fn data_error(&self) -> Result<(), low_level::SdmmcErrorCode> {
+        let sta = self.registers.sta.read();
+        if sta.dtimeout() {
+            Err(low_level::DATA_TIMEOUT)
+        } else if sta.dcrcfail() {
+            Err(low_level::DATA_CRC_FAIL)
+        } else if sta.rxoverr() {
+            Err(low_level::RX_OVERRUN)
+        } else if sta.txunderr() {
+            Err(low_level::TX_UNDERRUN)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// the card programs written blocks, it takes new commands for data only afterwards
+    fn wait_until_ready_for_data(&mut self) -> Result<(), low_level::SdmmcErrorCode> {
+        let rca = self.sd_card.relative_card_address as u32;
+        let timeout = ::system_clock::ticks() + TRANSFER_TIMEOUT;
+        while ::system_clock::ticks() < timeout {
+            if self.cmd_send_status(rca)? & READY_FOR_DATA != 0 {
+                return Ok(());
+            }
+        }
+        Err(low_level::TIMEOUT)
+    }
+
+    fn finish_transfer(&mut self,
+                       result: Result<(), low_level::SdmmcErrorCode>)
+                       -> Result<(), low_level::SdmmcErrorCode> {
+        self.clear_all_static_status_flags();
+        if let Err(error) = result {
+            self.error_code |= error;
+        }
+        self.context = Context::None;
+        self.state = State::Ready;
+        result
+    }
+}
@@ -0,0 +1,202 @@
+use super::directory_entry::*;
+use super::error::StorageError;
+use super::fat32_device_driver::Fat32DeviceDriver;
+use collections::vec::*;
+use core::cmp;
+
+//the size field of a directory entry has 32 bits
+const MAX_FILE_SIZE: usize = 0xFFFFFFFF;
+
+/// append handle for data logging, see Fat32DeviceDriver::append
+/// power may fail at any time:
+/// a cluster is linked into the chain before data goes into it,
+/// data is on the card before the size in the directory entry covers it,
+/// the size is only updated at checkpoints, every checkpoint_interval bytes
+/// after a reset the file reads up to the last checkpoint, never cross-linked;
+/// clusters behind it stay in the chain and are reused by the next append,
+/// fsck::check reports them as WrongFileSize until then
+/// the entry is written at its index, the driver refuses to move or remove the file
+/// until the writer is dropped; a forgotten writer keeps the file open
+pub struct AppendWriter<'a, 'b: 'a> {
+    driver: &'a Fat32DeviceDriver<'b>,
+    directory_cluster: usize,
+    index: usize,
+    directory_entry: [u8; 32],
+    checkpoint_interval: usize,
+    file_size: usize,
+    // size in the directory entry on the card
+    checkpoint_size: usize,
+    // cluster the buffer belongs to, 0 before the first cluster of an empty file
+    cluster: usize,
+    buffer: Vec<u8>,
+    // bytes of the buffer in use and bytes of them already on the card
+    filled: usize,
+    written: usize,
+}
+
+impl<'a, 'b: 'a> AppendWriter<'a, 'b> {
+    /// directory_entry is the entry with the given index in the directory at directory_cluster
+    /// the chain has to cover the file size, the last partial cluster is read
+    pub fn new(driver: &'a Fat32DeviceDriver<'b>,
+               directory_cluster: usize,
+               index: usize,
+               directory_entry: [u8; 32],
+               checkpoint_interval: usize)
+               -> Result<AppendWriter<'a, 'b>, StorageError> {
+        let entry = DirectoryEntry::new(&directory_entry)?;
+        let cluster_size = driver.cluster_size();
+        let file_size = entry.file_size();
+        let number_of_clusters = (file_size + cluster_size - 1) / cluster_size;
+
+        let mut cluster = 0;
+        if number_of_clusters > 0 {
+            cluster = entry.first_cluster();
+            if !driver.is_data_cluster(cluster) {
+                return Err(StorageError::CorruptChain);
+            }
+            for _ in 1..number_of_clusters {
+                cluster = match driver.next_cluster(cluster)? {
+                    Some(next_cluster) => next_cluster,
+                    None => return Err(StorageError::CorruptChain),
+                };
+            }
+        }
+
+        // a full (or no) last cluster: the next write moves on to the next one
+        let filled = file_size + cluster_size - number_of_clusters * cluster_size;
+        let mut buffer = Vec::new();
+        buffer.resize(cluster_size, 0);
+        if filled < cluster_size {
+            driver.read_cluster_data_region(cluster, &mut buffer)?;
+        }
+
+        Ok(AppendWriter {
+            driver: driver,
+            directory_cluster: directory_cluster,
+            index: index,
+            directory_entry: directory_entry,
+            checkpoint_interval: checkpoint_interval,
+            file_size: file_size,
+            checkpoint_size: file_size,
+            cluster: cluster,
+            buffer: buffer,
+            filled: filled,
+            written: filled,
+        })
+    }
+
+    /// appends data, full clusters are written at once, the rest at the next checkpoint
+    /// NoSpace if the volume is full or the file would grow beyond 4 GiB
+    pub fn write(&mut self, data: &[u8]) -> Result<(), StorageError> {
+        if data.len() > MAX_FILE_SIZE - self.file_size {
+            return Err(StorageError::NoSpace);
+        }
+        let cluster_size = self.buffer.len();
+        let mut data = data;
+        while !data.is_empty() {
+            if self.filled == cluster_size {
+                self.next_cluster()?;
+            }
+            let number = cmp::min(cluster_size - self.filled, data.len());
+            self.buffer[self.filled..self.filled + number].copy_from_slice(&data[..number]);
+            self.filled += number;
+            self.file_size += number;
+            data = &data[number..];
+
+            if self.filled == cluster_size {
+                self.write_buffer()?;
+            }
+            if self.file_size - self.checkpoint_size >= self.checkpoint_interval {
+                self.checkpoint()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// writes the buffered data, then the size into the directory entry
+    /// everything appended so far survives a reset afterwards
+    pub fn checkpoint(&mut self) -> Result<(), StorageError> {
+        self.write_buffer()?;
+        // the data has to be on the card before the size points to it
+        self.driver.flush()?;
+        if self.file_size != self.checkpoint_size {
+            set_file_size(&mut self.directory_entry, self.file_size);
+            if let Some(now) = self.driver.now() {
+                set_modified(&mut self.directory_entry, &now);
+            }
+            self.write_directory_entry()?;
+            self.driver.flush()?;
+            self.checkpoint_size = self.file_size;
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.file_size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.file_size == 0
+    }
+
+    /// the size that survives a reset
+    pub fn checkpoint_len(&self) -> usize {
+        self.checkpoint_size
+    }
+
+    /// moves on to the cluster following the current one
+    /// a cluster left in the chain by an interrupted append is reused,
+    /// otherwise a new one is linked before any data goes into it
+    fn next_cluster(&mut self) -> Result<(), StorageError> {
+        let next_cluster = if self.cluster == 0 {
+            let first_cluster = DirectoryEntry::new(&self.directory_entry)?.first_cluster();
+            if first_cluster != 0 {
+                first_cluster
+            } else {
+                let first_cluster = self.driver.append_cluster(None)?;
+                // the cluster is taken on the card before the entry points to it
+                self.driver.flush()?;
+                set_first_cluster(&mut self.directory_entry, first_cluster);
+                self.write_directory_entry()?;
+                first_cluster
+            }
+        } else {
+            match self.driver.next_cluster(self.cluster)? {
+                Some(next_cluster) => next_cluster,
+                None => self.driver.append_cluster(Some(self.cluster))?,
+            }
+        };
+        self.cluster = next_cluster;
+        self.filled = 0;
+        self.written = 0;
+        Ok(())
+    }
+
+    /// writes the blocks of the buffer that changed since the last write
+    fn write_buffer(&mut self) -> Result<(), StorageError> {
+        if self.filled == self.written {
+            return Ok(());
+        }
+        let block_size = self.driver.block_size();
+        let first_block = self.written / block_size;
+        let end_block = (self.filled + block_size - 1) / block_size;
+        self.driver
+            .write_cluster_blocks(self.cluster,
+                                  first_block,
+                                  &self.buffer[first_block * block_size..end_block * block_size])?;
+        self.written = self.filled;
+        Ok(())
+    }
+
+    fn write_directory_entry(&self) -> Result<(), StorageError> {
+        self.driver.write_directory_entry(self.directory_cluster, self.index, &self.directory_entry)
+    }
+}
+
+/// a last checkpoint, errors can't be reported here, call checkpoint() to see them
+impl<'a, 'b: 'a> Drop for AppendWriter<'a, 'b> {
+    fn drop(&mut self) {
+        let _ = self.checkpoint();
+        self.driver.close_append(self.directory_cluster, self.index);
+    }
+}
@@ -0,0 +1,13 @@
+bitflags! {
+    /// attribute byte of a FAT directory entry, exFAT uses the same bits
+    /// long name entries are marked with READ_ONLY | HIDDEN | SYSTEM | VOLUME_ID
+    pub flags Attributes: u8 {
+        const READ_ONLY = 0x01,
+        const HIDDEN = 0x02,
+        const SYSTEM = 0x04,
+        const VOLUME_ID = 0x08,
+        const DIRECTORY = 0x10,
+        /// set on every write, backup tools clear it
+        const ARCHIVE = 0x20,
+    }
+}
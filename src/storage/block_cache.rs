@@ -0,0 +1,294 @@
+use block_device::BlockDevice;
+use super::error::StorageError;
+use collections::vec::*;
+use core::cell::RefCell;
+use core::slice;
+use core::usize;
+
+/// keeps the most recently used blocks of block_device, e.g. FAT and directory blocks of the card
+/// writes stay in the cache until flush() or until their slot is needed (write-back),
+/// with write_through they go to block_device at once and the cache only saves reads
+/// between two flushes blocks reach block_device in any order, whoever depends on the order
+/// flushes in between, as Fat32DeviceDriver and AppendWriter do;
+/// call flush() before dropping the cache
+/// requests of several blocks and more than half the slots bypass it,
+/// so file data doesn't push out the FAT; without slots everything bypasses it
+pub struct BlockCache<'a, D: BlockDevice + ?Sized + 'a> {
+    block_device: &'a D,
+    write_through: bool,
+    inner: RefCell<Inner<'a>>,
+}
+
+struct Inner<'a> {
+    // the data of slot i is at memory[i * block_size..(i + 1) * block_size]
+    memory: Memory<'a>,
+    slots: Vec<Slot>,
+    clock: usize,
+}
+
+enum Memory<'a> {
+    Heap(Vec<u8>),
+    Borrowed(&'a mut [u8]),
+}
+
+struct Slot {
+    // usize::MAX for an empty slot
+    block_number: usize,
+    dirty: bool,
+    last_use: usize,
+}
+
+impl<'a> Memory<'a> {
+    fn as_slice(&self) -> &[u8] {
+        match *self {
+            Memory::Heap(ref memory) => memory,
+            Memory::Borrowed(ref memory) => memory,
+        }
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        match *self {
+            Memory::Heap(ref mut memory) => memory,
+            Memory::Borrowed(ref mut memory) => memory,
+        }
+    }
+}
+
+impl<'a, D: BlockDevice + ?Sized + 'a> BlockCache<'a, D> {
+    /// number_of_slots blocks on the heap
+    pub fn new(block_device: &'a D,
+               number_of_slots: usize,
+               write_through: bool)
+               -> BlockCache<'a, D> {
+        let mut memory = Vec::new();
+        memory.resize(number_of_slots * block_device.block_size(), 0);
+        BlockCache::with_memory(block_device, Memory::Heap(memory), write_through)
+    }
+
+    /// one slot for every whole block in memory
+    pub fn from_slice(block_device: &'a D,
+                      memory: &'a mut [u8],
+                      write_through: bool)
+                      -> BlockCache<'a, D> {
+        BlockCache::with_memory(block_device, Memory::Borrowed(memory), write_through)
+    }
+
+    /// slots in the region [start; start + size), e.g. in SDRAM behind the RAM disk
+    /// the region must not be used by anything else, see RamDisk::new
+    pub unsafe fn in_memory(block_device: &'a D,
+                            start: usize,
+                            size: usize,
+                            write_through: bool)
+                            -> BlockCache<'a, D> {
+        let memory = slice::from_raw_parts_mut(start as *mut u8, size);
+        BlockCache::from_slice(block_device, memory, write_through)
+    }
+
+    fn with_memory(block_device: &'a D,
+                   memory: Memory<'a>,
+                   write_through: bool)
+                   -> BlockCache<'a, D> {
+        let number_of_slots = memory.as_slice().len() / block_device.block_size();
+        let mut slots = Vec::with_capacity(number_of_slots);
+        for _ in 0..number_of_slots {
+            slots.push(Slot {
+                block_number: usize::MAX,
+                dirty: false,
+                last_use: 0,
+            });
+        }
+        BlockCache {
+            block_device: block_device,
+            write_through: write_through,
+            inner: RefCell::new(Inner {
+                memory: memory,
+                slots: slots,
+                clock: 0,
+            }),
+        }
+    }
+
+    pub fn number_of_slots(&self) -> usize {
+        self.inner.borrow().slots.len()
+    }
+
+    pub fn is_write_through(&self) -> bool {
+        self.write_through
+    }
+
+    /// blocks written to the cache, but not yet to block_device
+    pub fn number_of_dirty_blocks(&self) -> usize {
+        self.inner.borrow().slots.iter().filter(|slot| slot.dirty).count()
+    }
+}
+
+impl<'a, D> BlockCache<'a, D>
+    where D: BlockDevice + ?Sized + 'a,
+          D::Error: Into<StorageError>
+{
+    /// whole blocks on block_device only
+    fn check_range(&self, lba: usize, length: usize) -> Result<(), StorageError> {
+        let block_size = self.block_device.block_size();
+        if length % block_size != 0 || lba + length / block_size > self.number_of_blocks() {
+            return Err(StorageError::OutOfRange);
+        }
+        Ok(())
+    }
+
+    /// a free slot, the least recently used one is written back and taken, if there is none
+    fn allocate(&self, inner: &mut Inner) -> Result<usize, StorageError> {
+        // empty slots have never been used, they come first
+        let mut oldest = 0;
+        for i in 1..inner.slots.len() {
+            if inner.slots[i].last_use < inner.slots[oldest].last_use {
+                oldest = i;
+            }
+        }
+        if inner.slots[oldest].dirty {
+            let block_size = self.block_device.block_size();
+            let block_number = inner.slots[oldest].block_number;
+            let data = &inner.memory.as_slice()[oldest * block_size..(oldest + 1) * block_size];
+            self.block_device.write(block_number, data).map_err(Into::into)?;
+            inner.slots[oldest].dirty = false;
+        }
+        inner.slots[oldest].block_number = usize::MAX;
+        Ok(oldest)
+    }
+
+    /// writes the dirty blocks in ascending order, neighbouring blocks with one write
+    fn write_back(&self, inner: &mut Inner) -> Result<(), StorageError> {
+        let block_size = self.block_device.block_size();
+        let mut dirty: Vec<usize> = (0..inner.slots.len())
+            .filter(|&index| inner.slots[index].dirty)
+            .collect();
+        dirty.sort_by_key(|&index| inner.slots[index].block_number);
+
+        let mut blocks = Vec::new();
+        let mut first = 0;
+        while first < dirty.len() {
+            let first_block_number = inner.slots[dirty[first]].block_number;
+            let mut end = first;
+            blocks.clear();
+            while end < dirty.len() &&
+                  inner.slots[dirty[end]].block_number == first_block_number + end - first {
+                let index = dirty[end];
+                blocks.extend_from_slice(&inner.memory.as_slice()[index * block_size..
+                                                                   (index + 1) * block_size]);
+                end += 1;
+            }
+            self.block_device.write(first_block_number, &blocks).map_err(Into::into)?;
+            for &index in &dirty[first..end] {
+                inner.slots[index].dirty = false;
+            }
+            first = end;
+        }
+        Ok(())
+    }
+}
+
+impl<'a, D> BlockDevice for BlockCache<'a, D>
+    where D: BlockDevice + ?Sized + 'a,
+          D::Error: Into<StorageError>
+{
+    type Error = StorageError;
+
+    /// one read of block_device for everything that is not cached
+    fn read(&self, lba: usize, buffer: &mut [u8]) -> Result<(), StorageError> {
+        self.check_range(lba, buffer.len())?;
+        let block_size = self.block_device.block_size();
+        let number_of_blocks = buffer.len() / block_size;
+        let mut inner = self.inner.borrow_mut();
+        let inner = &mut *inner;
+        let bypass = bypasses(inner, number_of_blocks);
+
+        let all_cached = (lba..lba + number_of_blocks)
+            .all(|block_number| find(inner, block_number).is_some());
+        if !all_cached {
+            self.block_device.read(lba, buffer).map_err(Into::into)?;
+        }
+
+        // the blocks of this request are the most recently used, allocate() keeps them
+        inner.clock += 1;
+        let clock = inner.clock;
+        for (i, block) in buffer.chunks_mut(block_size).enumerate() {
+            if let Some(index) = find(inner, lba + i) {
+                // the cached block is newer, if it is dirty
+                if all_cached || inner.slots[index].dirty {
+                    block.copy_from_slice(&inner.memory.as_slice()[index * block_size..
+                                                                   (index + 1) * block_size]);
+                }
+                inner.slots[index].last_use = clock;
+            }
+        }
+        if bypass {
+            return Ok(());
+        }
+        for (i, block) in buffer.chunks(block_size).enumerate() {
+            if find(inner, lba + i).is_none() {
+                let index = self.allocate(inner)?;
+                inner.memory.as_mut_slice()[index * block_size..(index + 1) * block_size]
+                    .copy_from_slice(block);
+                inner.slots[index].block_number = lba + i;
+                inner.slots[index].last_use = clock;
+            }
+        }
+        Ok(())
+    }
+
+    /// cached copies are updated, new blocks are only cached without write_through
+    fn write(&self, lba: usize, buffer: &[u8]) -> Result<(), StorageError> {
+        self.check_range(lba, buffer.len())?;
+        let block_size = self.block_device.block_size();
+        let number_of_blocks = buffer.len() / block_size;
+        let mut inner = self.inner.borrow_mut();
+        let inner = &mut *inner;
+        let bypass = bypasses(inner, number_of_blocks);
+
+        if self.write_through || bypass {
+            self.block_device.write(lba, buffer).map_err(Into::into)?;
+        }
+
+        inner.clock += 1;
+        let clock = inner.clock;
+        for (i, block) in buffer.chunks(block_size).enumerate() {
+            let index = match find(inner, lba + i) {
+                Some(index) => index,
+                None if self.write_through || bypass => continue,
+                None => {
+                    let index = self.allocate(inner)?;
+                    inner.slots[index].block_number = lba + i;
+                    index
+                }
+            };
+            inner.memory.as_mut_slice()[index * block_size..(index + 1) * block_size]
+                .copy_from_slice(block);
+            inner.slots[index].dirty = !self.write_through && !bypass;
+            inner.slots[index].last_use = clock;
+        }
+        Ok(())
+    }
+
+    /// writes the dirty blocks back, then flushes block_device
+    fn flush(&self) -> Result<(), StorageError> {
+        self.write_back(&mut self.inner.borrow_mut())?;
+        self.block_device.flush().map_err(Into::into)
+    }
+
+    fn number_of_blocks(&self) -> usize {
+        self.block_device.number_of_blocks()
+    }
+
+    fn block_size(&self) -> usize {
+        self.block_device.block_size()
+    }
+}
+
+/// single blocks are cached, even with one slot
+fn bypasses(inner: &Inner, number_of_blocks: usize) -> bool {
+    inner.slots.is_empty() || number_of_blocks > 1 && number_of_blocks > inner.slots.len() / 2
+}
+
+/// index of the slot holding block_number
+fn find(inner: &Inner, block_number: usize) -> Option<usize> {
+    inner.slots.iter().position(|slot| slot.block_number == block_number)
+}
@@ -0,0 +1,13 @@
+/// CRC-32 as used by GPT and Ethernet (reflected polynomial 0xEDB88320)
+/// computed bit by bit, a table would cost 1 KiB of flash
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0xFFFFFFFF;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xEDB88320 & mask);
+        }
+    }
+    !crc
+}
@@ -0,0 +1,194 @@
+//FAT counts years from 1980 in seven bits
+const FIRST_YEAR: u16 = 1980;
+const LAST_YEAR: u16 = 2107;
+/// 1980-01-01 00:00:00, the first time FAT can store
+pub const EPOCH: DateTime = DateTime {
+    year: FIRST_YEAR,
+    month: 1,
+    day: 1,
+    hour: 0,
+    minute: 0,
+    second: 0,
+    millisecond: 0,
+};
+
+/// a point in time as FAT stores it, local time without time zone
+/// the order of the fields makes the derived order chronological
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DateTime {
+    year: u16,
+    month: u8,
+    day: u8,
+    hour: u8,
+    minute: u8,
+    second: u8,
+    millisecond: u16,
+}
+
+impl DateTime {
+    /// None for impossible dates and times and for years FAT can't store (1980 to 2107)
+    pub fn new(year: u16,
+               month: u8,
+               day: u8,
+               hour: u8,
+               minute: u8,
+               second: u8)
+               -> Option<DateTime> {
+        if year < FIRST_YEAR || year > LAST_YEAR || month == 0 || month > 12 || day == 0 ||
+           day > days_in_month(year, month) || hour > 23 || minute > 59 || second > 59 {
+            return None;
+        }
+        Some(DateTime {
+            year: year,
+            month: month,
+            day: day,
+            hour: hour,
+            minute: minute,
+            second: second,
+            millisecond: 0,
+        })
+    }
+
+    /// date and time fields of a directory entry, hundredths is only stored for the creation
+    /// None for a date of 0, the field is not maintained, or for impossible values
+    pub fn from_fat(date: u16, time: u16, hundredths: u8) -> Option<DateTime> {
+        if date == 0 || hundredths > 199 {
+            return None;
+        }
+        let date_time = DateTime::new(FIRST_YEAR + (date >> 9),
+                                      (date >> 5 & 0x0F) as u8,
+                                      (date & 0x1F) as u8,
+                                      (time >> 11) as u8,
+                                      (time >> 5 & 0x3F) as u8,
+                                      (time & 0x1F) as u8 * 2 + hundredths / 100);
+        date_time.map(|date_time| {
+            DateTime { millisecond: (hundredths % 100) as u16 * 10, ..date_time }
+        })
+    }
+
+    pub fn year(&self) -> u16 {
+        self.year
+    }
+
+    pub fn month(&self) -> u8 {
+        self.month
+    }
+
+    pub fn day(&self) -> u8 {
+        self.day
+    }
+
+    pub fn hour(&self) -> u8 {
+        self.hour
+    }
+
+    pub fn minute(&self) -> u8 {
+        self.minute
+    }
+
+    pub fn second(&self) -> u8 {
+        self.second
+    }
+
+    /// 0 except for creation times, they are stored in units of 10 ms
+    pub fn millisecond(&self) -> u16 {
+        self.millisecond
+    }
+
+    pub fn fat_date(&self) -> u16 {
+        (self.year - FIRST_YEAR) << 9 | (self.month as u16) << 5 | self.day as u16
+    }
+
+    /// seconds are stored divided by two, the odd second is in fat_hundredths()
+    pub fn fat_time(&self) -> u16 {
+        (self.hour as u16) << 11 | (self.minute as u16) << 5 | (self.second / 2) as u16
+    }
+
+    pub fn fat_hundredths(&self) -> u8 {
+        (self.second % 2) * 100 + (self.millisecond / 10) as u8
+    }
+}
+
+/// a clock to stamp new and modified directory entries, e.g. the RTC
+pub trait TimeSource {
+    fn now(&self) -> DateTime;
+}
+
+/// always the same time, for tests and boards without a clock
+pub struct FixedTimeSource {
+    date_time: DateTime,
+}
+
+impl FixedTimeSource {
+    pub fn new(date_time: DateTime) -> FixedTimeSource {
+        FixedTimeSource { date_time: date_time }
+    }
+}
+
+impl TimeSource for FixedTimeSource {
+    fn now(&self) -> DateTime {
+        self.date_time
+    }
+}
+
+/// the clock of the host in UTC, only with the std feature
+#[cfg(feature = "std")]
+pub struct HostTimeSource;
+
+#[cfg(feature = "std")]
+impl TimeSource for HostTimeSource {
+    /// times FAT can't store become the first or last second it can
+    fn now(&self) -> DateTime {
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+        let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+        let second_of_day = seconds % 86400;
+        let date_time = if year < FIRST_YEAR as i64 {
+            DateTime::new(FIRST_YEAR, 1, 1, 0, 0, 0)
+        } else if year > LAST_YEAR as i64 {
+            DateTime::new(LAST_YEAR, 12, 31, 23, 59, 58)
+        } else {
+            DateTime::new(year as u16,
+                          month,
+                          day,
+                          (second_of_day / 3600) as u8,
+                          (second_of_day / 60 % 60) as u8,
+                          (second_of_day % 60) as u8)
+        };
+        date_time.unwrap_or(EPOCH)
+    }
+}
+
+/// year, month and day of the day number days since 1970-01-01
+// see Howard Hinnant, "chrono-Compatible Low-Level Date Algorithms"
+#[cfg(feature = "std")]
+fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let days = days + 719468;
+    let era = (if days >= 0 { days } else { days - 146096 }) / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 -
+                       day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u8;
+    let month = (if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    }) as u8;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
@@ -1,3 +1,6 @@
+use super::attributes::{self, Attributes};
+use super::date_time::DateTime;
+use super::error::StorageError;
 use super::get_bytes::*;
 use collections::vec::*;
 use collections::string::*;
@@ -5,25 +8,68 @@ use collections::string::*;
 // offset and number could be a tuple
 const NAME_OFFSET: usize = 0; //11
 const ATTRIBUTE_OFFSET: usize = 11; //1
+const CREATION_HUNDREDTHS_OFFSET: usize = 13; //1
+const CREATION_TIME_OFFSET: usize = 14; //2
+const CREATION_DATE_OFFSET: usize = 16; //2
+const LAST_ACCESS_DATE_OFFSET: usize = 18; //2
 const FIRST_CLUSTER_HIGH_OFFSET: usize = 20; //2
+const MODIFICATION_TIME_OFFSET: usize = 22; //2
+const MODIFICATION_DATE_OFFSET: usize = 24; //2
 const FIRST_CLUSTER_LOW_OFFSET: usize = 26; //2
 const FILE_SIZE_OFFSET: usize = 28; //4
 
+//the upper two bits are reserved
+const ATTRIBUTE_MASK: u8 = 0x3F;
+//read-only, hidden, system and volume ID at once
+const ATTRIBUTE_LONG_NAME: u8 = 0x0F;
+
+const LONG_NAME_ORDER_OFFSET: usize = 0; //1
+const LONG_NAME_CHECKSUM_OFFSET: usize = 13; //1
+const LONG_NAME_LAST_ENTRY: u8 = 0x40;
+//offsets of the three UTF-16 fragments of a long name entry (5, 6 and 2 characters)
+const LONG_NAME_CHARACTER_OFFSETS: [usize; 13] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+const MAX_LONG_NAME_LENGTH: usize = 255;
+//besides control characters
+const LONG_NAME_INVALID_CHARACTERS: &'static str = "\"*/:<>?\\|";
+
+/// what a directory entry stands for
+/// entries of long names and deleted entries are neither
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Directory,
+    VolumeLabel,
+}
+
 //File [sic!] cant be a proper BlockDevice yet ->see BlockDevice comments
 //  should be a handle, that knows the mbr driver (?)
 /// just a simple container
-/// can represent a file or a directory
+/// can represent a file, a directory or the volume label
 pub struct DirectoryEntry {
     name_extension: String,
+    long_name: Option<String>,
+    kind: EntryKind,
+    attributes: Attributes,
     is_file: bool,
+    is_directory: bool,
     first_cluster_entry_number: usize,
     file_size: usize,
+    created: Option<DateTime>,
+    accessed: Option<DateTime>,
+    modified: Option<DateTime>,
 }
 
 impl DirectoryEntry {
-    pub fn new(directory_entry: &[u8]) -> DirectoryEntry {
+    pub fn new(directory_entry: &[u8]) -> Result<DirectoryEntry, StorageError> {
+        DirectoryEntry::with_long_name(directory_entry, None)
+    }
+
+    /// long_name has to be checked against the short name before, see LongNameBuilder
+    pub fn with_long_name(directory_entry: &[u8],
+                          long_name: Option<String>)
+                          -> Result<DirectoryEntry, StorageError> {
         if directory_entry.len() != 32 {
-            panic!("32");
+            return Err(StorageError::OutOfRange);
         }
 
         let mut name_vec = Vec::with_capacity(8);
@@ -42,42 +88,89 @@ impl DirectoryEntry {
         let mut name_extension = String::with_capacity(11);
         name_extension.push_str(&name);
         if !extension.is_empty() {
-            name_extension.push('.');
-            name_extension.push_str(&extension);
+            if directory_entry[ATTRIBUTE_OFFSET] & attributes::VOLUME_ID.bits() != 0 {
+                // the volume label uses all 11 bytes as one name
+                name_extension.clear();
+                let mut label_vec = Vec::with_capacity(11);
+                for i in 0..11 {
+                    label_vec.push(directory_entry[i] as u16);
+                }
+                let label = String::from_utf16_lossy(&label_vec);
+                name_extension.push_str(&label.trim().to_lowercase());
+            } else {
+                name_extension.push('.');
+                name_extension.push_str(&extension);
+            }
         }
 
         let high = two_bytes_at_offset(&directory_entry, FIRST_CLUSTER_HIGH_OFFSET) as u32;
         let low = two_bytes_at_offset(&directory_entry, FIRST_CLUSTER_LOW_OFFSET) as u32;
         let first_cluster_entry_number = (high << 16 | low) as usize;
 
-        let mut is_file = true;
-        let attr = directory_entry[ATTRIBUTE_OFFSET];
+        let attr = Attributes::from_bits_truncate(directory_entry[ATTRIBUTE_OFFSET]);
+        let is_volume_id = attr.contains(attributes::VOLUME_ID);
         let no_name = directory_entry[NAME_OFFSET];
+        let is_in_use = no_name != 0xE5 && no_name != 0;
+        let is_directory = is_in_use && !is_volume_id && attr.contains(attributes::DIRECTORY);
+        let is_file = is_in_use && !is_volume_id && !attr.contains(attributes::DIRECTORY);
 
-        let mut is_volume_id = false;
-        let mut is_directory = false;
-
-        if attr & 0x08 != 0 {
-            is_volume_id = true;
-        }
-
-        if attr & 0x10 != 0 {
-            is_directory = true;
-        }
-
-        if is_volume_id || is_directory {
-            is_file = false;
-        } else if no_name == 0xE5 || no_name == 0 {
-            is_file = false;
-        }
+        let kind = if is_volume_id {
+            EntryKind::VolumeLabel
+        } else if attr.contains(attributes::DIRECTORY) {
+            EntryKind::Directory
+        } else {
+            EntryKind::File
+        };
 
         let file_size = four_bytes_at_offset(&directory_entry, FILE_SIZE_OFFSET) as usize;
 
-        DirectoryEntry {
+        let field_at = |offset| two_bytes_at_offset(directory_entry, offset);
+        let created = DateTime::from_fat(field_at(CREATION_DATE_OFFSET),
+                                         field_at(CREATION_TIME_OFFSET),
+                                         directory_entry[CREATION_HUNDREDTHS_OFFSET]);
+        let accessed = DateTime::from_fat(field_at(LAST_ACCESS_DATE_OFFSET), 0, 0);
+        let modified = DateTime::from_fat(field_at(MODIFICATION_DATE_OFFSET),
+                                          field_at(MODIFICATION_TIME_OFFSET),
+                                          0);
+
+        Ok(DirectoryEntry {
             name_extension: name_extension,
+            long_name: long_name,
+            kind: kind,
+            attributes: attr,
             is_file: is_file,
+            is_directory: is_directory,
             first_cluster_entry_number: first_cluster_entry_number,
             file_size: file_size,
+            created: created,
+            accessed: accessed,
+            modified: modified,
+        })
+    }
+
+    /// for file systems without 32 byte FAT entries, e.g. exFAT
+    pub fn from_parts(name: String,
+                      attributes: Attributes,
+                      first_cluster: usize,
+                      file_size: usize)
+                      -> DirectoryEntry {
+        let is_directory = attributes.contains(attributes::DIRECTORY);
+        DirectoryEntry {
+            name_extension: name.to_lowercase(),
+            long_name: Some(name),
+            kind: if is_directory {
+                EntryKind::Directory
+            } else {
+                EntryKind::File
+            },
+            attributes: attributes,
+            is_file: !is_directory,
+            is_directory: is_directory,
+            first_cluster_entry_number: first_cluster,
+            file_size: file_size,
+            created: None,
+            accessed: None,
+            modified: None,
         }
     }
 
@@ -89,11 +182,355 @@ impl DirectoryEntry {
         self.is_file
     }
 
+    pub fn is_directory(&self) -> bool {
+        self.is_directory
+    }
+
+    /// only meaningful for entries in use, see is_file and is_directory
+    pub fn kind(&self) -> EntryKind {
+        self.kind
+    }
+
+    pub fn attributes(&self) -> Attributes {
+        self.attributes
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.attributes.contains(attributes::READ_ONLY)
+    }
+
+    /// hidden and system entries are left out of listings, see ReadDir::hide
+    pub fn is_hidden(&self) -> bool {
+        self.attributes.contains(attributes::HIDDEN)
+    }
+
     pub fn file_size(&self) -> usize {
         self.file_size
     }
 
+    /// None if the file system did not store it
+    pub fn created(&self) -> Option<DateTime> {
+        self.created
+    }
+
+    /// only the date is stored, the time is 00:00:00
+    pub fn accessed(&self) -> Option<DateTime> {
+        self.accessed
+    }
+
+    pub fn modified(&self) -> Option<DateTime> {
+        self.modified
+    }
+
     pub fn name_extension(&self) -> &String {
         &self.name_extension
     }
+
+    pub fn long_name(&self) -> Option<&String> {
+        self.long_name.as_ref()
+    }
+
+    /// the long name if there is one, the short name otherwise
+    pub fn name(&self) -> &str {
+        match self.long_name {
+            Some(ref long_name) => long_name,
+            None => &self.name_extension,
+        }
+    }
+
+    /// case insensitive comparison with the short and the long name
+    pub fn matches(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        if self.name_extension == name {
+            return true;
+        }
+        match self.long_name {
+            Some(ref long_name) => long_name.to_lowercase() == name,
+            None => false,
+        }
+    }
+}
+
+/// true for the VFAT entries holding fragments of a long name
+pub fn is_long_name_entry(directory_entry: &[u8]) -> bool {
+    directory_entry[NAME_OFFSET] != 0xE5 &&
+    directory_entry[ATTRIBUTE_OFFSET] & ATTRIBUTE_MASK == ATTRIBUTE_LONG_NAME
+}
+
+/// checksum of the 11 byte short name, stored in each long name entry
+pub fn short_name_checksum(short_name: &[u8]) -> u8 {
+    let mut sum: u8 = 0;
+    for byte in &short_name[..11] {
+        sum = ((sum & 1) << 7).wrapping_add(sum >> 1).wrapping_add(*byte);
+    }
+    sum
+}
+
+/// collects the long name entries preceding a short entry
+/// they are stored in reverse order, the first one is flagged with 0x40
+pub struct LongNameBuilder {
+    characters: Vec<u16>,
+    checksum: u8,
+    next_order: u8,
+}
+
+impl LongNameBuilder {
+    pub fn new() -> LongNameBuilder {
+        LongNameBuilder {
+            characters: Vec::new(),
+            checksum: 0,
+            next_order: 0,
+        }
+    }
+
+    /// an out of order fragment discards everything collected so far
+    pub fn push(&mut self, directory_entry: &[u8]) {
+        let order = directory_entry[LONG_NAME_ORDER_OFFSET];
+        let checksum = directory_entry[LONG_NAME_CHECKSUM_OFFSET];
+        let number = (order & !LONG_NAME_LAST_ENTRY) as usize;
+
+        if number == 0 {
+            self.clear();
+            return;
+        }
+        if order & LONG_NAME_LAST_ENTRY != 0 {
+            self.characters.clear();
+            self.characters.resize(number * 13, 0xFFFF);
+            self.checksum = checksum;
+        } else if self.characters.is_empty() || order != self.next_order ||
+                  checksum != self.checksum {
+            self.clear();
+            return;
+        }
+
+        for (i, offset) in LONG_NAME_CHARACTER_OFFSETS.iter().enumerate() {
+            self.characters[(number - 1) * 13 + i] = two_bytes_at_offset(directory_entry,
+                                                                         *offset);
+        }
+        self.next_order = (number - 1) as u8;
+    }
+
+    /// returns the collected long name, if it is complete and belongs to short_entry
+    /// the builder is empty afterwards
+    pub fn take(&mut self, short_entry: &[u8]) -> Option<String> {
+        let complete = !self.characters.is_empty() && self.next_order == 0 &&
+                       self.checksum == short_name_checksum(&short_entry[NAME_OFFSET..]);
+        let long_name = if complete {
+            let length = self.characters
+                .iter()
+                .position(|c| *c == 0x0000 || *c == 0xFFFF)
+                .unwrap_or(self.characters.len());
+            Some(String::from_utf16_lossy(&self.characters[..length]))
+        } else {
+            None
+        };
+        self.clear();
+        long_name
+    }
+
+    pub fn clear(&mut self) {
+        self.characters.clear();
+        self.next_order = 0;
+    }
+}
+
+/// converts "name.ext" into the space padded, upper case 8.3 form
+/// returns None if name_extension is no valid short name
+pub fn short_name(name_extension: &str) -> Option<[u8; 11]> {
+    let (name, extension) = match name_extension.rfind('.') {
+        Some(i) => (&name_extension[..i], &name_extension[i + 1..]),
+        None => (name_extension, ""),
+    };
+    if name.is_empty() || name.len() > 8 || extension.len() > 3 {
+        return None;
+    }
+
+    let mut short = [b' '; 11];
+    for (i, byte) in name.bytes().enumerate() {
+        short[i] = match short_name_byte(byte) {
+            Some(b) => b,
+            None => return None,
+        };
+    }
+    for (i, byte) in extension.bytes().enumerate() {
+        short[8 + i] = match short_name_byte(byte) {
+            Some(b) => b,
+            None => return None,
+        };
+    }
+    Some(short)
+}
+
+/// true if name_extension can be stored as long name: up to 255 UTF-16 characters,
+/// no control characters, none of "*/:<>?\| and no dot or space at the end
+pub fn is_valid_long_name(name_extension: &str) -> bool {
+    !name_extension.is_empty() &&
+    name_extension.encode_utf16().count() <= MAX_LONG_NAME_LENGTH &&
+    !name_extension.ends_with('.') && !name_extension.ends_with(' ') &&
+    !name_extension.chars()
+        .any(|c| (c as u32) < 0x20 || LONG_NAME_INVALID_CHARACTERS.contains(c))
+}
+
+/// the short name stored with a long name, e.g. "SENSOR~1CSV" for "Sensor Log.csv"
+/// and number 1: upper case, without spaces and leading dots, other characters a short
+/// name can't hold become '_', the name is cut to make room for "~number"
+pub fn numbered_short_name(long_name: &str, number: usize) -> [u8; 11] {
+    let long_name = long_name.trim_left_matches('.');
+    let (name, extension) = match long_name.rfind('.') {
+        Some(i) => (&long_name[..i], &long_name[i + 1..]),
+        None => (long_name, ""),
+    };
+    let basis = |part: &str, length: usize| -> Vec<u8> {
+        part.chars()
+            .filter(|c| *c != ' ' && *c != '.')
+            .map(|c| if (c as u32) < 0x80 {
+                     short_name_byte(c as u8).unwrap_or(b'_')
+                 } else {
+                     b'_'
+                 })
+            .take(length)
+            .collect()
+    };
+
+    let mut tail = Vec::new();
+    let mut rest = number;
+    loop {
+        tail.insert(0, b'0' + (rest % 10) as u8);
+        rest /= 10;
+        if rest == 0 {
+            break;
+        }
+    }
+    tail.insert(0, b'~');
+    let mut name = basis(name, 8);
+    name.truncate(8 - tail.len());
+    name.extend_from_slice(&tail);
+    let extension = basis(extension, 3);
+
+    let mut short = [b' '; 11];
+    short[..name.len()].copy_from_slice(&name);
+    short[8..8 + extension.len()].copy_from_slice(&extension);
+    short
+}
+
+/// the entries of long_name in front of the entry with short_name, as they are stored:
+/// the last part first, flagged with 0x40
+pub fn new_long_name_entries(long_name: &str, short_name: &[u8; 11]) -> Vec<[u8; 32]> {
+    let checksum = short_name_checksum(short_name);
+    let mut characters: Vec<u16> = long_name.encode_utf16().collect();
+    // a name that fills its last entry has no terminator
+    if characters.len() % 13 != 0 {
+        characters.push(0x0000);
+    }
+    while characters.len() % 13 != 0 {
+        characters.push(0xFFFF);
+    }
+    let number = characters.len() / 13;
+    let mut entries = Vec::with_capacity(number);
+    for n in (0..number).rev() {
+        let mut directory_entry = [0; 32];
+        directory_entry[LONG_NAME_ORDER_OFFSET] = (n + 1) as u8;
+        if n + 1 == number {
+            directory_entry[LONG_NAME_ORDER_OFFSET] |= LONG_NAME_LAST_ENTRY;
+        }
+        directory_entry[ATTRIBUTE_OFFSET] = ATTRIBUTE_LONG_NAME;
+        directory_entry[LONG_NAME_CHECKSUM_OFFSET] = checksum;
+        for (i, offset) in LONG_NAME_CHARACTER_OFFSETS.iter().enumerate() {
+            set_two_bytes_at_offset(&mut directory_entry, *offset, characters[n * 13 + i]);
+        }
+        entries.push(directory_entry);
+    }
+    entries
+}
+
+fn short_name_byte(byte: u8) -> Option<u8> {
+    match byte {
+        b'a'...b'z' => Some(byte - b'a' + b'A'),
+        b'A'...b'Z' | b'0'...b'9' => Some(byte),
+        b'!' | b'#' | b'$' | b'%' | b'&' | b'\'' | b'(' | b')' | b'-' | b'@' | b'^' | b'_' |
+        b'`' | b'{' | b'}' | b'~' => Some(byte),
+        _ => None,
+    }
+}
+
+/// converts label into the space padded, upper case form of the boot sector
+/// returns None if label is empty, longer than 11 characters or contains invalid ones
+pub fn volume_label(label: &str) -> Option<[u8; 11]> {
+    if label.is_empty() || label.len() > 11 {
+        return None;
+    }
+    let mut volume_label = [b' '; 11];
+    for (i, byte) in label.bytes().enumerate() {
+        volume_label[i] = match byte {
+            b' ' => byte,
+            _ => {
+                match short_name_byte(byte) {
+                    Some(b) => b,
+                    None => return None,
+                }
+            }
+        };
+    }
+    Some(volume_label)
+}
+
+/// raw directory entry of the volume label, it belongs into the root directory
+pub fn new_volume_label_entry(volume_label: &[u8; 11]) -> [u8; 32] {
+    let mut directory_entry = [0; 32];
+    directory_entry[NAME_OFFSET..NAME_OFFSET + 11].copy_from_slice(volume_label);
+    directory_entry[ATTRIBUTE_OFFSET] = attributes::VOLUME_ID.bits();
+    directory_entry
+}
+
+/// raw directory entry of an empty file
+pub fn new_file_entry(short_name: &[u8; 11]) -> [u8; 32] {
+    let mut directory_entry = [0; 32];
+    directory_entry[NAME_OFFSET..NAME_OFFSET + 11].copy_from_slice(short_name);
+    directory_entry[ATTRIBUTE_OFFSET] = attributes::ARCHIVE.bits();
+    directory_entry
+}
+
+/// raw directory entry of an empty directory, its cluster needs "." and ".." entries
+pub fn new_directory_entry(short_name: &[u8; 11]) -> [u8; 32] {
+    let mut directory_entry = [0; 32];
+    directory_entry[NAME_OFFSET..NAME_OFFSET + 11].copy_from_slice(short_name);
+    directory_entry[ATTRIBUTE_OFFSET] = attributes::DIRECTORY.bits();
+    directory_entry
+}
+
+/// the long name entries in front of it have to be removed, their checksum would not match
+pub fn set_short_name(directory_entry: &mut [u8], short_name: &[u8; 11]) {
+    directory_entry[NAME_OFFSET..NAME_OFFSET + 11].copy_from_slice(short_name);
+}
+
+/// the reserved upper bits are kept
+pub fn set_attributes(directory_entry: &mut [u8], attributes: Attributes) {
+    directory_entry[ATTRIBUTE_OFFSET] = (directory_entry[ATTRIBUTE_OFFSET] & !ATTRIBUTE_MASK) |
+                                        attributes.bits();
+}
+
+pub fn set_first_cluster(directory_entry: &mut [u8], first_cluster: usize) {
+    set_two_bytes_at_offset(directory_entry,
+                            FIRST_CLUSTER_HIGH_OFFSET,
+                            (first_cluster >> 16) as u16);
+    set_two_bytes_at_offset(directory_entry, FIRST_CLUSTER_LOW_OFFSET, first_cluster as u16);
+}
+
+/// sets the creation, access and modification time of a new entry
+pub fn set_created(directory_entry: &mut [u8], date_time: &DateTime) {
+    directory_entry[CREATION_HUNDREDTHS_OFFSET] = date_time.fat_hundredths();
+    set_two_bytes_at_offset(directory_entry, CREATION_TIME_OFFSET, date_time.fat_time());
+    set_two_bytes_at_offset(directory_entry, CREATION_DATE_OFFSET, date_time.fat_date());
+    set_modified(directory_entry, date_time);
+}
+
+/// sets the modification and access time, e.g. after a write
+pub fn set_modified(directory_entry: &mut [u8], date_time: &DateTime) {
+    set_two_bytes_at_offset(directory_entry, LAST_ACCESS_DATE_OFFSET, date_time.fat_date());
+    set_two_bytes_at_offset(directory_entry, MODIFICATION_TIME_OFFSET, date_time.fat_time());
+    set_two_bytes_at_offset(directory_entry, MODIFICATION_DATE_OFFSET, date_time.fat_date());
+}
+
+pub fn set_file_size(directory_entry: &mut [u8], file_size: usize) {
+    set_four_bytes_at_offset(directory_entry, FILE_SIZE_OFFSET, file_size as u32);
 }
@@ -0,0 +1,65 @@
+use block_device::BlockDevice;
+use collections::vec::*;
+
+/// everything that can go wrong in the storage stack
+/// a bad card must never halt the firmware, so nothing in here panics
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageError {
+    /// the block size of the device is no multiple of 512
+    InvalidBlockSize,
+    /// the cluster size is no power of two multiple of the block size,
+    /// or too large or too small for the volume
+    InvalidClusterSize,
+    /// a boot sector or partition table has a wrong signature or impossible values
+    BadSignature,
+    /// a cluster chain is too short, loops or runs into free or bad clusters
+    CorruptChain,
+    /// a block, cluster or entry behind the end of the device or volume
+    OutOfRange,
+    /// the block device failed
+    IoError,
+    /// no such file or directory
+    NotFound,
+    /// a file was expected
+    IsADirectory,
+    /// a directory was expected
+    NotADirectory,
+    /// there is a file or directory with that name already
+    AlreadyExists,
+    /// only empty directories can be removed
+    DirectoryNotEmpty,
+    /// the file has the read-only attribute
+    ReadOnly,
+    /// the name is neither a valid short name nor a valid long name
+    InvalidName,
+    /// no free cluster or directory entry left
+    NoSpace,
+    /// there is a partition table, but no partition in it
+    NoPartition,
+    /// none of the partitions is supported, contains the type byte of the first one
+    UnsupportedPartitionType(u8),
+    /// the boot sector belongs to no supported file system
+    UnsupportedFileSystem,
+    /// the device is too small for the file system to be formatted
+    VolumeTooSmall,
+    /// the file is open for appending, see Fat32DeviceDriver::append
+    InUse,
+}
+
+/// reads number blocks into a new vector, for everything except the hot path
+/// blocks behind the end of the device are OutOfRange
+pub fn read_blocks<D>(block_device: &D,
+                      offset: usize,
+                      number: usize)
+                      -> Result<Vec<u8>, StorageError>
+    where D: BlockDevice + ?Sized,
+          D::Error: Into<StorageError>
+{
+    if offset + number > block_device.number_of_blocks() {
+        return Err(StorageError::OutOfRange);
+    }
+    let mut blocks = Vec::new();
+    blocks.resize(number * block_device.block_size(), 0);
+    block_device.read(offset, &mut blocks).map_err(Into::into)?;
+    Ok(blocks)
+}
@@ -0,0 +1,558 @@
+use block_device::BlockDevice;
+use super::attributes::{self, Attributes};
+use super::directory_entry::DirectoryEntry;
+use super::error::{self, StorageError};
+use super::fat_cache::FatCache;
+use super::get_bytes::*;
+use collections::vec::*;
+use collections::string::*;
+use core::cmp;
+use core::usize;
+
+const FILE_SYSTEM_NAME_OFFSET: usize = 0x03; //8
+const FAT_OFFSET_OFFSET: usize = 0x50;
+const CLUSTER_HEAP_OFFSET_OFFSET: usize = 0x58;
+const CLUSTER_COUNT_OFFSET: usize = 0x5C;
+const FIRST_CLUSTER_OF_ROOT_DIRECTORY_OFFSET: usize = 0x60;
+const BYTES_PER_SECTOR_SHIFT_OFFSET: usize = 0x6C;
+const SECTORS_PER_CLUSTER_SHIFT_OFFSET: usize = 0x6D;
+
+//entry types, the upper bit is set for entries in use
+const ENTRY_TYPE_END_OF_DIRECTORY: u8 = 0x00;
+const ENTRY_TYPE_ALLOCATION_BITMAP: u8 = 0x81;
+const ENTRY_TYPE_UP_CASE_TABLE: u8 = 0x82;
+const ENTRY_TYPE_VOLUME_LABEL: u8 = 0x83;
+const ENTRY_TYPE_FILE: u8 = 0x85;
+const ENTRY_TYPE_STREAM_EXTENSION: u8 = 0xC0;
+const ENTRY_TYPE_FILE_NAME: u8 = 0xC1;
+
+//file entry
+const SECONDARY_COUNT_OFFSET: usize = 1; //1
+const SET_CHECKSUM_OFFSET: usize = 2; //2
+const FILE_ATTRIBUTES_OFFSET: usize = 4; //2
+//stream extension entry
+const GENERAL_SECONDARY_FLAGS_OFFSET: usize = 1; //1
+const NAME_LENGTH_OFFSET: usize = 3; //1
+const NAME_HASH_OFFSET: usize = 4; //2
+const VALID_DATA_LENGTH_OFFSET: usize = 8; //8
+//stream extension, allocation bitmap and up-case table entry
+const FIRST_CLUSTER_OFFSET: usize = 20; //4
+const DATA_LENGTH_OFFSET: usize = 24; //8
+//up-case table entry
+const TABLE_CHECKSUM_OFFSET: usize = 4; //4
+//file name and volume label entry
+const FILE_NAME_OFFSET: usize = 2; //30
+const CHARACTER_COUNT_OFFSET: usize = 1; //1
+const VOLUME_LABEL_OFFSET: usize = 2; //22
+
+const NO_FAT_CHAIN: u8 = 0x02;
+//lookups of neighbouring clusters hit the same block of the allocation bitmap
+const BITMAP_CACHE_SIZE: usize = 1;
+const FAT_END_OF_CHAIN: usize = 0xFFFFFFFF;
+const FAT_BAD_CLUSTER: usize = 0xFFFFFFF7;
+
+/// location of the data of a file, a directory or a system structure
+#[derive(Debug, Clone, Copy)]
+struct Stream {
+    first_cluster: usize,
+    // None: until the end of the cluster chain
+    data_length: Option<u64>,
+    valid_data_length: u64,
+    no_fat_chain: bool,
+}
+
+/// a file or directory entry together with its stream extension and file name entries
+struct EntrySet {
+    name: Vec<u16>,
+    name_hash: u16,
+    attributes: Attributes,
+    stream: Stream,
+}
+
+impl EntrySet {
+    /// the file size is the data length, bytes behind the valid data length read as zero
+    fn to_directory_entry(&self) -> DirectoryEntry {
+        let data_length = self.stream.data_length.unwrap_or(0);
+        DirectoryEntry::from_parts(String::from_utf16_lossy(&self.name),
+                                   self.attributes,
+                                   self.stream.first_cluster,
+                                   cmp::min(data_length, usize::MAX as u64) as usize)
+    }
+}
+
+/// characters, whose upper case differs, sorted by character
+/// everything else maps to itself
+struct UpCaseTable {
+    mappings: Vec<(u16, u16)>,
+}
+
+impl UpCaseTable {
+    /// table as it is stored on the volume: a run of identical mappings
+    /// is compressed to 0xFFFF and its length
+    fn new(table: &[u8]) -> UpCaseTable {
+        let mut mappings = Vec::new();
+        let mut character: u32 = 0;
+        let mut i = 0;
+        while i + 1 < table.len() && character <= 0xFFFF {
+            let value = two_bytes_at_offset(table, i);
+            if value == 0xFFFF && i + 3 < table.len() {
+                character += two_bytes_at_offset(table, i + 2) as u32;
+                i += 4;
+                continue;
+            }
+            if value as u32 != character {
+                mappings.push((character as u16, value));
+            }
+            character += 1;
+            i += 2;
+        }
+        UpCaseTable { mappings: mappings }
+    }
+
+    /// used if the volume has no valid table: only a-z are mapped
+    fn ascii() -> UpCaseTable {
+        let mut mappings = Vec::with_capacity(26);
+        for c in b'a'..b'z' + 1 {
+            mappings.push((c as u16, (c - b'a' + b'A') as u16));
+        }
+        UpCaseTable { mappings: mappings }
+    }
+
+    fn up_case(&self, character: u16) -> u16 {
+        match self.mappings.binary_search_by_key(&character, |&(c, _)| c) {
+            Ok(i) => self.mappings[i].1,
+            Err(_) => character,
+        }
+    }
+
+    fn equal(&self, first: &[u16], second: &[u16]) -> bool {
+        first.len() == second.len() &&
+        first.iter().zip(second.iter()).all(|(a, b)| self.up_case(*a) == self.up_case(*b))
+    }
+}
+
+/// read-only driver for exFAT, the file system of SDXC cards
+pub struct ExFatDeviceDriver<'a> {
+    block_device: &'a BlockDevice<Error = StorageError>,
+    block_size_cluster: usize,
+    fat_block_offset: usize,
+    cluster_heap_block_offset: usize,
+    number_of_clusters: usize,
+    root_directory: Stream,
+    // the clusters of the allocation bitmap, read once when mounting
+    allocation_bitmap: Option<Vec<usize>>,
+    bitmap_cache: FatCache,
+    up_case_table: UpCaseTable,
+    volume_label: Option<String>,
+}
+
+impl<'a> ExFatDeviceDriver<'a> {
+    /// Partition::get_partition_type() == 0x07 and the boot sector have to be checked before
+    /// mount::mount(...) does that
+    pub fn new(block_device: &'a BlockDevice<Error = StorageError>)
+               -> Result<ExFatDeviceDriver<'a>, StorageError> {
+        if !(block_device.block_size() >= 512 && block_device.block_size() % 512 == 0) {
+            return Err(StorageError::InvalidBlockSize);
+        }
+        let block = error::read_blocks(block_device, 0, 1)?;
+        if &block[FILE_SYSTEM_NAME_OFFSET..FILE_SYSTEM_NAME_OFFSET + 8] != b"EXFAT   " {
+            return Err(StorageError::BadSignature);
+        }
+
+        //512 to 4096 bytes per sector, at most 32 MiB per cluster
+        let bytes_per_sector_shift = block[BYTES_PER_SECTOR_SHIFT_OFFSET] as usize;
+        let sectors_per_cluster_shift = block[SECTORS_PER_CLUSTER_SHIFT_OFFSET] as usize;
+        if bytes_per_sector_shift < 9 || bytes_per_sector_shift > 12 ||
+           bytes_per_sector_shift + sectors_per_cluster_shift > 25 {
+            return Err(StorageError::BadSignature);
+        }
+        let byte_per_sector = 1 << bytes_per_sector_shift;
+        if !(byte_per_sector % block_device.block_size() == 0) {
+            return Err(StorageError::InvalidBlockSize);
+        }
+        let block_size_sector = byte_per_sector / block_device.block_size();
+        let block_size_cluster = (1 << sectors_per_cluster_shift) * block_size_sector;
+
+        let mut driver = ExFatDeviceDriver {
+            block_device: block_device,
+            block_size_cluster: block_size_cluster,
+            fat_block_offset: four_bytes_at_offset(&block, FAT_OFFSET_OFFSET) as usize *
+                              block_size_sector,
+            cluster_heap_block_offset: four_bytes_at_offset(&block, CLUSTER_HEAP_OFFSET_OFFSET) as
+                                       usize * block_size_sector,
+            number_of_clusters: four_bytes_at_offset(&block, CLUSTER_COUNT_OFFSET) as usize,
+            root_directory: Stream {
+                first_cluster: four_bytes_at_offset(&block,
+                                                    FIRST_CLUSTER_OF_ROOT_DIRECTORY_OFFSET) as
+                               usize,
+                data_length: None,
+                valid_data_length: 0,
+                no_fat_chain: false,
+            },
+            allocation_bitmap: None,
+            bitmap_cache: FatCache::new(BITMAP_CACHE_SIZE),
+            up_case_table: UpCaseTable::ascii(),
+            volume_label: None,
+        };
+        driver.read_system_entries()?;
+        Ok(driver)
+    }
+
+    /// the allocation bitmap, the up-case table and the volume label
+    /// are stored as special entries of the root directory
+    fn read_system_entries(&mut self) -> Result<(), StorageError> {
+        let root = self.read_stream(&self.root_directory)?;
+        for directory_entry in root.chunks(32) {
+            match directory_entry[0] {
+                ENTRY_TYPE_END_OF_DIRECTORY => break,
+                ENTRY_TYPE_ALLOCATION_BITMAP => {
+                    // the first one belongs to the first FAT
+                    if self.allocation_bitmap.is_none() {
+                        let chain = self.cluster_chain(&system_stream(directory_entry))?;
+                        self.allocation_bitmap = Some(chain);
+                    }
+                }
+                ENTRY_TYPE_UP_CASE_TABLE => {
+                    let table = self.read_stream(&system_stream(directory_entry))?;
+                    let checksum = four_bytes_at_offset(directory_entry, TABLE_CHECKSUM_OFFSET);
+                    if table_checksum(&table) == checksum {
+                        self.up_case_table = UpCaseTable::new(&table);
+                    }
+                }
+                ENTRY_TYPE_VOLUME_LABEL => {
+                    let count = cmp::min(directory_entry[CHARACTER_COUNT_OFFSET] as usize, 11);
+                    let mut label = Vec::with_capacity(count);
+                    for i in 0..count {
+                        label.push(two_bytes_at_offset(directory_entry,
+                                                       VOLUME_LABEL_OFFSET + i * 2));
+                    }
+                    self.volume_label = Some(String::from_utf16_lossy(&label));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    pub fn volume_label(&self) -> Option<&String> {
+        self.volume_label.as_ref()
+    }
+
+    /// size of a cluster in bytes
+    pub fn cluster_size(&self) -> usize {
+        self.block_size_cluster * self.block_device.block_size()
+    }
+
+    /// path is separated by "/", e.g. "/logs/2026/run01.bin"
+    /// names are compared case insensitive, using the up-case table of the volume
+    // sdram
+    pub fn read_file_to_vec(&self, path: &str) -> Result<Vec<u8>, StorageError> {
+        let set = self.find_entry_set(path)?;
+        if set.attributes.contains(attributes::DIRECTORY) {
+            return Err(StorageError::IsADirectory);
+        }
+        self.read_stream(&set.stream)
+    }
+
+    /// the root directory itself has no entry, it is not found
+    pub fn lookup(&self, path: &str) -> Result<DirectoryEntry, StorageError> {
+        self.find_entry_set(path).map(|set| set.to_directory_entry())
+    }
+
+    /// lists the directory at path, "" and "/" are the root directory
+    pub fn read_dir(&self, path: &str) -> Result<IntoIter<DirectoryEntry>, StorageError> {
+        let stream = self.directory_stream(path)?;
+        let directory = self.read_stream(&stream)?;
+        let entries: Vec<DirectoryEntry> = entry_sets(&directory)
+            .iter()
+            .map(|set| set.to_directory_entry())
+            .collect();
+        Ok(entries.into_iter())
+    }
+
+    /// clusters outside the cluster heap count as allocated
+    /// only the block of the bitmap with the bit of cluster is read, if it isn't cached
+    pub fn is_cluster_allocated(&self, cluster: usize) -> Result<bool, StorageError> {
+        let bitmap = match self.allocation_bitmap {
+            Some(ref b) => b,
+            None => return Ok(true),
+        };
+        if cluster < 2 || cluster >= self.number_of_clusters + 2 {
+            return Ok(true);
+        }
+        let bit = cluster - 2;
+        let byte_offset = bit / 8;
+        let cluster_size = self.cluster_size();
+        let block_size = self.block_device.block_size();
+        let bitmap_cluster = match bitmap.get(byte_offset / cluster_size) {
+            Some(c) => *c,
+            None => return Err(StorageError::CorruptChain),
+        };
+        let block_number = self.cluster_block(bitmap_cluster) +
+                           byte_offset % cluster_size / block_size;
+        self.bitmap_cache.read(self.block_device, block_number, |block| {
+            block[byte_offset % block_size] & (1 << (bit % 8)) != 0
+        })
+    }
+
+    /// counts the clear bits of the allocation bitmap
+    pub fn free_clusters(&self) -> Result<usize, StorageError> {
+        let bitmap = match self.allocation_bitmap {
+            Some(ref b) => b,
+            None => return Ok(0),
+        };
+        let mut free = 0;
+        let mut bit = 0;
+        for cluster in bitmap {
+            for byte in self.read_cluster(*cluster)? {
+                for i in 0..8 {
+                    if bit < self.number_of_clusters && byte & (1 << i) == 0 {
+                        free += 1;
+                    }
+                    bit += 1;
+                }
+            }
+        }
+        Ok(free)
+    }
+
+    fn find_entry_set(&self, path: &str) -> Result<EntrySet, StorageError> {
+        let path = path.trim_right_matches('/');
+        let (parent, name) = match path.rfind('/') {
+            Some(i) => (&path[..i], &path[i + 1..]),
+            None => ("", path),
+        };
+        if name.is_empty() {
+            return Err(StorageError::NotFound);
+        }
+        let stream = self.directory_stream(parent)?;
+        self.find_in_directory(&stream, name)
+    }
+
+    /// stream of the directory at path
+    fn directory_stream(&self, path: &str) -> Result<Stream, StorageError> {
+        let mut stream = self.root_directory;
+        for name in path.split('/').filter(|c| !c.is_empty()) {
+            let set = self.find_in_directory(&stream, name)?;
+            if !set.attributes.contains(attributes::DIRECTORY) {
+                return Err(StorageError::NotADirectory);
+            }
+            stream = set.stream;
+        }
+        Ok(stream)
+    }
+
+    /// the name hash of the stream extension rules out most entries before comparing
+    fn find_in_directory(&self, stream: &Stream, name: &str) -> Result<EntrySet, StorageError> {
+        let name: Vec<u16> = name.encode_utf16().collect();
+        let up_case_name: Vec<u16> = name.iter().map(|c| self.up_case_table.up_case(*c)).collect();
+        let hash = name_hash(&up_case_name);
+        let directory = self.read_stream(stream)?;
+        entry_sets(&directory)
+            .into_iter()
+            .find(|set| set.name_hash == hash && self.up_case_table.equal(&set.name, &name))
+            .ok_or(StorageError::NotFound)
+    }
+
+    /// reads the data of a stream, bytes behind the valid data length are zero
+    // sdram
+    fn read_stream(&self, stream: &Stream) -> Result<Vec<u8>, StorageError> {
+        let mut all = Vec::new();
+        for cluster in self.cluster_chain(stream)? {
+            all.append(&mut self.read_cluster(cluster)?);
+        }
+        if let Some(data_length) = stream.data_length {
+            all.truncate(data_length as usize);
+            let valid = cmp::min(stream.valid_data_length as usize, all.len());
+            for byte in &mut all[valid..] {
+                *byte = 0;
+            }
+        }
+        Ok(all)
+    }
+
+    /// streams flagged with NoFatChain are contiguous and the FAT is not maintained for them
+    /// a chain shorter than the data length of the stream is corrupt
+    fn cluster_chain(&self, stream: &Stream) -> Result<Vec<usize>, StorageError> {
+        let cluster_size = self.cluster_size() as u64;
+        let number = stream.data_length
+            .map(|length| ((length + cluster_size - 1) / cluster_size) as usize);
+        let mut chain = Vec::new();
+        if number == Some(0) {
+            return Ok(chain);
+        }
+        if stream.first_cluster < 2 || stream.first_cluster >= self.number_of_clusters + 2 {
+            return Err(StorageError::CorruptChain);
+        }
+        if stream.no_fat_chain {
+            let number = number.unwrap_or(0);
+            if stream.first_cluster + number > self.number_of_clusters + 2 {
+                return Err(StorageError::CorruptChain);
+            }
+            for i in 0..number {
+                chain.push(stream.first_cluster + i);
+            }
+            return Ok(chain);
+        }
+
+        let mut current = stream.first_cluster;
+        while number.map(|n| chain.len() < n).unwrap_or(true) {
+            chain.push(current);
+            // a looping chain would never end
+            if chain.len() > self.number_of_clusters {
+                return Err(StorageError::CorruptChain);
+            }
+            current = self.read_in_fat(current)?;
+            if current == FAT_END_OF_CHAIN {
+                break;
+            }
+            if current == FAT_BAD_CLUSTER || current < 2 ||
+               current >= self.number_of_clusters + 2 {
+                return Err(StorageError::CorruptChain);
+            }
+        }
+        if number.map(|n| chain.len() < n).unwrap_or(false) {
+            return Err(StorageError::CorruptChain);
+        }
+        Ok(chain)
+    }
+
+    // buffer
+    fn read_in_fat(&self, cluster: usize) -> Result<usize, StorageError> {
+        let block_size = self.block_device.block_size();
+        //4: byte-size of u32
+        let block = error::read_blocks(self.block_device,
+                                       self.fat_block_offset + cluster * 4 / block_size,
+                                       1)?;
+        Ok(four_bytes_at_offset(&block, cluster * 4 % block_size) as usize)
+    }
+
+    // sdram
+    fn read_cluster(&self, cluster: usize) -> Result<Vec<u8>, StorageError> {
+        if cluster < 2 || cluster >= self.number_of_clusters + 2 {
+            return Err(StorageError::OutOfRange);
+        }
+        error::read_blocks(self.block_device,
+                           self.cluster_block(cluster),
+                           self.block_size_cluster)
+    }
+
+    /// the first block of cluster in the cluster heap
+    fn cluster_block(&self, cluster: usize) -> usize {
+        //- 2 because the first two FAT entries are reserved
+        self.cluster_heap_block_offset + (cluster - 2) * self.block_size_cluster
+    }
+}
+
+/// stream of the allocation bitmap or the up-case table
+fn system_stream(directory_entry: &[u8]) -> Stream {
+    let data_length = eight_bytes_at_offset(directory_entry, DATA_LENGTH_OFFSET);
+    Stream {
+        first_cluster: four_bytes_at_offset(directory_entry, FIRST_CLUSTER_OFFSET) as usize,
+        data_length: Some(data_length),
+        valid_data_length: data_length,
+        no_fat_chain: false,
+    }
+}
+
+/// collects the file and directory entry sets of a directory
+/// sets with a wrong checksum or missing secondary entries are skipped
+fn entry_sets(directory: &[u8]) -> Vec<EntrySet> {
+    let mut sets = Vec::new();
+    let number = directory.len() / 32;
+    let mut i = 0;
+    while i < number {
+        let directory_entry = &directory[i * 32..(i + 1) * 32];
+        if directory_entry[0] == ENTRY_TYPE_END_OF_DIRECTORY {
+            break;
+        }
+        if directory_entry[0] != ENTRY_TYPE_FILE {
+            i += 1;
+            continue;
+        }
+
+        let secondary_count = directory_entry[SECONDARY_COUNT_OFFSET] as usize;
+        if secondary_count < 2 || i + secondary_count >= number {
+            i += 1;
+            continue;
+        }
+        let set = &directory[i * 32..(i + 1 + secondary_count) * 32];
+        i += 1 + secondary_count;
+        if entry_set_checksum(set) != two_bytes_at_offset(set, SET_CHECKSUM_OFFSET) {
+            continue;
+        }
+        if let Some(entry_set) = parse_entry_set(set) {
+            sets.push(entry_set);
+        }
+    }
+    sets
+}
+
+fn parse_entry_set(set: &[u8]) -> Option<EntrySet> {
+    let stream_extension = &set[32..64];
+    if stream_extension[0] != ENTRY_TYPE_STREAM_EXTENSION {
+        return None;
+    }
+    let flags = stream_extension[GENERAL_SECONDARY_FLAGS_OFFSET];
+    let name_length = stream_extension[NAME_LENGTH_OFFSET] as usize;
+    let stream = Stream {
+        first_cluster: four_bytes_at_offset(stream_extension, FIRST_CLUSTER_OFFSET) as usize,
+        data_length: Some(eight_bytes_at_offset(stream_extension, DATA_LENGTH_OFFSET)),
+        valid_data_length: eight_bytes_at_offset(stream_extension, VALID_DATA_LENGTH_OFFSET),
+        no_fat_chain: flags & NO_FAT_CHAIN != 0,
+    };
+
+    // 15 characters per file name entry
+    let mut name = Vec::with_capacity(name_length);
+    for file_name in set[64..].chunks(32) {
+        if file_name[0] != ENTRY_TYPE_FILE_NAME {
+            break;
+        }
+        for i in 0..15 {
+            if name.len() < name_length {
+                name.push(two_bytes_at_offset(file_name, FILE_NAME_OFFSET + i * 2));
+            }
+        }
+    }
+    if name.len() != name_length {
+        return None;
+    }
+
+    // the upper byte is reserved
+    let attributes = two_bytes_at_offset(set, FILE_ATTRIBUTES_OFFSET) as u8;
+    Some(EntrySet {
+        name: name,
+        name_hash: two_bytes_at_offset(stream_extension, NAME_HASH_OFFSET),
+        attributes: Attributes::from_bits_truncate(attributes),
+        stream: stream,
+    })
+}
+
+/// over all entries of a set, without the checksum field itself
+fn entry_set_checksum(set: &[u8]) -> u16 {
+    let mut checksum: u16 = 0;
+    for (i, byte) in set.iter().enumerate() {
+        if i == SET_CHECKSUM_OFFSET || i == SET_CHECKSUM_OFFSET + 1 {
+            continue;
+        }
+        checksum = (checksum >> 1 | checksum << 15).wrapping_add(*byte as u16);
+    }
+    checksum
+}
+
+fn table_checksum(table: &[u8]) -> u32 {
+    let mut checksum: u32 = 0;
+    for byte in table {
+        checksum = (checksum >> 1 | checksum << 31).wrapping_add(*byte as u32);
+    }
+    checksum
+}
+
+fn name_hash(up_case_name: &[u16]) -> u16 {
+    let mut hash: u16 = 0;
+    for character in up_case_name {
+        hash = (hash >> 1 | hash << 15).wrapping_add(*character & 0xFF);
+        hash = (hash >> 1 | hash << 15).wrapping_add(*character >> 8);
+    }
+    hash
+}
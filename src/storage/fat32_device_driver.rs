@@ -1,7 +1,16 @@
 use block_device::BlockDevice;
-use super::directory_entry::DirectoryEntry;
+use super::attributes;
+use super::append_writer::AppendWriter;
+use super::date_time::{DateTime, TimeSource};
+use super::directory_entry::*;
+use super::error::{self, StorageError};
+use super::fat_cache::FatCache;
+use super::file::File;
+use super::read_dir::ReadDir;
 use super::get_bytes::*;
 use collections::vec::*;
+use core::cell::{Cell, RefCell};
+use core::cmp;
 use core::option::*;
 
 //number of used bytes?!!
@@ -9,8 +18,48 @@ const BYTE_PER_SECTOR_OFFSET: usize = 0x0B;
 const SECTORS_PER_CLUSTER_OFFSET: usize = 0x0D;
 const NUMBER_OF_RESERVED_SECTORS_OFFSET: usize = 0x0E;
 const NUMBER_OF_FATS_OFFSET: usize = 0x010;
+const NUMBER_OF_ROOT_DIRECTORY_ENTRIES_OFFSET: usize = 0x011;
+const TOTAL_SECTORS_16_OFFSET: usize = 0x013;
+const NUMBER_OF_SECTORS_PER_FAT_16_OFFSET: usize = 0x016;
+const TOTAL_SECTORS_32_OFFSET: usize = 0x020;
 const NUMBER_OF_SECTORS_PER_FAT_OFFSET: usize = 0x024;
 const CLUSTER_NUMBER_ROOT_DIRECTORY_OFFSET: usize = 0x02C;
+const FS_INFO_SECTOR_OFFSET: usize = 0x030;
+const SIGNATURE_OFFSET: usize = 0x1FE;
+
+//FSInfo sector, FAT32 only
+const FS_INFO_LEAD_SIGNATURE_OFFSET: usize = 0x000;
+const FS_INFO_STRUCT_SIGNATURE_OFFSET: usize = 0x1E4;
+const FS_INFO_FREE_COUNT_OFFSET: usize = 0x1E8;
+const FS_INFO_NEXT_FREE_OFFSET: usize = 0x1EC;
+const FS_INFO_TRAIL_SIGNATURE_OFFSET: usize = 0x1FC;
+const FS_INFO_LEAD_SIGNATURE: u32 = 0x41615252;
+const FS_INFO_STRUCT_SIGNATURE: u32 = 0x61417272;
+const FS_INFO_TRAIL_SIGNATURE: u32 = 0xAA550000;
+//free count and next free hint are not known
+const FS_INFO_UNKNOWN: u32 = 0xFFFFFFFF;
+//"~999999" leaves one character of the name
+const MAX_SHORT_NAME_NUMBER: usize = 999999;
+
+const FAT_ENTRY_MASK: u32 = 0x0FFFFFFF;
+pub const END_OF_CHAIN: usize = 0x0FFFFFFF;
+//everything from here on marks the end of a chain
+const MIN_END_OF_CHAIN: usize = 0x0FFFFFF8;
+//number of FAT blocks kept in memory
+const FAT_CACHE_SIZE: usize = 4;
+//volumes with less clusters are FAT12 respectively FAT16
+const MAX_CLUSTERS_FAT12: usize = 4084;
+const MAX_CLUSTERS_FAT16: usize = 65524;
+//the first two FAT entries are reserved
+const FIRST_DATA_CLUSTER: usize = 2;
+
+/// the width of the FAT entries, determined by the number of clusters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
 
 /*
 dbg:
@@ -20,21 +69,79 @@ in the file:
 (2048 + 4022) * 512 = 3107840 :first cluster (data)
 */
 
+/// number of free clusters and where the next allocation starts looking,
+/// see Fat32DeviceDriver::free_space
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FreeSpace {
+    free_clusters: usize,
+    next_free_cluster: usize,
+    cluster_size: usize,
+}
+
+impl FreeSpace {
+    pub fn free_clusters(&self) -> usize {
+        self.free_clusters
+    }
+
+    /// only a hint, the cluster is not necessarily free
+    pub fn next_free_cluster(&self) -> usize {
+        self.next_free_cluster
+    }
+
+    pub fn free_bytes(&self) -> u64 {
+        self.free_clusters as u64 * self.cluster_size as u64
+    }
+}
+
+/// where a directory entry is stored, see Fat32DeviceDriver::find_entry
+struct EntryLocation {
+    // first cluster of the directory
+    directory_cluster: usize,
+    // index of the first long name entry, index itself without a long name
+    first_index: usize,
+    index: usize,
+}
+
+/// despite its name it drives FAT12 and FAT16 volumes too
+/// their root directory is a fixed region in front of the data region,
+/// it is addressed as cluster 0
 pub struct Fat32DeviceDriver<'a> {
-    block_device: &'a BlockDevice,
+    block_device: &'a BlockDevice<Error = StorageError>,
+    fat_type: FatType,
     block_size_cluster: usize,
     number_of_reserved_blocks: usize,
+    number_of_fats: usize,
+    number_of_blocks_per_fat: usize,
     data_region_block_offset: usize,
     root_directory_cluster_offset: usize,
+    root_directory_block_offset: usize,
+    number_of_root_directory_blocks: usize,
+    number_of_clusters: usize,
+    fat_cache: FatCache,
+    // None: there is no valid FSInfo sector, e.g. on FAT12/16
+    fs_info_block_offset: Option<usize>,
+    // None: unknown, the FAT has to be counted
+    free_cluster_count: Cell<Option<usize>>,
+    next_free_cluster: Cell<usize>,
+    // None: new and modified entries get no timestamps
+    time_source: Option<&'a TimeSource>,
+    // (directory cluster, index) of the entries of the files open for appending
+    open_appends: RefCell<Vec<(usize, usize)>>,
 }
 
 impl<'a> Fat32DeviceDriver<'a> {
-    /// Partition::get_partition_type() == 0x0B has to be checked before
-    pub fn new(block_device: &'a BlockDevice) -> Fat32DeviceDriver<'a> {
+    /// Partition::get_partition_type() has to be checked before,
+    /// 0x01 (FAT12), 0x04, 0x06, 0x0E (FAT16), 0x0B or 0x0C (FAT32)
+    /// mount::mount(...) does that
+    pub fn new(block_device: &'a BlockDevice<Error = StorageError>)
+               -> Result<Fat32DeviceDriver<'a>, StorageError> {
         if !(block_device.block_size() >= 512 && block_device.block_size() % 512 == 0) {
-            panic!("wrong block_size");
+            return Err(StorageError::InvalidBlockSize);
+        }
+        let block = error::read_blocks(block_device, 0, 1)?;
+        if two_bytes_at_offset(&block, SIGNATURE_OFFSET) != 0xAA55 {
+            return Err(StorageError::BadSignature);
         }
-        let block = block_device.read_blocks(0, 1);
 
         let byte_per_sector = two_bytes_at_offset(&block, BYTE_PER_SECTOR_OFFSET) as usize;
         let sectors_per_cluster = block[SECTORS_PER_CLUSTER_OFFSET] as usize;
@@ -43,95 +150,1187 @@ impl<'a> Fat32DeviceDriver<'a> {
         let cluster_number_root_directory =
             four_bytes_at_offset(&block, CLUSTER_NUMBER_ROOT_DIRECTORY_OFFSET) as usize;
 
-        if !(byte_per_sector % 512 == 0) {
-            panic!("wrong byte_per_sector");
+        if byte_per_sector == 0 || byte_per_sector % block_device.block_size() != 0 ||
+           sectors_per_cluster == 0 {
+            return Err(StorageError::BadSignature);
         }
         let block_size_sector = byte_per_sector / block_device.block_size();
         let block_size_cluster = sectors_per_cluster * block_size_sector;
-        let number_of_reserved_blocks = number_of_reserved_sectors / block_size_sector;
+        let number_of_reserved_blocks = number_of_reserved_sectors * block_size_sector;
         let number_of_fats = block[NUMBER_OF_FATS_OFFSET] as usize;
-        let number_of_sectors_per_fat =
-            four_bytes_at_offset(&block, NUMBER_OF_SECTORS_PER_FAT_OFFSET) as usize;
-        let data_region_block_offset = number_of_fats * number_of_sectors_per_fat *
-                                       block_size_sector +
-                                       number_of_reserved_blocks;
-        let root_directory_cluster_offset = cluster_number_root_directory;
-
-        Fat32DeviceDriver {
+        let number_of_sectors_per_fat_16 =
+            two_bytes_at_offset(&block, NUMBER_OF_SECTORS_PER_FAT_16_OFFSET) as usize;
+        let number_of_sectors_per_fat = if number_of_sectors_per_fat_16 != 0 {
+            number_of_sectors_per_fat_16
+        } else {
+            four_bytes_at_offset(&block, NUMBER_OF_SECTORS_PER_FAT_OFFSET) as usize
+        };
+        let number_of_blocks_per_fat = number_of_sectors_per_fat * block_size_sector;
+        // the boot sector itself is a reserved sector
+        if number_of_fats == 0 || number_of_blocks_per_fat == 0 ||
+           number_of_reserved_sectors == 0 {
+            return Err(StorageError::BadSignature);
+        }
+
+        //FAT32: 0
+        let number_of_root_directory_entries =
+            two_bytes_at_offset(&block, NUMBER_OF_ROOT_DIRECTORY_ENTRIES_OFFSET) as usize;
+        let number_of_root_directory_blocks = (number_of_root_directory_entries * 32 +
+                                               block_device.block_size() -
+                                               1) / block_device.block_size();
+        let root_directory_block_offset = number_of_fats * number_of_blocks_per_fat +
+                                          number_of_reserved_blocks;
+        let data_region_block_offset = root_directory_block_offset +
+                                       number_of_root_directory_blocks;
+
+        let mut total_sectors = two_bytes_at_offset(&block, TOTAL_SECTORS_16_OFFSET) as usize;
+        if total_sectors == 0 {
+            total_sectors = four_bytes_at_offset(&block, TOTAL_SECTORS_32_OFFSET) as usize;
+        }
+        if total_sectors * block_size_sector <= data_region_block_offset {
+            return Err(StorageError::BadSignature);
+        }
+        let number_of_clusters = (total_sectors * block_size_sector - data_region_block_offset) /
+                                 block_size_cluster;
+
+        // the number of clusters alone decides, except for small FAT32 volumes: their boot
+        // sector has no FAT16 fields, so they can't be anything else
+        let fat_type = if number_of_sectors_per_fat_16 == 0 &&
+                          number_of_root_directory_entries == 0 {
+            FatType::Fat32
+        } else if number_of_clusters <= MAX_CLUSTERS_FAT12 {
+            FatType::Fat12
+        } else if number_of_clusters <= MAX_CLUSTERS_FAT16 {
+            FatType::Fat16
+        } else {
+            FatType::Fat32
+        };
+        // clusters without an entry in the FAT can't be used, their entries would be
+        // written into the next FAT copy, the root directory or the data region
+        let bits_per_entry = match fat_type {
+            FatType::Fat12 => 12,
+            FatType::Fat16 => 16,
+            FatType::Fat32 => 32,
+        };
+        let number_of_entries = number_of_blocks_per_fat * block_device.block_size() * 8 /
+                                bits_per_entry;
+        let number_of_clusters = cmp::min(number_of_clusters,
+                                          number_of_entries - FIRST_DATA_CLUSTER);
+        let root_directory_cluster_offset = match fat_type {
+            FatType::Fat32 => cluster_number_root_directory,
+            _ => 0,
+        };
+
+        // a damaged FSInfo sector is ignored, it only holds hints
+        let mut fs_info_block_offset = None;
+        let mut free_cluster_count = None;
+        let mut next_free_cluster = FIRST_DATA_CLUSTER;
+        let fs_info_sector = two_bytes_at_offset(&block, FS_INFO_SECTOR_OFFSET) as usize;
+        if fat_type == FatType::Fat32 && fs_info_sector != 0 &&
+           fs_info_sector < number_of_reserved_sectors {
+            let fs_info = error::read_blocks(block_device, fs_info_sector * block_size_sector, 1)?;
+            if is_fs_info(&fs_info) {
+                fs_info_block_offset = Some(fs_info_sector * block_size_sector);
+                let free_count = four_bytes_at_offset(&fs_info, FS_INFO_FREE_COUNT_OFFSET);
+                if free_count != FS_INFO_UNKNOWN && (free_count as usize) <= number_of_clusters {
+                    free_cluster_count = Some(free_count as usize);
+                }
+                let next_free = four_bytes_at_offset(&fs_info, FS_INFO_NEXT_FREE_OFFSET) as usize;
+                if next_free >= FIRST_DATA_CLUSTER &&
+                   next_free < number_of_clusters + FIRST_DATA_CLUSTER {
+                    next_free_cluster = next_free;
+                }
+            }
+        }
+
+        Ok(Fat32DeviceDriver {
             block_device: block_device,
+            fat_type: fat_type,
             block_size_cluster: block_size_cluster,
             number_of_reserved_blocks: number_of_reserved_blocks,
+            number_of_fats: number_of_fats,
+            number_of_blocks_per_fat: number_of_blocks_per_fat,
             data_region_block_offset: data_region_block_offset,
             root_directory_cluster_offset: root_directory_cluster_offset,
-        }
+            root_directory_block_offset: root_directory_block_offset,
+            number_of_root_directory_blocks: number_of_root_directory_blocks,
+            number_of_clusters: number_of_clusters,
+            fat_cache: FatCache::new(FAT_CACHE_SIZE),
+            fs_info_block_offset: fs_info_block_offset,
+            free_cluster_count: Cell::new(free_cluster_count),
+            next_free_cluster: Cell::new(next_free_cluster),
+            time_source: None,
+            open_appends: RefCell::new(Vec::new()),
+        })
+    }
+
+    pub fn fat_type(&self) -> FatType {
+        self.fat_type
     }
 
-    /// only short name
-    /// only in root directory
+    /// stamps the entries created or modified from now on, e.g. with the RTC
+    pub fn set_time_source(&mut self, time_source: &'a TimeSource) {
+        self.time_source = Some(time_source);
+    }
+
+    /// the time of the time source, None without one
+    pub fn now(&self) -> Option<DateTime> {
+        self.time_source.map(|time_source| time_source.now())
+    }
+
+    /// everything written so far is on the card, e.g. behind a cache
+    /// the driver flushes itself wherever the order of two writes matters,
+    /// a cache may reorder the writes in between, see BlockCache
+    pub fn flush(&self) -> Result<(), StorageError> {
+        self.block_device.flush()
+    }
+
+    /// the free count of the FSInfo sector, if it is valid
+    /// otherwise the FAT is counted once and the FSInfo sector is corrected
+    pub fn free_space(&self) -> Result<FreeSpace, StorageError> {
+        let free_clusters = match self.free_cluster_count.get() {
+            Some(free_clusters) => free_clusters,
+            None => {
+                let mut free_clusters = 0;
+                for cluster in FIRST_DATA_CLUSTER..self.number_of_clusters + FIRST_DATA_CLUSTER {
+                    if self.read_in_fat(cluster)? == 0 {
+                        free_clusters += 1;
+                    }
+                }
+                self.set_free_space(Some(free_clusters), self.next_free_cluster.get())?;
+                free_clusters
+            }
+        };
+        Ok(FreeSpace {
+            free_clusters: free_clusters,
+            next_free_cluster: self.next_free_cluster.get(),
+            cluster_size: self.cluster_size(),
+        })
+    }
+
+    /// keeps the values in memory and in the FSInfo sector
+    pub fn set_free_space(&self,
+                          free_cluster_count: Option<usize>,
+                          next_free_cluster: usize)
+                          -> Result<(), StorageError> {
+        self.free_cluster_count.set(free_cluster_count);
+        self.next_free_cluster.set(next_free_cluster);
+        let block_number = match self.fs_info_block_offset {
+            Some(b) => b,
+            None => return Ok(()),
+        };
+        let mut fs_info = error::read_blocks(self.block_device, block_number, 1)?;
+        set_four_bytes_at_offset(&mut fs_info,
+                                 FS_INFO_FREE_COUNT_OFFSET,
+                                 free_cluster_count.map(|c| c as u32).unwrap_or(FS_INFO_UNKNOWN));
+        set_four_bytes_at_offset(&mut fs_info, FS_INFO_NEXT_FREE_OFFSET, next_free_cluster as u32);
+        self.block_device.write(block_number, &fs_info)
+    }
+
+    /// path is separated by "/", e.g. "/logs/2026/run01.bin"
+    /// every component can be given by its short or its long name
     // sdram
-    pub fn read_file_to_vec(&self, name_extension: &str) -> Option<Vec<u8>> {
-        let opt_file = self.file_directory_entry(name_extension);
-        let file = match opt_file {
-            Some(f) => f,
-            None => return None,
+    pub fn read_file_to_vec(&self, path: &str) -> Result<Vec<u8>, StorageError> {
+        let file = self.lookup(path)?;
+        if !file.is_file() {
+            return Err(StorageError::IsADirectory);
+        }
+        let mut full = self.compile_clusters_begin_with_number(file.first_cluster())?;
+        if full.len() < file.file_size() {
+            return Err(StorageError::CorruptChain);
+        }
+        full.truncate(file.file_size());
+        Ok(full)
+    }
+
+    /// opens the file for reading in small steps, see File
+    pub fn open(&self, path: &str) -> Result<File, StorageError> {
+        let file = self.lookup(path)?;
+        if !file.is_file() {
+            return Err(StorageError::IsADirectory);
+        }
+        Ok(File::new(self, &file))
+    }
+
+    /// creates the file or overwrites it, if it already exists and is not read-only
+    /// the parent directory has to exist
+    /// a new name that is no valid short name is stored as long name, see new_name(...),
+    /// existing files are found by their long name as well
+    /// the old data is freed only after the entry points to the new one, a failed or
+    /// interrupted overwrite leaves the old file (and maybe a lost chain)
+    pub fn write_file(&self, path: &str, data: &[u8]) -> Result<(), StorageError> {
+        let (parent, name_extension) = split_path(path);
+        let directory_cluster = self.directory_cluster(parent)?;
+        let directory = self.read_directory(directory_cluster)?;
+        let (found, _) = search_directory(&directory, name_extension)?;
+        let is_new = found.is_none();
+
+        let (index, mut directory_entry, old_first_cluster, long_name) = match found {
+            Some((_, index, ref old)) => {
+                if !old.is_file() {
+                    return Err(StorageError::IsADirectory);
+                }
+                if old.is_read_only() {
+                    return Err(StorageError::ReadOnly);
+                }
+                self.check_not_appending(directory_cluster, index)?;
+                // a corrupt old chain is refused before anything is written
+                self.cluster_chain(old.first_cluster())?;
+                let mut directory_entry = [0; 32];
+                directory_entry.copy_from_slice(&directory[index * 32..(index + 1) * 32]);
+                // tells backup tools that the file changed
+                set_attributes(&mut directory_entry, old.attributes() | attributes::ARCHIVE);
+                (index, directory_entry, old.first_cluster(), Vec::new())
+            }
+            None => {
+                let (long_name, short) = new_name(&directory, name_extension)?;
+                // no free entries: the directory grows, a full fixed root directory
+                // of FAT12/16 fails here, before the data takes any clusters
+                let first_index =
+                    self.reserve_entries(directory_cluster, &directory, long_name.len() + 1)?;
+                (first_index + long_name.len(), new_file_entry(&short), 0, long_name)
+            }
         };
-        let mut full = self.compile_clusters_begin_with_number(file.first_cluster());
-        full.split_off(file.file_size());
-        Some(full)
+
+        let cluster_size = self.cluster_size();
+        let clusters = self.allocate_clusters((data.len() + cluster_size - 1) / cluster_size)?;
+        for (cluster, chunk) in clusters.iter().zip(data.chunks(cluster_size)) {
+            if chunk.len() == cluster_size {
+                self.write_cluster_data_region(*cluster, chunk)?;
+            } else {
+                let mut last = chunk.to_vec();
+                last.resize(cluster_size, 0);
+                self.write_cluster_data_region(*cluster, &last)?;
+            }
+        }
+
+        set_first_cluster(&mut directory_entry,
+                          clusters.first().cloned().unwrap_or(0));
+        set_file_size(&mut directory_entry, data.len());
+        if let Some(time_source) = self.time_source {
+            if is_new {
+                set_created(&mut directory_entry, &time_source.now());
+            } else {
+                set_modified(&mut directory_entry, &time_source.now());
+            }
+        }
+        // the new chain is on the card before the entry points to it,
+        // the entry before the old chain is freed
+        self.flush()?;
+        self.write_long_name(directory_cluster, index, &long_name)?;
+        self.write_directory_entry(directory_cluster, index, &directory_entry)?;
+        self.flush()?;
+        self.free_clusters(old_first_cluster)
+    }
+
+    /// opens the file for appending, it is created if it does not exist
+    /// the size in the directory entry is updated every checkpoint_interval bytes,
+    /// see AppendWriter
+    /// while the writer is open, the file can't be overwritten, moved, removed
+    /// or opened for appending again, that fails with InUse
+    pub fn append(&self,
+                  path: &str,
+                  checkpoint_interval: usize)
+                  -> Result<AppendWriter, StorageError> {
+        let (parent, name_extension) = split_path(path);
+        let directory_cluster = self.directory_cluster(parent)?;
+        let directory = self.read_directory(directory_cluster)?;
+        let (found, _) = search_directory(&directory, name_extension)?;
+
+        let (index, mut directory_entry) = match found {
+            Some((_, index, ref old)) => {
+                if !old.is_file() {
+                    return Err(StorageError::IsADirectory);
+                }
+                if old.is_read_only() {
+                    return Err(StorageError::ReadOnly);
+                }
+                self.check_not_appending(directory_cluster, index)?;
+                let mut directory_entry = [0; 32];
+                directory_entry.copy_from_slice(&directory[index * 32..(index + 1) * 32]);
+                set_attributes(&mut directory_entry, old.attributes() | attributes::ARCHIVE);
+                (index, directory_entry)
+            }
+            None => {
+                let (long_name, short) = new_name(&directory, name_extension)?;
+                // like write_file: a full fixed root directory fails, others grow
+                let first_index =
+                    self.reserve_entries(directory_cluster, &directory, long_name.len() + 1)?;
+                let index = first_index + long_name.len();
+                self.write_long_name(directory_cluster, index, &long_name)?;
+                let mut directory_entry = new_file_entry(&short);
+                if let Some(now) = self.now() {
+                    set_created(&mut directory_entry, &now);
+                }
+                (index, directory_entry)
+            }
+        };
+        if let Some(now) = self.now() {
+            set_modified(&mut directory_entry, &now);
+        }
+        self.write_directory_entry(directory_cluster, index, &directory_entry)?;
+        let append_writer = AppendWriter::new(self,
+                                              directory_cluster,
+                                              index,
+                                              directory_entry,
+                                              checkpoint_interval)?;
+        self.open_appends.borrow_mut().push((directory_cluster, index));
+        Ok(append_writer)
+    }
+
+    /// called by AppendWriter when it is dropped, the entry may change again
+    pub fn close_append(&self, directory_cluster: usize, index: usize) {
+        self.open_appends.borrow_mut().retain(|&open| open != (directory_cluster, index));
+    }
+
+    /// an AppendWriter keeps the index of the entry and writes it at every checkpoint,
+    /// the entry must stay where it is
+    fn check_not_appending(&self, directory_cluster: usize, index: usize)
+                           -> Result<(), StorageError> {
+        if self.open_appends.borrow().contains(&(directory_cluster, index)) {
+            Err(StorageError::InUse)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// removes the file with its long name and frees its clusters
+    /// directories are removed with remove_dir
+    pub fn remove_file(&self, path: &str) -> Result<(), StorageError> {
+        let (location, directory, directory_entry) = self.find_entry(path)?;
+        if !directory_entry.is_file() {
+            return Err(StorageError::IsADirectory);
+        }
+        if directory_entry.is_read_only() {
+            return Err(StorageError::ReadOnly);
+        }
+        self.check_not_appending(location.directory_cluster, location.index)?;
+        // the entry goes first: an interruption leaves a lost chain, not a free one in use
+        self.delete_entries(&location, &directory)?;
+        self.flush()?;
+        self.free_clusters(directory_entry.first_cluster())
+    }
+
+    /// creates an empty directory, the parent directory has to exist
+    /// names are stored like the ones of new files, see write_file(...)
+    pub fn create_dir(&self, path: &str) -> Result<(), StorageError> {
+        let (parent, name_extension) = split_path(path);
+        let parent_cluster = self.directory_cluster(parent)?;
+        let directory = self.read_directory(parent_cluster)?;
+        if search_directory(&directory, name_extension)?.0.is_some() {
+            return Err(StorageError::AlreadyExists);
+        }
+        let (long_name, short) = new_name(&directory, name_extension)?;
+
+        // a full fixed root directory fails before the cluster is taken
+        let first_index = self.reserve_entries(parent_cluster, &directory, long_name.len() + 1)?;
+        let index = first_index + long_name.len();
+
+        let cluster = self.allocate_clusters(1)?[0];
+        let mut directory_entry = new_directory_entry(&short);
+        set_first_cluster(&mut directory_entry, cluster);
+        let mut dot = new_directory_entry(b".          ");
+        set_first_cluster(&mut dot, cluster);
+        let mut dot_dot = new_directory_entry(b"..         ");
+        set_first_cluster(&mut dot_dot, self.parent_reference(parent_cluster));
+        if let Some(time_source) = self.time_source {
+            let now = time_source.now();
+            for entry in &mut [&mut directory_entry, &mut dot, &mut dot_dot] {
+                set_created(*entry, &now);
+            }
+        }
+
+        let mut data = Vec::new();
+        data.resize(self.cluster_size(), 0);
+        data[..32].copy_from_slice(&dot);
+        data[32..64].copy_from_slice(&dot_dot);
+        self.write_cluster_data_region(cluster, &data)?;
+        // the cluster is taken and filled before the entry points to it
+        self.flush()?;
+        self.write_long_name(parent_cluster, index, &long_name)?;
+        self.write_directory_entry(parent_cluster, index, &directory_entry)
+    }
+
+    /// removes the directory, if it contains nothing but "." and ".."
+    pub fn remove_dir(&self, path: &str) -> Result<(), StorageError> {
+        let (location, directory, directory_entry) = self.find_entry(path)?;
+        if !directory_entry.is_directory() {
+            return Err(StorageError::NotADirectory);
+        }
+        let content = self.read_directory(directory_entry.first_cluster())?;
+        let is_empty = content.chunks(32)
+            .take_while(|entry| entry[0] != 0x00)
+            .all(|entry| entry[0] == 0xE5 || entry[0] == b'.' || is_long_name_entry(entry));
+        if !is_empty {
+            return Err(StorageError::DirectoryNotEmpty);
+        }
+        self.delete_entries(&location, &directory)?;
+        self.flush()?;
+        self.free_clusters(directory_entry.first_cluster())
+    }
+
+    /// renames or moves a file or directory, to has to be free
+    /// a move that keeps the name keeps the long name (and the short name) as well,
+    /// a new name is stored like the one of a new file, see write_file(...)
+    /// moving a directory below itself is refused with InvalidName
+    pub fn rename(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        let (location, directory, directory_entry) = self.find_entry(from)?;
+        self.check_not_appending(location.directory_cluster, location.index)?;
+        let (to_parent, to_name_extension) = split_path(to);
+        if is_dot_name(to_name_extension) {
+            return Err(StorageError::InvalidName);
+        }
+        let to_cluster = self.directory_cluster(to_parent)?;
+        let to_directory = self.read_directory(to_cluster)?;
+        if search_directory(&to_directory, to_name_extension)?.0.is_some() {
+            return Err(StorageError::AlreadyExists);
+        }
+        let is_move = to_cluster != location.directory_cluster;
+        if directory_entry.is_directory() && is_move &&
+           self.is_below(to_cluster, directory_entry.first_cluster())? {
+            return Err(StorageError::InvalidName);
+        }
+
+        let mut new_entries = Vec::new();
+        // the checksum in the long name entries still fits the short name,
+        // if it is not taken in the new directory, the entries are kept
+        if directory_entry.matches(to_name_extension) &&
+           search_directory(&to_directory, directory_entry.name_extension())?.0.is_none() {
+            new_entries.extend_from_slice(&directory[location.first_index * 32..
+                                                     (location.index + 1) * 32]);
+        } else {
+            let (long_name, short) = new_name(&to_directory, to_name_extension)?;
+            for long_name_entry in &long_name {
+                new_entries.extend_from_slice(long_name_entry);
+            }
+            let mut new_entry = [0; 32];
+            new_entry.copy_from_slice(&directory[location.index * 32..(location.index + 1) * 32]);
+            set_short_name(&mut new_entry, &short);
+            new_entries.extend_from_slice(&new_entry);
+        }
+
+        // the new entries go first: an interruption leaves both names, not none
+        let number = new_entries.len() / 32;
+        let first_index = self.reserve_entries(to_cluster, &to_directory, number)?;
+        for (i, new_entry) in new_entries.chunks(32).enumerate() {
+            self.write_directory_entry(to_cluster, first_index + i, new_entry)?;
+        }
+        self.flush()?;
+        self.delete_entries(&location, &directory)?;
+
+        if directory_entry.is_directory() && is_move {
+            let cluster = directory_entry.first_cluster();
+            let mut data = Vec::new();
+            data.resize(self.cluster_size(), 0);
+            self.read_cluster_data_region(cluster, &mut data)?;
+            if &data[32..34] == b".." {
+                let mut dot_dot = [0; 32];
+                dot_dot.copy_from_slice(&data[32..64]);
+                set_first_cluster(&mut dot_dot, self.parent_reference(to_cluster));
+                self.write_directory_entry(cluster, 1, &dot_dot)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// resolves path to its directory entry, walking down the subdirectories
+    /// the root directory itself has no entry, it is not found
+    pub fn lookup(&self, path: &str) -> Result<DirectoryEntry, StorageError> {
+        let (parent, name_extension) = split_path(path);
+        if name_extension.is_empty() {
+            return Err(StorageError::NotFound);
+        }
+        let cluster = self.directory_cluster(parent)?;
+        self.find_in_directory(cluster, name_extension)
+    }
+
+    /// lists the directory at path, "" and "/" are the root directory
+    pub fn read_dir(&self, path: &str) -> Result<ReadDir, StorageError> {
+        let cluster = self.directory_cluster(path)?;
+        Ok(ReadDir::new(self, cluster))
+    }
+
+    /// first cluster of the directory at path
+    fn directory_cluster(&self, path: &str) -> Result<usize, StorageError> {
+        let mut cluster = self.root_directory_cluster_offset;
+        for name_extension in path.split('/').filter(|c| !c.is_empty()) {
+            let directory_entry = self.find_in_directory(cluster, name_extension)?;
+            if !directory_entry.is_directory() {
+                return Err(StorageError::NotADirectory);
+            }
+            cluster = directory_entry.first_cluster();
+            // ".." of a directory below the root points to cluster 0
+            if cluster == 0 {
+                cluster = self.root_directory_cluster_offset;
+            }
+        }
+        Ok(cluster)
+    }
+
+    /// the entry at path, where it is stored and the data of its directory
+    /// "." and ".." are refused, they are the directory itself and its parent
+    fn find_entry(&self,
+                  path: &str)
+                  -> Result<(EntryLocation, Vec<u8>, DirectoryEntry), StorageError> {
+        let (parent, name_extension) = split_path(path);
+        if name_extension.is_empty() {
+            return Err(StorageError::NotFound);
+        }
+        if is_dot_name(name_extension) {
+            return Err(StorageError::InvalidName);
+        }
+        let directory_cluster = self.directory_cluster(parent)?;
+        let directory = self.read_directory(directory_cluster)?;
+        match search_directory(&directory, name_extension)?.0 {
+            Some((first_index, index, directory_entry)) => {
+                let location = EntryLocation {
+                    directory_cluster: directory_cluster,
+                    first_index: first_index,
+                    index: index,
+                };
+                Ok((location, directory, directory_entry))
+            }
+            None => Err(StorageError::NotFound),
+        }
+    }
+
+    /// marks the entry and its long name entries as deleted (0xE5)
+    fn delete_entries(&self,
+                      location: &EntryLocation,
+                      directory: &[u8])
+                      -> Result<(), StorageError> {
+        for i in location.first_index..location.index + 1 {
+            let mut directory_entry = [0; 32];
+            directory_entry.copy_from_slice(&directory[i * 32..(i + 1) * 32]);
+            directory_entry[0] = 0xE5;
+            self.write_directory_entry(location.directory_cluster, i, &directory_entry)?;
+        }
+        Ok(())
+    }
+
+    /// the cluster ".." points to in a directory below cluster
+    /// the root directory is referenced as cluster 0, even on FAT32
+    fn parent_reference(&self, cluster: usize) -> usize {
+        if cluster == self.root_directory_cluster_offset {
+            0
+        } else {
+            cluster
+        }
+    }
+
+    /// true if the directory at cluster is ancestor or lies below it, following ".."
+    fn is_below(&self, cluster: usize, ancestor: usize) -> Result<bool, StorageError> {
+        let mut data = Vec::new();
+        data.resize(self.cluster_size(), 0);
+        let mut current = cluster;
+        // deeper than the volume has clusters: the ".." entries loop
+        for _ in 0..self.number_of_clusters {
+            if current == ancestor {
+                return Ok(true);
+            }
+            if current == self.root_directory_cluster_offset {
+                return Ok(false);
+            }
+            self.read_cluster_data_region(current, &mut data)?;
+            let dot_dot = DirectoryEntry::new(&data[32..64])?;
+            current = match dot_dot.first_cluster() {
+                0 => self.root_directory_cluster_offset,
+                parent => parent,
+            };
+        }
+        Err(StorageError::CorruptChain)
+    }
+
+    /// name_extension can be the short or the long name
+    fn find_in_directory(&self,
+                         cluster: usize,
+                         name_extension: &str)
+                         -> Result<DirectoryEntry, StorageError> {
+        let directory = self.read_directory(cluster)?;
+        match search_directory(&directory, name_extension)?.0 {
+            Some((_, _, directory_entry)) => Ok(directory_entry),
+            None => Err(StorageError::NotFound),
+        }
     }
 
     // sdram
-    fn compile_clusters_begin_with_number(&self, offset: usize) -> Vec<u8> {
+    fn compile_clusters_begin_with_number(&self, offset: usize) -> Result<Vec<u8>, StorageError> {
+        let cluster_size = self.cluster_size();
+        let chain = self.cluster_chain(offset)?;
         let mut all = Vec::new();
-        let mut current_offset = offset;
-        //[0x?0000002; 0x?FFFFFF6] //max should be calculated first
-        while (current_offset & 0x0FFFFFFF) >= 0x2 && (current_offset & 0x0FFFFFFF) <= 0xFFFFFF6 {
-            //println!("current_offset: {0:08.x}", current_offset);
-            all.append(&mut self.read_cluster_data_region(current_offset));
-            current_offset = self.read_in_fat(current_offset);
+        all.resize(chain.len() * cluster_size, 0);
+        for (cluster, data) in chain.iter().zip(all.chunks_mut(cluster_size)) {
+            self.read_cluster_data_region(*cluster, data)?;
         }
-        all
+        Ok(all)
     }
 
-    fn file_directory_entry(&self, name_extension: &str) -> Option<DirectoryEntry> {
-        let root = self.read_root_directory();
-        let number: usize = root.len() / 32;
-        // better: implement check is_last_entry and break
-        for i in 0..number {
-            let mut directory_entry = Vec::with_capacity(32);
-            for j in 0..32 {
-                directory_entry.push(root[i * 32 + j]);
-            }
-            let dir_entr = DirectoryEntry::new(&directory_entry);
-            if dir_entr.is_file() && dir_entr.name_extension() == name_extension {
-                return Some(dir_entr);
+    /// the clusters of the chain beginning with offset, empty for offset 0
+    pub fn cluster_chain(&self, offset: usize) -> Result<Vec<usize>, StorageError> {
+        let mut chain = Vec::new();
+        if offset == 0 {
+            return Ok(chain);
+        }
+        if !self.is_data_cluster(offset) {
+            return Err(StorageError::CorruptChain);
+        }
+        let mut current_offset = Some(offset);
+        while let Some(cluster) = current_offset {
+            chain.push(cluster);
+            // a chain longer than the volume loops
+            if chain.len() > self.number_of_clusters {
+                return Err(StorageError::CorruptChain);
             }
+            current_offset = self.next_cluster(cluster)?;
+        }
+        Ok(chain)
+    }
+
+    /// the cluster following offset in its chain, None at the end of the chain
+    pub fn next_cluster(&self, offset: usize) -> Result<Option<usize>, StorageError> {
+        let next_offset = self.read_in_fat(offset)?;
+        if next_offset >= MIN_END_OF_CHAIN {
+            Ok(None)
+        } else if self.is_data_cluster(next_offset) {
+            Ok(Some(next_offset))
+        } else {
+            // free, reserved or bad cluster
+            Err(StorageError::CorruptChain)
         }
-        None
     }
 
+    /// follows the cluster chain of the directory
+    /// up to the cluster containing the end of directory marker
     // sdram
-    fn read_root_directory(&self) -> Vec<u8> {
-        self.read_cluster_data_region(self.root_directory_cluster_offset)
+    fn read_directory(&self, cluster: usize) -> Result<Vec<u8>, StorageError> {
+        if self.is_fixed_root_directory(cluster) {
+            return self.read_fixed_root_directory();
+        }
+        let cluster_size = self.cluster_size();
+        let mut all = Vec::new();
+        let mut current_offset = Some(cluster);
+        while let Some(offset) = current_offset {
+            let length = all.len();
+            all.resize(length + cluster_size, 0);
+            self.read_cluster_data_region(offset, &mut all[length..])?;
+            let is_last = all[length..]
+                .chunks(32)
+                .any(|directory_entry| directory_entry[0] == 0x00);
+            if is_last {
+                break;
+            }
+            // a directory larger than the volume loops
+            if all.len() > self.number_of_clusters * cluster_size {
+                return Err(StorageError::CorruptChain);
+            }
+            current_offset = self.next_cluster(offset)?;
+        }
+        Ok(all)
+    }
+
+    /// writes the entry with the given index into the directory beginning at cluster
+    /// the directory grows by one cluster, if the index is right behind its end
+    pub fn write_directory_entry(&self,
+                                 cluster: usize,
+                                 index: usize,
+                                 directory_entry: &[u8])
+                                 -> Result<(), StorageError> {
+        if self.is_fixed_root_directory(cluster) {
+            let block_size = self.block_device.block_size();
+            if index * 32 >= self.number_of_root_directory_blocks * block_size {
+                return Err(StorageError::NoSpace);
+            }
+            let block_number = self.root_directory_block_offset + index * 32 / block_size;
+            let offset = index * 32 % block_size;
+            let mut block = error::read_blocks(self.block_device, block_number, 1)?;
+            block[offset..offset + 32].copy_from_slice(directory_entry);
+            return self.block_device.write(block_number, &block);
+        }
+
+        let cluster_size = self.cluster_size();
+        let current_offset = self.entry_cluster(cluster, index)?;
+        let offset = index * 32 % cluster_size;
+        let mut data = Vec::new();
+        data.resize(cluster_size, 0);
+        self.read_cluster_data_region(current_offset, &mut data)?;
+        data[offset..offset + 32].copy_from_slice(directory_entry);
+        self.write_cluster_data_region(current_offset, &data)
+    }
+
+    /// makes sure the entry with the given index can be written later without allocating,
+    /// NoSpace if the fixed root directory is too small, other directories grow here
+    fn reserve_entry(&self, cluster: usize, index: usize) -> Result<(), StorageError> {
+        if self.is_fixed_root_directory(cluster) {
+            if index * 32 >= self.number_of_root_directory_blocks * self.block_size() {
+                return Err(StorageError::NoSpace);
+            }
+            return Ok(());
+        }
+        self.entry_cluster(cluster, index).map(|_| ())
+    }
+
+    /// finds and reserves a run of number free entries in directory, see reserve_entry(...),
+    /// returns the index of the first one
+    fn reserve_entries(&self,
+                       cluster: usize,
+                       directory: &[u8],
+                       number: usize)
+                       -> Result<usize, StorageError> {
+        let first_index = free_entries(directory, number);
+        self.reserve_entry(cluster, first_index + number - 1)?;
+        Ok(first_index)
+    }
+
+    /// writes the long name entries in front of the entry index
+    fn write_long_name(&self,
+                       cluster: usize,
+                       index: usize,
+                       long_name: &[[u8; 32]])
+                       -> Result<(), StorageError> {
+        for (i, long_name_entry) in long_name.iter().enumerate() {
+            self.write_directory_entry(cluster, index - long_name.len() + i, long_name_entry)?;
+        }
+        Ok(())
+    }
+
+    /// the cluster of the directory beginning at cluster that holds the entry index
+    /// the directory grows by one cluster, if the index is right behind its end
+    fn entry_cluster(&self, cluster: usize, index: usize) -> Result<usize, StorageError> {
+        let mut current_offset = cluster;
+        for _ in 0..index * 32 / self.cluster_size() {
+            current_offset = match self.next_cluster(current_offset)? {
+                Some(next_offset) => next_offset,
+                None => self.extend_chain(current_offset)?,
+            };
+        }
+        Ok(current_offset)
+    }
+
+    /// allocates one cluster and links it behind last, the start of a new chain without one
+    /// the cluster ends its chain before it is linked, an interruption leaves it lost,
+    /// never cross-linked; its content is not cleared
+    pub fn append_cluster(&self, last: Option<usize>) -> Result<usize, StorageError> {
+        let cluster = self.allocate_clusters(1)?[0];
+        if let Some(last) = last {
+            self.flush()?;
+            self.write_in_fat(last, cluster)?;
+        }
+        Ok(cluster)
+    }
+
+    /// links a new, zeroed cluster behind the last cluster of a chain
+    fn extend_chain(&self, last: usize) -> Result<usize, StorageError> {
+        let cluster = self.allocate_clusters(1)?[0];
+        let mut zeroes = Vec::new();
+        zeroes.resize(self.cluster_size(), 0);
+        self.write_cluster_data_region(cluster, &zeroes)?;
+        // the directory must not run into a free or uncleared cluster
+        self.flush()?;
+        self.write_in_fat(last, cluster)?;
+        Ok(cluster)
+    }
+
+    /// size of a cluster in bytes
+    pub fn cluster_size(&self) -> usize {
+        self.block_size_cluster * self.block_device.block_size()
+    }
+
+    /// true if offset is the number of a cluster in the data region
+    pub fn is_data_cluster(&self, offset: usize) -> bool {
+        offset >= 2 && offset < self.number_of_clusters + 2
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.block_device.block_size()
+    }
+
+    /// the free count as it is stored, None if it is unknown
+    pub fn stored_free_cluster_count(&self) -> Option<usize> {
+        self.free_cluster_count.get()
+    }
+
+    pub fn number_of_clusters(&self) -> usize {
+        self.number_of_clusters
+    }
+
+    /// first cluster of the root directory, 0 for the fixed root directory
+    pub fn root_directory_cluster(&self) -> usize {
+        self.root_directory_cluster_offset
+    }
+
+    pub fn number_of_fats(&self) -> usize {
+        self.number_of_fats
     }
 
-    /// will crash if (offset * 4) >= block_size
-    // buffer
-    fn read_in_fat(&self, offset: usize) -> usize {
-        //better: buffer for read block_device with offset
-        let block = self.block_device
-            .read_blocks(self.number_of_reserved_blocks, 1);
-        //4: byte-size of u32
-        (four_bytes_at_offset(&block, offset * 4) & 0x0FFFFFFF) as usize
+    pub fn number_of_blocks_per_fat(&self) -> usize {
+        self.number_of_blocks_per_fat
+    }
+
+    /// reads block index of the FAT copy fat, 0 is the first FAT
+    pub fn read_fat_block(&self,
+                          fat: usize,
+                          index: usize,
+                          buffer: &mut [u8])
+                          -> Result<(), StorageError> {
+        if fat >= self.number_of_fats || index >= self.number_of_blocks_per_fat {
+            return Err(StorageError::OutOfRange);
+        }
+        self.block_device
+            .read(self.number_of_reserved_blocks + fat * self.number_of_blocks_per_fat + index,
+                  buffer)
+    }
+
+    /// overwrites the other copies with the first FAT
+    pub fn mirror_fat(&self) -> Result<(), StorageError> {
+        let mut block = Vec::new();
+        block.resize(self.block_size(), 0);
+        for index in 0..self.number_of_blocks_per_fat {
+            self.read_fat_block(0, index, &mut block)?;
+            for fat in 1..self.number_of_fats {
+                self.block_device
+                    .write(self.number_of_reserved_blocks + fat * self.number_of_blocks_per_fat +
+                           index,
+                           &block)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// the fixed root directory of FAT12/16 has no clusters, it is addressed as cluster 0
+    pub fn is_fixed_root_directory(&self, cluster: usize) -> bool {
+        self.fat_type != FatType::Fat32 && cluster == 0
     }
 
     // sdram
-    fn read_cluster_data_region(&self, cluster_entry_offset: usize) -> Vec<u8> {
+    pub fn read_fixed_root_directory(&self) -> Result<Vec<u8>, StorageError> {
+        error::read_blocks(self.block_device,
+                           self.root_directory_block_offset,
+                           self.number_of_root_directory_blocks)
+    }
+
+    /// searches number free clusters and links them to a new chain
+    fn allocate_clusters(&self, number: usize) -> Result<Vec<usize>, StorageError> {
+        let clusters = self.find_free_clusters(number)?;
+        for i in 0..clusters.len() {
+            let next = if i + 1 < clusters.len() {
+                clusters[i + 1]
+            } else {
+                END_OF_CHAIN
+            };
+            self.write_in_fat(clusters[i], next)?;
+        }
+        if let Some(last) = clusters.last() {
+            let next_free_cluster = if self.is_data_cluster(last + 1) {
+                last + 1
+            } else {
+                FIRST_DATA_CLUSTER
+            };
+            let free_cluster_count = self.free_cluster_count
+                .get()
+                .map(|c| c.saturating_sub(clusters.len()));
+            self.set_free_space(free_cluster_count, next_free_cluster)?;
+        }
+        Ok(clusters)
+    }
+
+    /// the whole chain is checked before anything is freed
+    fn free_clusters(&self, offset: usize) -> Result<(), StorageError> {
+        let chain = self.cluster_chain(offset)?;
+        for cluster in &chain {
+            self.write_in_fat(*cluster, 0)?;
+        }
+        if let Some(first_freed) = chain.iter().min() {
+            let free_cluster_count = self.free_cluster_count
+                .get()
+                .map(|c| cmp::min(c + chain.len(), self.number_of_clusters));
+            self.set_free_space(free_cluster_count,
+                                cmp::min(*first_freed, self.next_free_cluster.get()))?;
+        }
+        Ok(())
+    }
+
+    /// starts at the next free hint and wraps around
+    fn find_free_clusters(&self, number: usize) -> Result<Vec<usize>, StorageError> {
+        let mut free = Vec::with_capacity(number);
+        if number == 0 {
+            return Ok(free);
+        }
+        let start = self.next_free_cluster.get();
+        let end = self.number_of_clusters + FIRST_DATA_CLUSTER;
+        for cluster in (start..end).chain(FIRST_DATA_CLUSTER..start) {
+            if self.read_in_fat(cluster)? == 0 {
+                free.push(cluster);
+                if free.len() == number {
+                    return Ok(free);
+                }
+            }
+        }
+        Err(StorageError::NoSpace)
+    }
+
+    /// writes value into every copy of the FAT
+    /// the upper four bits of a FAT32 entry are reserved and kept
+    /// the free count is not updated, see set_free_space
+    pub fn write_in_fat(&self, offset: usize, value: usize) -> Result<(), StorageError> {
+        if offset >= self.number_of_clusters + 2 {
+            return Err(StorageError::OutOfRange);
+        }
+        let value = value as u32;
+        match self.fat_type {
+            FatType::Fat12 => {
+                let byte_offset = offset + offset / 2;
+                let old = self.read_fat_bytes(byte_offset, 2)?;
+                let new = if offset % 2 == 0 {
+                    (old & 0xF000) | (value & 0x0FFF)
+                } else {
+                    (old & 0x000F) | ((value & 0x0FFF) << 4)
+                };
+                self.write_fat_bytes(byte_offset, 2, new)
+            }
+            FatType::Fat16 => self.write_fat_bytes(offset * 2, 2, value & 0xFFFF),
+            FatType::Fat32 => {
+                let old = self.read_fat_bytes(offset * 4, 4)?;
+                self.write_fat_bytes(offset * 4,
+                                     4,
+                                     (old & !FAT_ENTRY_MASK) | (value & FAT_ENTRY_MASK))
+            }
+        }
+    }
+
+    /// the values of FAT12/16 are widened to FAT32,
+    /// e.g. the end of chain 0xFFF8 becomes 0x0FFFFFF8
+    pub fn read_in_fat(&self, offset: usize) -> Result<usize, StorageError> {
+        if offset >= self.number_of_clusters + 2 {
+            return Err(StorageError::OutOfRange);
+        }
+        let (value, mask) = match self.fat_type {
+            FatType::Fat12 => {
+                let both = self.read_fat_bytes(offset + offset / 2, 2)?;
+                let value = if offset % 2 == 0 {
+                    both & 0x0FFF
+                } else {
+                    both >> 4
+                };
+                (value, 0x0FFF)
+            }
+            FatType::Fat16 => (self.read_fat_bytes(offset * 2, 2)?, 0xFFFF),
+            FatType::Fat32 => {
+                (self.read_fat_bytes(offset * 4, 4)? & FAT_ENTRY_MASK, FAT_ENTRY_MASK)
+            }
+        };
+        //bad cluster and end of chain
+        if value >= mask - 8 {
+            Ok((value | (FAT_ENTRY_MASK & !mask)) as usize)
+        } else {
+            Ok(value as usize)
+        }
+    }
+
+    /// little endian value of number bytes of the first FAT
+    fn read_fat_bytes(&self, byte_offset: usize, number: usize) -> Result<u32, StorageError> {
+        let mut value = 0;
+        for i in 0..number {
+            let (block_number, offset_in_block) = self.fat_position(byte_offset + i);
+            let byte = self.fat_cache
+                .read(self.block_device, block_number, |block| block[offset_in_block])?;
+            value |= (byte as u32) << (8 * i);
+        }
+        Ok(value)
+    }
+
+    /// FAT12 entries can lie across two blocks
+    fn write_fat_bytes(&self,
+                       byte_offset: usize,
+                       number: usize,
+                       value: u32)
+                       -> Result<(), StorageError> {
+        let block_size = self.block_device.block_size();
+        let mut i = 0;
+        while i < number {
+            let (block_number, offset_in_block) = self.fat_position(byte_offset + i);
+            let in_block = cmp::min(number - i, block_size - offset_in_block);
+            self.fat_cache
+                .write(self.block_device, block_number, |block| {
+                    for j in i..i + in_block {
+                        block[offset_in_block + j - i] = (value >> (8 * j)) as u8;
+                    }
+                })?;
+            // the other copies mirror the first one
+            self.fat_cache
+                .read(self.block_device, block_number, |block| {
+                    for fat in 1..self.number_of_fats {
+                        self.block_device
+                            .write(block_number + fat * self.number_of_blocks_per_fat, block)?;
+                    }
+                    Ok(())
+                })??;
+            i += in_block;
+        }
+        Ok(())
+    }
+
+    /// block of the first FAT and offset in it of the byte at byte_offset
+    fn fat_position(&self, byte_offset: usize) -> (usize, usize) {
+        let block_size = self.block_device.block_size();
+        (self.number_of_reserved_blocks + byte_offset / block_size, byte_offset % block_size)
+    }
+
+    /// buffer has to be cluster_size() bytes long
+    pub fn read_cluster_data_region(&self,
+                                    cluster_entry_offset: usize,
+                                    buffer: &mut [u8])
+                                    -> Result<(), StorageError> {
+        if !self.is_data_cluster(cluster_entry_offset) || buffer.len() != self.cluster_size() {
+            return Err(StorageError::OutOfRange);
+        }
         //- 2 because the first two cluster-entries in the FAT are reserved
         //and dont represent clusters in the data section
         self.block_device
-            .read_blocks(self.data_region_block_offset +
-                         (cluster_entry_offset - 2) * self.block_size_cluster,
-                         self.block_size_cluster)
+            .read(self.data_region_block_offset +
+                  (cluster_entry_offset - 2) * self.block_size_cluster,
+                  buffer)
+    }
+
+    /// writes whole blocks into a cluster, starting with its block first_block
+    pub fn write_cluster_blocks(&self,
+                                cluster_entry_offset: usize,
+                                first_block: usize,
+                                data: &[u8])
+                                -> Result<(), StorageError> {
+        let block_size = self.block_device.block_size();
+        if !self.is_data_cluster(cluster_entry_offset) || data.len() % block_size != 0 ||
+           first_block + data.len() / block_size > self.block_size_cluster {
+            return Err(StorageError::OutOfRange);
+        }
+        self.block_device
+            .write(self.data_region_block_offset +
+                   (cluster_entry_offset - 2) * self.block_size_cluster + first_block,
+                   data)
+    }
+
+    fn write_cluster_data_region(&self,
+                                 cluster_entry_offset: usize,
+                                 data: &[u8])
+                                 -> Result<(), StorageError> {
+        if !self.is_data_cluster(cluster_entry_offset) {
+            return Err(StorageError::OutOfRange);
+        }
+        self.block_device
+            .write(self.data_region_block_offset +
+                   (cluster_entry_offset - 2) * self.block_size_cluster,
+                   data)
+    }
+}
+
+fn is_fs_info(fs_info: &[u8]) -> bool {
+    four_bytes_at_offset(fs_info, FS_INFO_LEAD_SIGNATURE_OFFSET) == FS_INFO_LEAD_SIGNATURE &&
+    four_bytes_at_offset(fs_info, FS_INFO_STRUCT_SIGNATURE_OFFSET) == FS_INFO_STRUCT_SIGNATURE &&
+    four_bytes_at_offset(fs_info, FS_INFO_TRAIL_SIGNATURE_OFFSET) == FS_INFO_TRAIL_SIGNATURE
+}
+
+/// splits path into the path of the parent directory and the last component
+fn split_path(path: &str) -> (&str, &str) {
+    let path = path.trim_right_matches('/');
+    match path.rfind('/') {
+        Some(i) => (&path[..i], &path[i + 1..]),
+        None => ("", path),
+    }
+}
+
+/// index of the first run of number free entries, deleted ones or those behind the end
+/// marker; the run can reach behind the end of the directory, it grows then
+fn free_entries(directory: &[u8], number: usize) -> usize {
+    let mut start = 0;
+    for i in 0..directory.len() / 32 {
+        match directory[i * 32] {
+            0x00 => return start,
+            0xE5 if i + 1 - start >= number => return start,
+            0xE5 => {}
+            _ => start = i + 1,
+        }
+    }
+    start
+}
+
+/// the long name entries and the short name of a new entry named name_extension:
+/// a valid short name needs no long name, other names get the first numbered short name
+/// ("NAME~1.EXT", "NAME~2.EXT", ...) that is not taken in directory
+fn new_name(directory: &[u8],
+            name_extension: &str)
+            -> Result<(Vec<[u8; 32]>, [u8; 11]), StorageError> {
+    if let Some(short) = short_name(name_extension) {
+        return Ok((Vec::new(), short));
+    }
+    if !is_valid_long_name(name_extension) {
+        return Err(StorageError::InvalidName);
+    }
+    let taken: Vec<&[u8]> = directory.chunks(32)
+        .take_while(|entry| entry[0] != 0x00)
+        .filter(|entry| entry[0] != 0xE5 && !is_long_name_entry(entry))
+        .map(|entry| &entry[..11])
+        .collect();
+    for number in 1..MAX_SHORT_NAME_NUMBER + 1 {
+        let short = numbered_short_name(name_extension, number);
+        if !taken.iter().any(|name| *name == &short[..]) {
+            return Ok((new_long_name_entries(name_extension, &short), short));
+        }
+    }
+    Err(StorageError::AlreadyExists)
+}
+
+/// true for "." and "..", the entries every directory but the root starts with
+fn is_dot_name(name_extension: &str) -> bool {
+    name_extension == "." || name_extension == ".."
+}
+
+/// returns the index of the first long name entry (the index of the entry itself,
+/// if there is no long name), the index and the entry of the file or directory
+/// named name_extension and the index of the first free entry
+fn search_directory(directory: &[u8],
+                    name_extension: &str)
+                    -> Result<(Option<(usize, usize, DirectoryEntry)>, Option<usize>),
+                              StorageError> {
+    let mut free = None;
+    let mut long_name = LongNameBuilder::new();
+    let mut long_name_start = None;
+    for i in 0..directory.len() / 32 {
+        let directory_entry = &directory[i * 32..(i + 1) * 32];
+        match directory_entry[0] {
+            0x00 => {
+                if free.is_none() {
+                    free = Some(i);
+                }
+                break;
+            }
+            0xE5 => {
+                long_name.clear();
+                long_name_start = None;
+                if free.is_none() {
+                    free = Some(i);
+                }
+            }
+            _ if is_long_name_entry(directory_entry) => {
+                // the physically first entry of a long name carries the last part
+                if long_name_start.is_none() || directory_entry[0] & 0x40 != 0 {
+                    long_name_start = Some(i);
+                }
+                long_name.push(directory_entry)
+            }
+            _ => {
+                let long = long_name.take(directory_entry);
+                let first = if long.is_some() {
+                    long_name_start.unwrap_or(i)
+                } else {
+                    i
+                };
+                long_name_start = None;
+                let dir_entr = DirectoryEntry::with_long_name(directory_entry, long)?;
+                if (dir_entr.is_file() || dir_entr.is_directory()) &&
+                   dir_entr.matches(name_extension) {
+                    return Ok((Some((first, i, dir_entr)), free));
+                }
+            }
+        }
     }
+    Ok((None, free))
 }
@@ -0,0 +1,105 @@
+use block_device::BlockDevice;
+use super::error::StorageError;
+use collections::vec::*;
+use core::cell::RefCell;
+use core::usize;
+
+/// keeps the most recently used blocks of the FAT
+/// writes go through to the block device immediately
+/// once the cache is full, replaced blocks are read into the buffers of the old ones
+pub struct FatCache {
+    capacity: usize,
+    inner: RefCell<Inner>,
+}
+
+struct Inner {
+    blocks: Vec<CachedBlock>,
+    clock: usize,
+}
+
+struct CachedBlock {
+    block_number: usize,
+    data: Vec<u8>,
+    last_use: usize,
+}
+
+impl FatCache {
+    pub fn new(capacity: usize) -> FatCache {
+        FatCache {
+            capacity: capacity,
+            inner: RefCell::new(Inner {
+                blocks: Vec::with_capacity(capacity),
+                clock: 0,
+            }),
+        }
+    }
+
+    pub fn read<F, T>(&self,
+                      block_device: &BlockDevice<Error = StorageError>,
+                      block_number: usize,
+                      f: F)
+                      -> Result<T, StorageError>
+        where F: FnOnce(&[u8]) -> T
+    {
+        let index = self.load(block_device, block_number)?;
+        let inner = self.inner.borrow();
+        Ok(f(&inner.blocks[index].data))
+    }
+
+    /// changes the block with f and writes it to the block device
+    pub fn write<F>(&self,
+                    block_device: &BlockDevice<Error = StorageError>,
+                    block_number: usize,
+                    f: F)
+                    -> Result<(), StorageError>
+        where F: FnOnce(&mut [u8])
+    {
+        let index = self.load(block_device, block_number)?;
+        let mut inner = self.inner.borrow_mut();
+        let block = &mut inner.blocks[index];
+        f(&mut block.data);
+        block_device.write(block_number, &block.data)
+    }
+
+    /// returns the index of block_number in the cache
+    /// the least recently used block is replaced, if the cache is full
+    fn load(&self,
+            block_device: &BlockDevice<Error = StorageError>,
+            block_number: usize)
+            -> Result<usize, StorageError> {
+        let mut inner = self.inner.borrow_mut();
+        inner.clock += 1;
+        let clock = inner.clock;
+
+        let cached_index = inner.blocks.iter().position(|b| b.block_number == block_number);
+        if let Some(index) = cached_index {
+            inner.blocks[index].last_use = clock;
+            return Ok(index);
+        }
+
+        if inner.blocks.len() < self.capacity {
+            let mut data = Vec::new();
+            data.resize(block_device.block_size(), 0);
+            block_device.read(block_number, &mut data)?;
+            inner.blocks.push(CachedBlock {
+                block_number: block_number,
+                data: data,
+                last_use: clock,
+            });
+            return Ok(inner.blocks.len() - 1);
+        }
+        let mut oldest = 0;
+        for i in 1..inner.blocks.len() {
+            if inner.blocks[i].last_use < inner.blocks[oldest].last_use {
+                oldest = i;
+            }
+        }
+        let block = &mut inner.blocks[oldest];
+        // a failed read must not leave the old block number with the new data
+        block.block_number = usize::MAX;
+        block_device.read(block_number, &mut block.data)?;
+        block.block_number = block_number;
+        block.last_use = clock;
+        Ok(oldest)
+    }
+}
@@ -0,0 +1,102 @@
+use super::directory_entry::DirectoryEntry;
+use super::error::StorageError;
+use super::fat32_device_driver::Fat32DeviceDriver;
+use collections::vec::*;
+use core::cmp;
+
+/// read handle of a file, see Fat32DeviceDriver::open
+/// holds one cluster of the file at a time, the buffer is allocated once
+pub struct File<'a, 'b: 'a> {
+    driver: &'a Fat32DeviceDriver<'b>,
+    first_cluster: usize,
+    file_size: usize,
+    position: usize,
+    // index in the chain and number of the cluster last walked to
+    cluster_index: usize,
+    cluster: usize,
+    buffer: Vec<u8>,
+    buffer_loaded: bool,
+}
+
+impl<'a, 'b: 'a> File<'a, 'b> {
+    pub fn new(driver: &'a Fat32DeviceDriver<'b>,
+               directory_entry: &DirectoryEntry)
+               -> File<'a, 'b> {
+        File {
+            driver: driver,
+            first_cluster: directory_entry.first_cluster(),
+            file_size: directory_entry.file_size(),
+            position: 0,
+            cluster_index: 0,
+            cluster: directory_entry.first_cluster(),
+            buffer: Vec::new(),
+            buffer_loaded: false,
+        }
+    }
+
+    /// reads from the current position and advances it
+    /// returns the number of read bytes, 0 at the end of the file
+    pub fn read(&mut self, buffer: &mut [u8]) -> Result<usize, StorageError> {
+        let cluster_size = self.driver.cluster_size();
+        let mut read = 0;
+        while read < buffer.len() && self.position < self.file_size {
+            self.load_cluster(self.position / cluster_size)?;
+            let offset = self.position % cluster_size;
+            let number = cmp::min(cmp::min(cluster_size - offset, self.file_size - self.position),
+                                  buffer.len() - read);
+            buffer[read..read + number].copy_from_slice(&self.buffer[offset..offset + number]);
+            read += number;
+            self.position += number;
+        }
+        Ok(read)
+    }
+
+    /// positions behind the end of the file are set to the end
+    /// returns the new position
+    pub fn seek(&mut self, position: usize) -> usize {
+        self.position = cmp::min(position, self.file_size);
+        self.position
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn len(&self) -> usize {
+        self.file_size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.file_size == 0
+    }
+
+    /// walks the chain to the cluster with the given index and reads it into the buffer
+    /// sequential reads continue from the last cluster instead of the beginning
+    /// the chain must not end before, the file size promises its length
+    fn load_cluster(&mut self, cluster_index: usize) -> Result<(), StorageError> {
+        if self.buffer_loaded && cluster_index == self.cluster_index {
+            return Ok(());
+        }
+        self.buffer_loaded = false;
+        if cluster_index < self.cluster_index {
+            self.cluster_index = 0;
+            self.cluster = self.first_cluster;
+        }
+        if !self.driver.is_data_cluster(self.cluster) {
+            return Err(StorageError::CorruptChain);
+        }
+        while self.cluster_index < cluster_index {
+            self.cluster = match self.driver.next_cluster(self.cluster)? {
+                Some(cluster) => cluster,
+                None => return Err(StorageError::CorruptChain),
+            };
+            self.cluster_index += 1;
+        }
+        if self.buffer.is_empty() {
+            self.buffer.resize(self.driver.cluster_size(), 0);
+        }
+        self.driver.read_cluster_data_region(self.cluster, &mut self.buffer)?;
+        self.buffer_loaded = true;
+        Ok(())
+    }
+}
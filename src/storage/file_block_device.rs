@@ -0,0 +1,86 @@
+use block_device::BlockDevice;
+use super::error::StorageError;
+use std::cell::RefCell;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const DEFAULT_BLOCK_SIZE: usize = 512;
+
+/// block device backed by a regular file, e.g. a dd image of a card
+/// only with the std feature of the host crate, see host/
+/// bytes behind the last whole block of the file are ignored
+pub struct FileBlockDevice {
+    file: RefCell<File>,
+    block_size: usize,
+    number_of_blocks: usize,
+}
+
+impl FileBlockDevice {
+    /// opens the image for reading and writing
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<FileBlockDevice> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        FileBlockDevice::with_block_size(file, DEFAULT_BLOCK_SIZE)
+    }
+
+    /// writes fail with IoError, golden images stay untouched
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> io::Result<FileBlockDevice> {
+        let file = File::open(path)?;
+        FileBlockDevice::with_block_size(file, DEFAULT_BLOCK_SIZE)
+    }
+
+    pub fn with_block_size(file: File, block_size: usize) -> io::Result<FileBlockDevice> {
+        let length = file.metadata()?.len() as usize;
+        Ok(FileBlockDevice {
+            file: RefCell::new(file),
+            block_size: block_size,
+            number_of_blocks: length / block_size,
+        })
+    }
+
+    /// whole blocks inside the image only
+    fn seek(&self, lba: usize, length: usize) -> Result<(), StorageError> {
+        if length % self.block_size != 0 || lba + length / self.block_size > self.number_of_blocks {
+            return Err(StorageError::OutOfRange);
+        }
+        self.file
+            .borrow_mut()
+            .seek(SeekFrom::Start((lba * self.block_size) as u64))
+            .map(|_| ())
+            .map_err(|_| StorageError::IoError)
+    }
+}
+
+impl BlockDevice for FileBlockDevice {
+    type Error = StorageError;
+
+    fn read(&self, lba: usize, buffer: &mut [u8]) -> Result<(), StorageError> {
+        self.seek(lba, buffer.len())?;
+        self.file
+            .borrow_mut()
+            .read_exact(buffer)
+            .map_err(|_| StorageError::IoError)
+    }
+
+    fn write(&self, lba: usize, buffer: &[u8]) -> Result<(), StorageError> {
+        self.seek(lba, buffer.len())?;
+        self.file
+            .borrow_mut()
+            .write_all(buffer)
+            .map_err(|_| StorageError::IoError)
+    }
+
+    fn flush(&self) -> Result<(), StorageError> {
+        let mut file = self.file.borrow_mut();
+        file.flush().map_err(|_| StorageError::IoError)?;
+        file.sync_data().map_err(|_| StorageError::IoError)
+    }
+
+    fn number_of_blocks(&self) -> usize {
+        self.number_of_blocks
+    }
+
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+}
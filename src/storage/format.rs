@@ -0,0 +1,296 @@
+use block_device::BlockDevice;
+use super::date_time::{DateTime, TimeSource};
+use super::directory_entry;
+use super::error::StorageError;
+use super::get_bytes::*;
+use collections::string::*;
+use collections::vec::*;
+use core::cmp;
+
+const JUMP_OFFSET: usize = 0x00; //3
+const OEM_NAME_OFFSET: usize = 0x03; //8
+const BYTE_PER_SECTOR_OFFSET: usize = 0x0B;
+const SECTORS_PER_CLUSTER_OFFSET: usize = 0x0D;
+const NUMBER_OF_RESERVED_SECTORS_OFFSET: usize = 0x0E;
+const NUMBER_OF_FATS_OFFSET: usize = 0x10;
+const MEDIA_OFFSET: usize = 0x15;
+const SECTORS_PER_TRACK_OFFSET: usize = 0x18;
+const NUMBER_OF_HEADS_OFFSET: usize = 0x1A;
+const TOTAL_SECTORS_32_OFFSET: usize = 0x20;
+const NUMBER_OF_SECTORS_PER_FAT_OFFSET: usize = 0x24;
+const CLUSTER_NUMBER_ROOT_DIRECTORY_OFFSET: usize = 0x2C;
+const FS_INFO_SECTOR_OFFSET: usize = 0x30;
+const BACKUP_BOOT_SECTOR_OFFSET: usize = 0x32;
+const DRIVE_NUMBER_OFFSET: usize = 0x40;
+const BOOT_SIGNATURE_OFFSET: usize = 0x42;
+const VOLUME_ID_OFFSET: usize = 0x43;
+const VOLUME_LABEL_OFFSET: usize = 0x47; //11
+const FILE_SYSTEM_TYPE_OFFSET: usize = 0x52; //8
+const SIGNATURE_OFFSET: usize = 0x1FE;
+
+const FS_INFO_LEAD_SIGNATURE_OFFSET: usize = 0x000;
+const FS_INFO_STRUCT_SIGNATURE_OFFSET: usize = 0x1E4;
+const FS_INFO_FREE_COUNT_OFFSET: usize = 0x1E8;
+const FS_INFO_NEXT_FREE_OFFSET: usize = 0x1EC;
+const FS_INFO_TRAIL_SIGNATURE_OFFSET: usize = 0x1FC;
+const FS_INFO_LEAD_SIGNATURE: u32 = 0x41615252;
+const FS_INFO_STRUCT_SIGNATURE: u32 = 0x61417272;
+const FS_INFO_TRAIL_SIGNATURE: u32 = 0xAA550000;
+
+//sectors in front of the first FAT, as written by other formatters
+const NUMBER_OF_RESERVED_SECTORS: usize = 32;
+const NUMBER_OF_FATS: usize = 2;
+const FS_INFO_SECTOR: usize = 1;
+const BACKUP_BOOT_SECTOR: usize = 6;
+//fixed disk
+const MEDIA: u8 = 0xF8;
+const ROOT_DIRECTORY_CLUSTER: usize = 2;
+const END_OF_CHAIN: u32 = 0x0FFFFFFF;
+//cluster numbers from 0x0FFFFFF7 on are bad clusters and end of chain markers
+const MAX_CLUSTERS_FAT32: usize = 0x0FFFFFF5;
+//with fewer clusters every other driver takes the volume for FAT12 or FAT16
+const MIN_CLUSTERS_FAT32: usize = 65525;
+const MAX_CLUSTER_SIZE: usize = 32 * 1024;
+//number of blocks zeroed with one write
+const ZERO_BLOCKS: usize = 16;
+
+/// the optional settings of format_fat32(...)
+pub struct FormatOptions<'a> {
+    volume_label: Option<String>,
+    cluster_size: Option<usize>,
+    volume_id: Option<u32>,
+    time_source: &'a TimeSource,
+}
+
+impl<'a> FormatOptions<'a> {
+    /// no label, the cluster size depends on the size of the volume
+    /// the volume id is derived from the time of the format
+    pub fn new(time_source: &'a TimeSource) -> FormatOptions<'a> {
+        FormatOptions {
+            volume_label: None,
+            cluster_size: None,
+            volume_id: None,
+            time_source: time_source,
+        }
+    }
+
+    /// up to 11 characters of a short name, spaces are allowed, e.g. "SD PARTY"
+    pub fn volume_label(mut self, volume_label: &str) -> FormatOptions<'a> {
+        self.volume_label = Some(String::from(volume_label));
+        self
+    }
+
+    /// in bytes, a power of two multiple of the block size up to 32 KiB
+    pub fn cluster_size(mut self, cluster_size: usize) -> FormatOptions<'a> {
+        self.cluster_size = Some(cluster_size);
+        self
+    }
+
+    /// serial number of the volume instead of the one derived from the time
+    pub fn volume_id(mut self, volume_id: u32) -> FormatOptions<'a> {
+        self.volume_id = Some(volume_id);
+        self
+    }
+}
+
+/// the serial number DOS gives a volume formatted at date_time, the date and time fields
+/// added up in two 16 bit halves
+pub fn volume_id_of(date_time: &DateTime) -> u32 {
+    let high = ((date_time.month() as u32) << 8 | date_time.day() as u32) +
+               ((date_time.second() as u32) << 8 | (date_time.millisecond() / 10) as u32);
+    let low = ((date_time.hour() as u32) << 8 | date_time.minute() as u32) +
+              date_time.year() as u32;
+    (high & 0xFFFF) << 16 | (low & 0xFFFF)
+}
+
+/// creates an empty FAT32 volume on the whole block device, e.g. a partition
+/// writes the boot sector and its backup, the FSInfo sector, both FATs and the root directory
+/// the data region is not cleared
+/// FAT32 needs 65525 clusters: without a given cluster size smaller clusters are taken,
+/// VolumeTooSmall if even clusters of one block are too few (about 33 MiB with 512 byte blocks)
+pub fn format_fat32<D>(block_device: &D, options: &FormatOptions) -> Result<(), StorageError>
+    where D: BlockDevice + ?Sized,
+          D::Error: Into<StorageError>
+{
+    let byte_per_sector = block_device.block_size();
+    if !(byte_per_sector >= 512 && byte_per_sector <= 4096 && byte_per_sector.is_power_of_two()) {
+        return Err(StorageError::InvalidBlockSize);
+    }
+    let total_sectors = block_device.number_of_blocks();
+    if total_sectors > u32::max_value() as usize {
+        return Err(StorageError::OutOfRange);
+    }
+    let volume_label = match options.volume_label {
+        Some(ref label) => {
+            Some(directory_entry::volume_label(label).ok_or(StorageError::InvalidName)?)
+        }
+        None => None,
+    };
+
+    let mut cluster_size = match options.cluster_size {
+        Some(cluster_size) => cluster_size,
+        None => cmp::max(default_cluster_size(block_device.len()), byte_per_sector),
+    };
+    if !(cluster_size >= byte_per_sector && cluster_size <= MAX_CLUSTER_SIZE &&
+         cluster_size.is_power_of_two()) {
+        return Err(StorageError::InvalidClusterSize);
+    }
+    // a default cluster size is halved until there are enough clusters for FAT32
+    let (mut sectors_per_fat, mut number_of_clusters) =
+        layout(total_sectors, byte_per_sector, cluster_size);
+    while number_of_clusters < MIN_CLUSTERS_FAT32 && options.cluster_size.is_none() &&
+          cluster_size > byte_per_sector {
+        cluster_size /= 2;
+        let (fat, clusters) = layout(total_sectors, byte_per_sector, cluster_size);
+        sectors_per_fat = fat;
+        number_of_clusters = clusters;
+    }
+    if number_of_clusters < MIN_CLUSTERS_FAT32 {
+        return Err(match options.cluster_size {
+                       Some(_) => StorageError::InvalidClusterSize,
+                       None => StorageError::VolumeTooSmall,
+                   });
+    }
+    if number_of_clusters > MAX_CLUSTERS_FAT32 {
+        return Err(StorageError::InvalidClusterSize);
+    }
+    let sectors_per_cluster = cluster_size / byte_per_sector;
+    let data_region_sector = NUMBER_OF_RESERVED_SECTORS + NUMBER_OF_FATS * sectors_per_fat;
+
+    // reserved sectors, FATs and root directory
+    zero_blocks(block_device, 0, data_region_sector + sectors_per_cluster)?;
+
+    let mut boot_sector = Vec::new();
+    boot_sector.resize(byte_per_sector, 0);
+    boot_sector[JUMP_OFFSET..JUMP_OFFSET + 3].copy_from_slice(&[0xEB, 0x58, 0x90]);
+    boot_sector[OEM_NAME_OFFSET..OEM_NAME_OFFSET + 8].copy_from_slice(b"MSWIN4.1");
+    set_two_bytes_at_offset(&mut boot_sector, BYTE_PER_SECTOR_OFFSET, byte_per_sector as u16);
+    boot_sector[SECTORS_PER_CLUSTER_OFFSET] = sectors_per_cluster as u8;
+    set_two_bytes_at_offset(&mut boot_sector,
+                            NUMBER_OF_RESERVED_SECTORS_OFFSET,
+                            NUMBER_OF_RESERVED_SECTORS as u16);
+    boot_sector[NUMBER_OF_FATS_OFFSET] = NUMBER_OF_FATS as u8;
+    // number of root directory entries, total sectors 16 and sectors per FAT 16 stay 0
+    boot_sector[MEDIA_OFFSET] = MEDIA;
+    set_two_bytes_at_offset(&mut boot_sector, SECTORS_PER_TRACK_OFFSET, 63);
+    set_two_bytes_at_offset(&mut boot_sector, NUMBER_OF_HEADS_OFFSET, 255);
+    set_four_bytes_at_offset(&mut boot_sector, TOTAL_SECTORS_32_OFFSET, total_sectors as u32);
+    set_four_bytes_at_offset(&mut boot_sector,
+                             NUMBER_OF_SECTORS_PER_FAT_OFFSET,
+                             sectors_per_fat as u32);
+    set_four_bytes_at_offset(&mut boot_sector,
+                             CLUSTER_NUMBER_ROOT_DIRECTORY_OFFSET,
+                             ROOT_DIRECTORY_CLUSTER as u32);
+    set_two_bytes_at_offset(&mut boot_sector, FS_INFO_SECTOR_OFFSET, FS_INFO_SECTOR as u16);
+    set_two_bytes_at_offset(&mut boot_sector,
+                            BACKUP_BOOT_SECTOR_OFFSET,
+                            BACKUP_BOOT_SECTOR as u16);
+    boot_sector[DRIVE_NUMBER_OFFSET] = 0x80;
+    boot_sector[BOOT_SIGNATURE_OFFSET] = 0x29;
+    let volume_id = match options.volume_id {
+        Some(volume_id) => volume_id,
+        None => volume_id_of(&options.time_source.now()),
+    };
+    set_four_bytes_at_offset(&mut boot_sector, VOLUME_ID_OFFSET, volume_id);
+    boot_sector[VOLUME_LABEL_OFFSET..VOLUME_LABEL_OFFSET + 11]
+        .copy_from_slice(match volume_label {
+                             Some(ref label) => label,
+                             None => b"NO NAME    ",
+                         });
+    boot_sector[FILE_SYSTEM_TYPE_OFFSET..FILE_SYSTEM_TYPE_OFFSET + 8].copy_from_slice(b"FAT32   ");
+    set_two_bytes_at_offset(&mut boot_sector, SIGNATURE_OFFSET, 0xAA55);
+
+    // the root directory takes the first cluster
+    let mut fs_info = Vec::new();
+    fs_info.resize(byte_per_sector, 0);
+    set_four_bytes_at_offset(&mut fs_info, FS_INFO_LEAD_SIGNATURE_OFFSET, FS_INFO_LEAD_SIGNATURE);
+    set_four_bytes_at_offset(&mut fs_info,
+                             FS_INFO_STRUCT_SIGNATURE_OFFSET,
+                             FS_INFO_STRUCT_SIGNATURE);
+    set_four_bytes_at_offset(&mut fs_info,
+                             FS_INFO_FREE_COUNT_OFFSET,
+                             (number_of_clusters - 1) as u32);
+    set_four_bytes_at_offset(&mut fs_info,
+                             FS_INFO_NEXT_FREE_OFFSET,
+                             (ROOT_DIRECTORY_CLUSTER + 1) as u32);
+    set_four_bytes_at_offset(&mut fs_info,
+                             FS_INFO_TRAIL_SIGNATURE_OFFSET,
+                             FS_INFO_TRAIL_SIGNATURE);
+
+    // media type, reserved entry and the root directory
+    let mut fat = Vec::new();
+    fat.resize(byte_per_sector, 0);
+    set_four_bytes_at_offset(&mut fat, 0, 0x0FFFFF00 | MEDIA as u32);
+    set_four_bytes_at_offset(&mut fat, 4, END_OF_CHAIN);
+    set_four_bytes_at_offset(&mut fat, ROOT_DIRECTORY_CLUSTER * 4, END_OF_CHAIN);
+    for i in 0..NUMBER_OF_FATS {
+        write(block_device, NUMBER_OF_RESERVED_SECTORS + i * sectors_per_fat, &fat)?;
+    }
+
+    if let Some(ref label) = volume_label {
+        let mut root_directory = Vec::new();
+        root_directory.resize(byte_per_sector, 0);
+        root_directory[..32].copy_from_slice(&directory_entry::new_volume_label_entry(label));
+        write(block_device, data_region_sector, &root_directory)?;
+    }
+
+    // the boot sector comes last, an interrupted format leaves no half valid volume behind
+    for &offset in &[BACKUP_BOOT_SECTOR, 0] {
+        write(block_device, offset + FS_INFO_SECTOR, &fs_info)?;
+        write(block_device, offset, &boot_sector)?;
+    }
+    block_device.flush().map_err(Into::into)
+}
+
+/// sectors per FAT and number of clusters of a volume with total_sectors
+/// the FAT is sized for the clusters there would be without it, a few entries stay unused
+fn layout(total_sectors: usize, byte_per_sector: usize, cluster_size: usize) -> (usize, usize) {
+    let sectors_per_cluster = cluster_size / byte_per_sector;
+    let maximum_clusters = total_sectors.saturating_sub(NUMBER_OF_RESERVED_SECTORS) /
+                           sectors_per_cluster;
+    let sectors_per_fat = ((maximum_clusters + 2) * 4 + byte_per_sector - 1) / byte_per_sector;
+    let data_region_sector = NUMBER_OF_RESERVED_SECTORS + NUMBER_OF_FATS * sectors_per_fat;
+    (sectors_per_fat, total_sectors.saturating_sub(data_region_sector) / sectors_per_cluster)
+}
+
+/// cluster size by the size of the volume in bytes, similar to other formatters
+fn default_cluster_size(volume_size: u64) -> usize {
+    const MIB: u64 = 1024 * 1024;
+    if volume_size <= 64 * MIB {
+        512
+    } else if volume_size <= 128 * MIB {
+        1024
+    } else if volume_size <= 256 * MIB {
+        2048
+    } else if volume_size <= 8192 * MIB {
+        4096
+    } else if volume_size <= 16384 * MIB {
+        8192
+    } else if volume_size <= 32768 * MIB {
+        16384
+    } else {
+        32768
+    }
+}
+
+fn write<D>(block_device: &D, offset: usize, blocks: &[u8]) -> Result<(), StorageError>
+    where D: BlockDevice + ?Sized,
+          D::Error: Into<StorageError>
+{
+    block_device.write(offset, blocks).map_err(Into::into)
+}
+
+fn zero_blocks<D>(block_device: &D, offset: usize, number: usize) -> Result<(), StorageError>
+    where D: BlockDevice + ?Sized,
+          D::Error: Into<StorageError>
+{
+    let mut zeroes = Vec::new();
+    zeroes.resize(ZERO_BLOCKS * block_device.block_size(), 0);
+    let mut block = offset;
+    while block < offset + number {
+        let blocks = cmp::min(ZERO_BLOCKS, offset + number - block);
+        write(block_device, block, &zeroes[..blocks * block_device.block_size()])?;
+        block += blocks;
+    }
+    Ok(())
+}
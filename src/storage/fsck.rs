@@ -0,0 +1,450 @@
+use super::directory_entry::*;
+use super::error::StorageError;
+use super::fat32_device_driver::{self, Fat32DeviceDriver};
+use collections::string::*;
+use collections::vec::*;
+
+//the first two FAT entries are reserved
+const FIRST_DATA_CLUSTER: usize = 2;
+//widened to FAT32, see Fat32DeviceDriver::read_in_fat
+const BAD_CLUSTER: usize = 0x0FFFFFF7;
+
+/// an inconsistency of the volume
+/// path is the path of the file or directory, "/" for the root directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// the copy fat differs from the first FAT in number_of_blocks blocks
+    FatCopyDiffers { fat: usize, number_of_blocks: usize },
+    /// the chain leads from its last cluster back to cluster, one of its own
+    Loop { path: String, cluster: usize },
+    /// the chain leads into cluster, which belongs to another chain
+    CrossLinked { path: String, cluster: usize },
+    /// the cluster following cluster is free, bad, reserved or out of range
+    BrokenChain { path: String, cluster: usize },
+    /// the entry points to a reserved cluster or behind the last one
+    InvalidFirstCluster { path: String, cluster: usize },
+    /// the entry points to a free cluster
+    FreeCluster { path: String, cluster: usize },
+    /// the file size does not fit the length of the chain
+    WrongFileSize {
+        path: String,
+        file_size: usize,
+        number_of_clusters: usize,
+    },
+    /// clusters in use, that no directory entry leads to
+    LostChain {
+        first_cluster: usize,
+        number_of_clusters: usize,
+    },
+    /// the free count of the FSInfo sector is wrong
+    WrongFreeCount { stored: usize, counted: usize },
+}
+
+/// a problem and whether check has fixed it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    problem: Problem,
+    repaired: bool,
+}
+
+impl Finding {
+    pub fn problem(&self) -> &Problem {
+        &self.problem
+    }
+
+    pub fn is_repaired(&self) -> bool {
+        self.repaired
+    }
+}
+
+/// checks the FAT copies, the chains of all directory entries and the free count
+/// nothing is written unless repair is set, an empty result means the volume is clean
+/// repair only does, what does not lose data that is still reachable:
+/// loops and broken chains are ended, lost chains and clusters behind the file size are freed,
+/// files on free clusters are emptied, file sizes are cut to the chain
+/// and the FAT copies are overwritten with the first FAT
+/// cross-linked chains are only reported
+pub fn check(driver: &Fat32DeviceDriver, repair: bool) -> Result<Vec<Finding>, StorageError> {
+    let mut checker = Checker {
+        driver: driver,
+        repair: repair,
+        used: ClusterSet::new(driver.number_of_clusters() + FIRST_DATA_CLUSTER),
+        findings: Vec::new(),
+    };
+    // before any repair touches the copies
+    checker.compare_fat_copies()?;
+    let (free_clusters, _) = checker.count_free_clusters()?;
+    checker.check_directory_tree()?;
+    checker.check_lost_chains()?;
+
+    if let Some(stored) = driver.stored_free_cluster_count() {
+        if stored != free_clusters {
+            checker.report(Problem::WrongFreeCount {
+                               stored: stored,
+                               counted: free_clusters,
+                           },
+                           repair);
+        }
+    }
+    if repair {
+        driver.mirror_fat()?;
+        let (free_clusters, first_free_cluster) = checker.count_free_clusters()?;
+        driver.set_free_space(Some(free_clusters), first_free_cluster)?;
+    }
+    Ok(checker.findings)
+}
+
+struct Checker<'a, 'b: 'a> {
+    driver: &'a Fat32DeviceDriver<'b>,
+    repair: bool,
+    // clusters of the chains found in the directory tree
+    used: ClusterSet,
+    findings: Vec<Finding>,
+}
+
+impl<'a, 'b> Checker<'a, 'b> {
+    fn report(&mut self, problem: Problem, repaired: bool) {
+        self.findings.push(Finding {
+            problem: problem,
+            repaired: repaired,
+        });
+    }
+
+    fn compare_fat_copies(&mut self) -> Result<(), StorageError> {
+        let block_size = self.driver.block_size();
+        let mut first = Vec::new();
+        first.resize(block_size, 0);
+        let mut copy = Vec::new();
+        copy.resize(block_size, 0);
+        let mut differing = Vec::new();
+        differing.resize(self.driver.number_of_fats(), 0);
+        for index in 0..self.driver.number_of_blocks_per_fat() {
+            self.driver.read_fat_block(0, index, &mut first)?;
+            for fat in 1..self.driver.number_of_fats() {
+                self.driver.read_fat_block(fat, index, &mut copy)?;
+                if first != copy {
+                    differing[fat] += 1;
+                }
+            }
+        }
+        for fat in 1..differing.len() {
+            if differing[fat] > 0 {
+                let repair = self.repair;
+                self.report(Problem::FatCopyDiffers {
+                                fat: fat,
+                                number_of_blocks: differing[fat],
+                            },
+                            repair);
+            }
+        }
+        Ok(())
+    }
+
+    /// number of free clusters and the first of them
+    fn count_free_clusters(&self) -> Result<(usize, usize), StorageError> {
+        let mut free_clusters = 0;
+        let mut first_free_cluster = None;
+        for cluster in FIRST_DATA_CLUSTER..self.driver.number_of_clusters() + FIRST_DATA_CLUSTER {
+            if self.driver.read_in_fat(cluster)? == 0 {
+                free_clusters += 1;
+                first_free_cluster = first_free_cluster.or(Some(cluster));
+            }
+        }
+        Ok((free_clusters, first_free_cluster.unwrap_or(FIRST_DATA_CLUSTER)))
+    }
+
+    /// walks all directories beginning at the root, without recursion
+    fn check_directory_tree(&mut self) -> Result<(), StorageError> {
+        let root = self.driver.root_directory_cluster();
+        let root_chain = if self.driver.is_fixed_root_directory(root) {
+            Vec::new()
+        } else {
+            self.follow_chain("/", root)?.0
+        };
+        let mut directories = Vec::new();
+        directories.push((root, root_chain, String::new()));
+
+        while let Some((cluster, chain, path)) = directories.pop() {
+            let directory = self.read_directory(cluster, &chain)?;
+            let mut long_name = LongNameBuilder::new();
+            for index in 0..directory.len() / 32 {
+                let raw = &directory[index * 32..(index + 1) * 32];
+                match raw[0] {
+                    0x00 => break,
+                    0xE5 => {
+                        long_name.clear();
+                        continue;
+                    }
+                    _ if is_long_name_entry(raw) => {
+                        long_name.push(raw);
+                        continue;
+                    }
+                    _ => {}
+                }
+                let directory_entry = DirectoryEntry::with_long_name(raw, long_name.take(raw))?;
+                // "." and ".." point to chains, that are checked anyway
+                if raw[0] == b'.' ||
+                   !(directory_entry.is_file() || directory_entry.is_directory()) {
+                    continue;
+                }
+                let mut entry_path = path.clone();
+                entry_path.push('/');
+                entry_path.push_str(directory_entry.name());
+                if let Some(entry_chain) =
+                    self.check_entry(cluster, index, raw, &directory_entry, &entry_path)? {
+                    directories.push((directory_entry.first_cluster(), entry_chain, entry_path));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// the directory data of the (already checked) chain up to the end of directory marker
+    // sdram
+    fn read_directory(&self, cluster: usize, chain: &[usize]) -> Result<Vec<u8>, StorageError> {
+        if self.driver.is_fixed_root_directory(cluster) {
+            return self.driver.read_fixed_root_directory();
+        }
+        let cluster_size = self.driver.cluster_size();
+        let mut directory = Vec::new();
+        for cluster in chain {
+            let length = directory.len();
+            directory.resize(length + cluster_size, 0);
+            self.driver.read_cluster_data_region(*cluster, &mut directory[length..])?;
+            if directory[length..].chunks(32).any(|entry| entry[0] == 0x00) {
+                break;
+            }
+        }
+        Ok(directory)
+    }
+
+    /// checks the chain and the size of the entry with index in the directory at cluster
+    /// returns the chain of a directory that has to be walked
+    fn check_entry(&mut self,
+                   cluster: usize,
+                   index: usize,
+                   raw: &[u8],
+                   directory_entry: &DirectoryEntry,
+                   path: &str)
+                   -> Result<Option<Vec<usize>>, StorageError> {
+        let first_cluster = directory_entry.first_cluster();
+        let is_file = directory_entry.is_file();
+        // directories only get their problems reported, their content might be found again
+        let repair = self.repair && is_file;
+        let mut raw_entry = [0; 32];
+        raw_entry.copy_from_slice(raw);
+
+        if first_cluster == 0 {
+            // an empty file has no chain, an empty directory still has one
+            if !is_file {
+                self.report(Problem::InvalidFirstCluster {
+                                path: String::from(path),
+                                cluster: first_cluster,
+                            },
+                            false);
+            } else if directory_entry.file_size() > 0 {
+                self.report(Problem::WrongFileSize {
+                                path: String::from(path),
+                                file_size: directory_entry.file_size(),
+                                number_of_clusters: 0,
+                            },
+                            repair);
+                if repair {
+                    set_file_size(&mut raw_entry, 0);
+                    self.driver.write_directory_entry(cluster, index, &raw_entry)?;
+                }
+            }
+            return Ok(None);
+        }
+
+        let problem = if !self.driver.is_data_cluster(first_cluster) {
+            Some(Problem::InvalidFirstCluster {
+                path: String::from(path),
+                cluster: first_cluster,
+            })
+        } else if self.driver.read_in_fat(first_cluster)? == 0 {
+            Some(Problem::FreeCluster {
+                path: String::from(path),
+                cluster: first_cluster,
+            })
+        } else {
+            None
+        };
+        if let Some(problem) = problem {
+            self.report(problem, repair);
+            if repair {
+                set_first_cluster(&mut raw_entry, 0);
+                set_file_size(&mut raw_entry, 0);
+                self.driver.write_directory_entry(cluster, index, &raw_entry)?;
+            }
+            return Ok(None);
+        }
+
+        let (chain, is_intact) = self.follow_chain(path, first_cluster)?;
+        if !is_file {
+            return Ok(Some(chain));
+        }
+        // the size of a file in a damaged chain says nothing
+        if !is_intact {
+            return Ok(None);
+        }
+
+        let cluster_size = self.driver.cluster_size();
+        let file_size = directory_entry.file_size();
+        let needed = (file_size + cluster_size - 1) / cluster_size;
+        if needed != chain.len() {
+            self.report(Problem::WrongFileSize {
+                            path: String::from(path),
+                            file_size: file_size,
+                            number_of_clusters: chain.len(),
+                        },
+                        repair);
+            if repair && needed < chain.len() {
+                if needed == 0 {
+                    set_first_cluster(&mut raw_entry, 0);
+                    self.driver.write_directory_entry(cluster, index, &raw_entry)?;
+                } else {
+                    self.driver.write_in_fat(chain[needed - 1], fat32_device_driver::END_OF_CHAIN)?;
+                }
+                for cluster in &chain[needed..] {
+                    self.driver.write_in_fat(*cluster, 0)?;
+                    self.used.remove(*cluster);
+                }
+            } else if repair {
+                set_file_size(&mut raw_entry, chain.len() * cluster_size);
+                self.driver.write_directory_entry(cluster, index, &raw_entry)?;
+            }
+        }
+        Ok(None)
+    }
+
+    /// marks the clusters of the chain as used, up to the first one that is used already
+    /// returns the marked clusters and whether they form a proper chain
+    /// in repair mode a loop or a broken chain ends with the last marked cluster
+    fn follow_chain(&mut self,
+                    path: &str,
+                    first_cluster: usize)
+                    -> Result<(Vec<usize>, bool), StorageError> {
+        let mut chain: Vec<usize> = Vec::new();
+        let mut cluster = first_cluster;
+        loop {
+            if self.used.contains(cluster) {
+                let is_loop = chain.contains(&cluster);
+                let problem = if is_loop {
+                    Problem::Loop {
+                        path: String::from(path),
+                        cluster: cluster,
+                    }
+                } else {
+                    Problem::CrossLinked {
+                        path: String::from(path),
+                        cluster: cluster,
+                    }
+                };
+                let repair = self.repair && is_loop;
+                self.report(problem, repair);
+                if repair {
+                    let last = chain[chain.len() - 1];
+                    self.driver.write_in_fat(last, fat32_device_driver::END_OF_CHAIN)?;
+                }
+                return Ok((chain, repair));
+            }
+            self.used.insert(cluster);
+            chain.push(cluster);
+            match self.driver.next_cluster(cluster) {
+                Ok(Some(next_cluster)) => cluster = next_cluster,
+                Ok(None) => return Ok((chain, true)),
+                Err(StorageError::CorruptChain) => {
+                    let repair = self.repair;
+                    self.report(Problem::BrokenChain {
+                                    path: String::from(path),
+                                    cluster: cluster,
+                                },
+                                repair);
+                    if repair {
+                        self.driver.write_in_fat(cluster, fat32_device_driver::END_OF_CHAIN)?;
+                    }
+                    return Ok((chain, repair));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// clusters in use outside of the directory tree
+    /// chains are reported from their first cluster, loops without one from any cluster
+    fn check_lost_chains(&mut self) -> Result<(), StorageError> {
+        let end = self.driver.number_of_clusters() + FIRST_DATA_CLUSTER;
+        let mut lost = ClusterSet::new(end);
+        // clusters some lost cluster points to
+        let mut successors = ClusterSet::new(end);
+        for cluster in FIRST_DATA_CLUSTER..end {
+            if self.used.contains(cluster) {
+                continue;
+            }
+            let value = self.driver.read_in_fat(cluster)?;
+            if value != 0 && value != BAD_CLUSTER {
+                lost.insert(cluster);
+                if self.driver.is_data_cluster(value) {
+                    successors.insert(value);
+                }
+            }
+        }
+
+        for &only_first_clusters in &[true, false] {
+            for first_cluster in FIRST_DATA_CLUSTER..end {
+                if !lost.contains(first_cluster) ||
+                   (only_first_clusters && successors.contains(first_cluster)) {
+                    continue;
+                }
+                let mut chain = Vec::new();
+                let mut cluster = first_cluster;
+                while lost.contains(cluster) {
+                    lost.remove(cluster);
+                    chain.push(cluster);
+                    cluster = self.driver.read_in_fat(cluster)?;
+                    if !self.driver.is_data_cluster(cluster) {
+                        break;
+                    }
+                }
+                let repair = self.repair;
+                self.report(Problem::LostChain {
+                                first_cluster: first_cluster,
+                                number_of_clusters: chain.len(),
+                            },
+                            repair);
+                if repair {
+                    for cluster in chain {
+                        self.driver.write_in_fat(cluster, 0)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// one bit per cluster, a map of the whole FAT would not fit into memory
+struct ClusterSet {
+    bits: Vec<u8>,
+}
+
+impl ClusterSet {
+    fn new(number_of_clusters: usize) -> ClusterSet {
+        let mut bits = Vec::new();
+        bits.resize((number_of_clusters + 7) / 8, 0);
+        ClusterSet { bits: bits }
+    }
+
+    fn contains(&self, cluster: usize) -> bool {
+        self.bits[cluster / 8] & (1 << (cluster % 8)) != 0
+    }
+
+    fn insert(&mut self, cluster: usize) {
+        self.bits[cluster / 8] |= 1 << (cluster % 8);
+    }
+
+    fn remove(&mut self, cluster: usize) {
+        self.bits[cluster / 8] &= !(1 << (cluster % 8));
+    }
+}
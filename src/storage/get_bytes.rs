@@ -11,3 +11,21 @@ pub fn two_bytes_at_offset(block: &[u8], offset: usize) -> u16 {
     let second: u16 = block[offset + 1] as u16;
     (first | second << 8)
 }
+
+pub fn set_four_bytes_at_offset(block: &mut [u8], offset: usize, value: u32) {
+    block[offset] = value as u8;
+    block[offset + 1] = (value >> 8) as u8;
+    block[offset + 2] = (value >> 16) as u8;
+    block[offset + 3] = (value >> 24) as u8;
+}
+
+pub fn set_two_bytes_at_offset(block: &mut [u8], offset: usize, value: u16) {
+    block[offset] = value as u8;
+    block[offset + 1] = (value >> 8) as u8;
+}
+
+pub fn eight_bytes_at_offset(block: &[u8], offset: usize) -> u64 {
+    let low = four_bytes_at_offset(block, offset) as u64;
+    let high = four_bytes_at_offset(block, offset + 4) as u64;
+    (low | high << 32)
+}
@@ -0,0 +1,262 @@
+use block_device::BlockDevice;
+use super::crc32::crc32;
+use super::error::{self, StorageError};
+use super::get_bytes::*;
+use super::partition::Partition;
+use collections::vec::*;
+use collections::string::*;
+
+const PRIMARY_HEADER_BLOCK: usize = 1;
+const SIGNATURE: &'static [u8; 8] = b"EFI PART";
+
+//header
+const SIGNATURE_OFFSET: usize = 0x00; //8
+const HEADER_SIZE_OFFSET: usize = 0x0C; //4
+const HEADER_CRC32_OFFSET: usize = 0x10; //4
+const MY_LBA_OFFSET: usize = 0x18; //8
+const DISK_GUID_OFFSET: usize = 0x38; //16
+const PARTITION_ENTRY_LBA_OFFSET: usize = 0x48; //8
+const NUMBER_OF_PARTITION_ENTRIES_OFFSET: usize = 0x50; //4
+const SIZE_OF_PARTITION_ENTRY_OFFSET: usize = 0x54; //4
+const PARTITION_ENTRY_ARRAY_CRC32_OFFSET: usize = 0x58; //4
+const MIN_HEADER_SIZE: usize = 92;
+const MIN_PARTITION_ENTRY_SIZE: usize = 128;
+//128 entries of 128 bytes, what every partitioning tool writes
+const MAX_PARTITION_ENTRY_ARRAY_SIZE: usize = 16 * 1024;
+
+//partition entry
+const PARTITION_TYPE_GUID_OFFSET: usize = 0x00; //16
+const UNIQUE_PARTITION_GUID_OFFSET: usize = 0x10; //16
+const STARTING_LBA_OFFSET: usize = 0x20; //8
+const ENDING_LBA_OFFSET: usize = 0x28; //8
+const ATTRIBUTES_OFFSET: usize = 0x30; //8
+const PARTITION_NAME_OFFSET: usize = 0x38; //72
+
+//MBR
+const PARTITION_TABLE_OFFSET: usize = 0x01BE;
+const MBR_SIGNATURE_OFFSET: usize = 0x01FE;
+const PROTECTIVE_MBR_TYPE: u8 = 0xEE;
+
+/// as stored on the disk: the first three fields are little endian
+pub type Guid = [u8; 16];
+
+pub const UNUSED_PARTITION: Guid = [0; 16];
+/// EBD0A0A2-B9E5-4433-87C0-68B6B72699C7, FAT and exFAT partitions of Windows
+pub const BASIC_DATA_PARTITION: Guid = [0xA2, 0xA0, 0xD0, 0xEB, 0xE5, 0xB9, 0x33, 0x44, 0x87,
+                                        0xC0, 0x68, 0xB6, 0xB7, 0x26, 0x99, 0xC7];
+/// C12A7328-F81F-11D2-BA4B-00A0C93EC93B
+pub const EFI_SYSTEM_PARTITION: Guid = [0x28, 0x73, 0x2A, 0xC1, 0x1F, 0xF8, 0xD2, 0x11, 0xBA,
+                                        0x4B, 0x00, 0xA0, 0xC9, 0x3E, 0xC9, 0x3B];
+
+pub struct GptPartitionEntry {
+    partition_type_guid: Guid,
+    unique_partition_guid: Guid,
+    first_block: usize,
+    last_block: usize,
+    attributes: u64,
+    name: String,
+}
+
+impl GptPartitionEntry {
+    fn new(partition_entry: &[u8]) -> GptPartitionEntry {
+        let mut name = Vec::with_capacity(36);
+        for i in 0..36 {
+            let character = two_bytes_at_offset(partition_entry, PARTITION_NAME_OFFSET + i * 2);
+            if character == 0 {
+                break;
+            }
+            name.push(character);
+        }
+
+        GptPartitionEntry {
+            partition_type_guid: guid_at_offset(partition_entry, PARTITION_TYPE_GUID_OFFSET),
+            unique_partition_guid: guid_at_offset(partition_entry, UNIQUE_PARTITION_GUID_OFFSET),
+            first_block: eight_bytes_at_offset(partition_entry, STARTING_LBA_OFFSET) as usize,
+            last_block: eight_bytes_at_offset(partition_entry, ENDING_LBA_OFFSET) as usize,
+            attributes: eight_bytes_at_offset(partition_entry, ATTRIBUTES_OFFSET),
+            name: String::from_utf16_lossy(&name),
+        }
+    }
+
+    pub fn partition_type_guid(&self) -> &Guid {
+        &self.partition_type_guid
+    }
+
+    pub fn unique_partition_guid(&self) -> &Guid {
+        &self.unique_partition_guid
+    }
+
+    pub fn first_block(&self) -> usize {
+        self.first_block
+    }
+
+    /// inclusive
+    pub fn last_block(&self) -> usize {
+        self.last_block
+    }
+
+    pub fn attributes(&self) -> u64 {
+        self.attributes
+    }
+
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+}
+
+/// GUID partition table
+/// the backup header at the end of the device is used, if the primary one is damaged
+pub struct GptDeviceDriver<'a, D: BlockDevice + ?Sized + 'a> {
+    block_device: &'a D,
+    disk_guid: Guid,
+    partition_entries: Vec<GptPartitionEntry>,
+    uses_backup_header: bool,
+}
+
+impl<'a, D> GptDeviceDriver<'a, D>
+    where D: BlockDevice + ?Sized + 'a,
+          D::Error: Into<StorageError>
+{
+    /// is_protective_mbr(...) should be checked before
+    /// fails with BadSignature, if neither header is valid
+    pub fn new(block_device: &'a D) -> Result<GptDeviceDriver<'a, D>, StorageError> {
+        if !(block_device.block_size() >= 512 && block_device.block_size() % 512 == 0) {
+            return Err(StorageError::InvalidBlockSize);
+        }
+
+        let mut uses_backup_header = false;
+        let mut header = read_header(block_device, PRIMARY_HEADER_BLOCK);
+        if header.is_none() && block_device.number_of_blocks() > PRIMARY_HEADER_BLOCK + 1 {
+            uses_backup_header = true;
+            header = read_header(block_device, block_device.number_of_blocks() - 1);
+        }
+        let (header, entries) = match header {
+            Some(h) => h,
+            None => return Err(StorageError::BadSignature),
+        };
+
+        let number = four_bytes_at_offset(&header, NUMBER_OF_PARTITION_ENTRIES_OFFSET) as usize;
+        let size = four_bytes_at_offset(&header, SIZE_OF_PARTITION_ENTRY_OFFSET) as usize;
+        let number_of_blocks = block_device.number_of_blocks() as u64;
+        let mut partition_entries = Vec::new();
+        for i in 0..number {
+            let partition_entry = &entries[i * size..(i + 1) * size];
+            if guid_at_offset(partition_entry, PARTITION_TYPE_GUID_OFFSET) == UNUSED_PARTITION {
+                continue;
+            }
+            // entries that are empty or end behind the device are skipped
+            let first_block = eight_bytes_at_offset(partition_entry, STARTING_LBA_OFFSET);
+            let last_block = eight_bytes_at_offset(partition_entry, ENDING_LBA_OFFSET);
+            if last_block < first_block || last_block >= number_of_blocks {
+                continue;
+            }
+            partition_entries.push(GptPartitionEntry::new(partition_entry));
+        }
+
+        Ok(GptDeviceDriver {
+            block_device: block_device,
+            disk_guid: guid_at_offset(&header, DISK_GUID_OFFSET),
+            partition_entries: partition_entries,
+            uses_backup_header: uses_backup_header,
+        })
+    }
+
+    pub fn disk_guid(&self) -> &Guid {
+        &self.disk_guid
+    }
+
+    /// true if the primary header was damaged
+    pub fn uses_backup_header(&self) -> bool {
+        self.uses_backup_header
+    }
+
+    /// only the used entries, in the order of the table
+    pub fn partition_entries(&self) -> &[GptPartitionEntry] {
+        &self.partition_entries
+    }
+
+    /// the partition type of the returned Partition is 0, there is no MBR type
+    pub fn partition(&self, index: usize) -> Option<Partition<'a, D>> {
+        self.partition_entries.get(index).map(|entry| {
+            Partition::with_range(self.block_device,
+                                  0,
+                                  entry.first_block,
+                                  entry.last_block + 1 - entry.first_block)
+        })
+    }
+}
+
+/// a GPT disk has an MBR with a single entry of type 0xEE spanning the disk
+/// without the MBR signature the entries are garbage, e.g. boot code of a superfloppy
+pub fn is_protective_mbr<D>(block_device: &D) -> Result<bool, StorageError>
+    where D: BlockDevice + ?Sized,
+          D::Error: Into<StorageError>
+{
+    let mbr = error::read_blocks(block_device, 0, 1)?;
+    if two_bytes_at_offset(&mbr, MBR_SIGNATURE_OFFSET) != 0xAA55 {
+        return Ok(false);
+    }
+    Ok((0..4).any(|i| mbr[PARTITION_TABLE_OFFSET + i * 16 + 4] == PROTECTIVE_MBR_TYPE))
+}
+
+/// returns the header and the partition entry array, if both checksums are right
+/// unreadable blocks count as invalid
+fn read_header<D>(block_device: &D, block_number: usize) -> Option<(Vec<u8>, Vec<u8>)>
+    where D: BlockDevice + ?Sized,
+          D::Error: Into<StorageError>
+{
+    let block_size = block_device.block_size();
+    let mut header = match error::read_blocks(block_device, block_number, 1) {
+        Ok(header) => header,
+        Err(_) => return None,
+    };
+    if &header[SIGNATURE_OFFSET..SIGNATURE_OFFSET + 8] != SIGNATURE {
+        return None;
+    }
+    let header_size = four_bytes_at_offset(&header, HEADER_SIZE_OFFSET) as usize;
+    if header_size < MIN_HEADER_SIZE || header_size > block_size ||
+       eight_bytes_at_offset(&header, MY_LBA_OFFSET) != block_number as u64 {
+        return None;
+    }
+
+    // the checksum is computed with its own field set to 0
+    let header_crc32 = four_bytes_at_offset(&header, HEADER_CRC32_OFFSET);
+    set_four_bytes_at_offset(&mut header, HEADER_CRC32_OFFSET, 0);
+    if crc32(&header[..header_size]) != header_crc32 {
+        return None;
+    }
+    set_four_bytes_at_offset(&mut header, HEADER_CRC32_OFFSET, header_crc32);
+
+    // the entry size is 128 * 2^n (128, 256, ...), the entry array is read into memory,
+    // it has to be of a sane size and fit on the device behind its first block
+    let number = four_bytes_at_offset(&header, NUMBER_OF_PARTITION_ENTRIES_OFFSET) as u64;
+    let size = four_bytes_at_offset(&header, SIZE_OF_PARTITION_ENTRY_OFFSET) as u64;
+    let min_size = MIN_PARTITION_ENTRY_SIZE as u64;
+    if size % min_size != 0 || !(size / min_size).is_power_of_two() {
+        return None;
+    }
+    let entries_block = eight_bytes_at_offset(&header, PARTITION_ENTRY_LBA_OFFSET);
+    let number_of_blocks = block_device.number_of_blocks() as u64;
+    if number * size > MAX_PARTITION_ENTRY_ARRAY_SIZE as u64 || entries_block >= number_of_blocks ||
+       number * size > (number_of_blocks - entries_block) * block_size as u64 {
+        return None;
+    }
+    let (number, size) = (number as usize, size as usize);
+    let mut entries = match error::read_blocks(block_device,
+                                               entries_block as usize,
+                                               (number * size + block_size - 1) / block_size) {
+        Ok(entries) => entries,
+        Err(_) => return None,
+    };
+    entries.truncate(number * size);
+    if crc32(&entries) != four_bytes_at_offset(&header, PARTITION_ENTRY_ARRAY_CRC32_OFFSET) {
+        return None;
+    }
+    Some((header, entries))
+}
+
+fn guid_at_offset(block: &[u8], offset: usize) -> Guid {
+    let mut guid = [0; 16];
+    guid.copy_from_slice(&block[offset..offset + 16]);
+    guid
+}
@@ -1,31 +1,370 @@
 use block_device::BlockDevice;
+use super::crc32;
+use super::error::{self, StorageError};
+use super::get_bytes::*;
 use super::partition::Partition;
 use collections::vec::*;
 
+//everything in front of it is boot code
+const DISK_SIGNATURE_OFFSET: usize = 0x01B8;
 const PARTITION_TABLE_OFFSET: usize = 0x01BE;
+const SIGNATURE_OFFSET: usize = 0x01FE;
+const BOOT_FLAG_OFFSET: usize = 0x00;
+const CHS_FIRST_SECTOR_OFFSET: usize = 0x01;
+const TYPE_OFFSET: usize = 0x04;
+const CHS_LAST_SECTOR_OFFSET: usize = 0x05;
+const LBA_FIRST_SECTOR_OFFSET: usize = 0x08;
+const LBA_NUMBER_OF_SECTORS_OFFSET: usize = 0x0C;
+const BOOTABLE: u8 = 0x80;
+//geometry used for the CHS fields, LBA is what counts
+const HEADS: usize = 255;
+const SECTORS_PER_TRACK: usize = 63;
+const MAX_CYLINDER: usize = 1023;
 
-pub struct MbrDeviceDriver<'a> {
-    first_partition: Partition<'a>,
+/// 4 MiB in 512 byte blocks, the allocation unit of most SDHC and SDXC cards
+/// partitions starting on it don't share erase blocks with the partition table
+pub const DEFAULT_ALIGNMENT: usize = 8192;
+
+//CHS and LBA addressed extended partitions
+const EXTENDED_PARTITION_TYPES: [u8; 2] = [0x05, 0x0F];
+//protects against EBR chains pointing back
+const MAX_LOGICAL_PARTITIONS: usize = 128;
+
+pub struct MbrDeviceDriver<'a, D: BlockDevice + ?Sized + 'a> {
+    first_partition: Partition<'a, D>,
+    partitions: Vec<Partition<'a, D>>,
 }
 
-impl<'a> MbrDeviceDriver<'a> {
-    pub fn new(block_device: &'a BlockDevice) -> MbrDeviceDriver<'a> {
+impl<'a, D> MbrDeviceDriver<'a, D>
+    where D: BlockDevice + ?Sized + 'a,
+          D::Error: Into<StorageError>
+{
+    pub fn new(block_device: &'a D) -> Result<MbrDeviceDriver<'a, D>, StorageError> {
         if !(block_device.block_size() >= 512 && block_device.block_size() % 512 == 0) {
-            panic!("512");
+            return Err(StorageError::InvalidBlockSize);
         }
 
-        let mbr = block_device.read_blocks(0, 1);
+        let mbr = error::read_blocks(block_device, 0, 1)?;
+        if two_bytes_at_offset(&mbr, SIGNATURE_OFFSET) != 0xAA55 {
+            return Err(StorageError::BadSignature);
+        }
 
-        let mut first_entry: Vec<u8> = Vec::with_capacity(16);
-        for i in 0..16 {
-            first_entry.push(mbr[PARTITION_TABLE_OFFSET + i]);
+        let first_partition = Partition::new(block_device,
+                                             &mbr[PARTITION_TABLE_OFFSET..
+                                                  PARTITION_TABLE_OFFSET + 16])?;
+
+        let mut partitions = Vec::with_capacity(4);
+        let mut extended_start_block = None;
+        for i in 0..4 {
+            let offset = PARTITION_TABLE_OFFSET + i * 16;
+            let entry = &mbr[offset..offset + 16];
+            let partition_type = entry[TYPE_OFFSET];
+            if partition_type == 0 {
+                continue;
+            }
+            if EXTENDED_PARTITION_TYPES.contains(&partition_type) {
+                extended_start_block =
+                    Some(four_bytes_at_offset(entry, LBA_FIRST_SECTOR_OFFSET) as usize);
+            } else {
+                partitions.push(Partition::new(block_device, entry)?);
+            }
+        }
+        // logical partitions come after the primary ones, like partition 5, 6, ... in Linux
+        if let Some(start_block) = extended_start_block {
+            read_logical_partitions(block_device, start_block, &mut partitions);
         }
-        let first_partition = Partition::new(block_device, &first_entry);
 
-        MbrDeviceDriver { first_partition: first_partition }
+        Ok(MbrDeviceDriver {
+            first_partition: first_partition,
+            partitions: partitions,
+        })
     }
 
-    pub fn get_first_partition(&self) -> &Partition {
+    pub fn get_first_partition(&self) -> &Partition<'a, D> {
         &self.first_partition
     }
+
+    /// all primary and logical partitions, without empty entries and the extended partition
+    pub fn partitions(&self) -> &[Partition<'a, D>] {
+        &self.partitions
+    }
+}
+
+/// follows the chain of extended boot records (EBR)
+/// the first entry of an EBR is the logical partition, relative to the EBR,
+/// the second one points to the next EBR, relative to the extended partition
+/// a damaged EBR ends the chain, the partitions found so far are kept
+fn read_logical_partitions<'a, D>(block_device: &'a D,
+                                  extended_start_block: usize,
+                                  partitions: &mut Vec<Partition<'a, D>>)
+    where D: BlockDevice + ?Sized + 'a,
+          D::Error: Into<StorageError>
+{
+    let mut ebr_block = extended_start_block;
+    for _ in 0..MAX_LOGICAL_PARTITIONS {
+        let ebr = match error::read_blocks(block_device, ebr_block, 1) {
+            Ok(ebr) => ebr,
+            Err(_) => return,
+        };
+        if two_bytes_at_offset(&ebr, SIGNATURE_OFFSET) != 0xAA55 {
+            return;
+        }
+
+        let logical = &ebr[PARTITION_TABLE_OFFSET..PARTITION_TABLE_OFFSET + 16];
+        if logical[TYPE_OFFSET] != 0 {
+            let start_block = four_bytes_at_offset(logical, LBA_FIRST_SECTOR_OFFSET) as usize;
+            let block_count = four_bytes_at_offset(logical, LBA_NUMBER_OF_SECTORS_OFFSET) as
+                              usize;
+            partitions.push(Partition::with_range(block_device,
+                                                  logical[TYPE_OFFSET],
+                                                  ebr_block + start_block,
+                                                  block_count));
+        }
+
+        let next = &ebr[PARTITION_TABLE_OFFSET + 16..PARTITION_TABLE_OFFSET + 32];
+        if !EXTENDED_PARTITION_TYPES.contains(&next[TYPE_OFFSET]) {
+            return;
+        }
+        ebr_block = extended_start_block +
+                    four_bytes_at_offset(next, LBA_FIRST_SECTOR_OFFSET) as usize;
+    }
+}
+
+/// one primary entry of an MBR partition table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MbrPartitionEntry {
+    partition_type: u8,
+    start_block: usize,
+    block_count: usize,
+    bootable: bool,
+}
+
+impl MbrPartitionEntry {
+    pub fn new(partition_type: u8, start_block: usize, block_count: usize) -> MbrPartitionEntry {
+        MbrPartitionEntry {
+            partition_type: partition_type,
+            start_block: start_block,
+            block_count: block_count,
+            bootable: false,
+        }
+    }
+
+    pub fn get_partition_type(&self) -> u8 {
+        self.partition_type
+    }
+
+    pub fn get_start_block(&self) -> usize {
+        self.start_block
+    }
+
+    pub fn get_block_count(&self) -> usize {
+        self.block_count
+    }
+
+    pub fn is_bootable(&self) -> bool {
+        self.bootable
+    }
+
+    fn end_block(&self) -> usize {
+        self.start_block + self.block_count
+    }
+
+    fn to_bytes(&self) -> [u8; 16] {
+        let mut entry = [0; 16];
+        entry[BOOT_FLAG_OFFSET] = if self.bootable { BOOTABLE } else { 0 };
+        entry[CHS_FIRST_SECTOR_OFFSET..CHS_FIRST_SECTOR_OFFSET + 3]
+            .copy_from_slice(&chs(self.start_block));
+        entry[TYPE_OFFSET] = self.partition_type;
+        entry[CHS_LAST_SECTOR_OFFSET..CHS_LAST_SECTOR_OFFSET + 3]
+            .copy_from_slice(&chs(self.end_block() - 1));
+        set_four_bytes_at_offset(&mut entry, LBA_FIRST_SECTOR_OFFSET, self.start_block as u32);
+        set_four_bytes_at_offset(&mut entry,
+                                 LBA_NUMBER_OF_SECTORS_OFFSET,
+                                 self.block_count as u32);
+        entry
+    }
+}
+
+/// the four primary entries of block 0, to create or edit them
+/// logical partitions are kept as they are, as long as the extended entry stays
+pub struct MbrPartitionTable {
+    number_of_blocks: usize,
+    disk_signature: u32,
+    entries: [Option<MbrPartitionEntry>; 4],
+}
+
+impl MbrPartitionTable {
+    /// an empty table for a disk of number_of_blocks blocks, see disk_signature(...)
+    pub fn new(number_of_blocks: usize, disk_signature: u32) -> MbrPartitionTable {
+        MbrPartitionTable {
+            number_of_blocks: number_of_blocks,
+            disk_signature: disk_signature,
+            entries: [None; 4],
+        }
+    }
+
+    /// the table of block_device, BadSignature if there is none
+    pub fn read<D>(block_device: &D) -> Result<MbrPartitionTable, StorageError>
+        where D: BlockDevice + ?Sized,
+              D::Error: Into<StorageError>
+    {
+        let mbr = error::read_blocks(block_device, 0, 1)?;
+        if two_bytes_at_offset(&mbr, SIGNATURE_OFFSET) != 0xAA55 {
+            return Err(StorageError::BadSignature);
+        }
+        let mut table = MbrPartitionTable::new(block_device.number_of_blocks(),
+                                               four_bytes_at_offset(&mbr, DISK_SIGNATURE_OFFSET));
+        for i in 0..4 {
+            let offset = PARTITION_TABLE_OFFSET + i * 16;
+            let entry = &mbr[offset..offset + 16];
+            if entry[TYPE_OFFSET] != 0 {
+                table.entries[i] = Some(MbrPartitionEntry {
+                    partition_type: entry[TYPE_OFFSET],
+                    start_block: four_bytes_at_offset(entry, LBA_FIRST_SECTOR_OFFSET) as usize,
+                    block_count: four_bytes_at_offset(entry, LBA_NUMBER_OF_SECTORS_OFFSET) as
+                                 usize,
+                    bootable: entry[BOOT_FLAG_OFFSET] == BOOTABLE,
+                });
+            }
+        }
+        Ok(table)
+    }
+
+    pub fn disk_signature(&self) -> u32 {
+        self.disk_signature
+    }
+
+    pub fn set_disk_signature(&mut self, disk_signature: u32) {
+        self.disk_signature = disk_signature;
+    }
+
+    pub fn entry(&self, index: usize) -> Option<MbrPartitionEntry> {
+        self.entries.get(index).and_then(|entry| *entry)
+    }
+
+    /// None clears the entry
+    /// OutOfRange if the partition is empty, starts in block 0, ends behind the disk
+    /// or overlaps another one
+    pub fn set_entry(&mut self,
+                     index: usize,
+                     entry: Option<MbrPartitionEntry>)
+                     -> Result<(), StorageError> {
+        if index >= 4 {
+            return Err(StorageError::OutOfRange);
+        }
+        if let Some(ref entry) = entry {
+            if entry.partition_type == 0 || entry.start_block == 0 || entry.block_count == 0 ||
+               entry.end_block() > self.number_of_blocks ||
+               entry.end_block() > u32::max_value() as usize {
+                return Err(StorageError::OutOfRange);
+            }
+            let overlaps = self.entries
+                .iter()
+                .enumerate()
+                .filter_map(|(i, other)| if i == index { None } else { *other })
+                .any(|other| {
+                    entry.start_block < other.end_block() && other.start_block < entry.end_block()
+                });
+            if overlaps {
+                return Err(StorageError::OutOfRange);
+            }
+        }
+        self.entries[index] = entry;
+        Ok(())
+    }
+
+    /// puts a partition into the first free entry, behind the last partition
+    /// its start is rounded up to a multiple of alignment blocks, see DEFAULT_ALIGNMENT,
+    /// block_count None takes the rest of the disk
+    /// returns the index of the entry, NoSpace if no entry or not enough blocks are left
+    pub fn add_partition(&mut self,
+                         partition_type: u8,
+                         block_count: Option<usize>,
+                         alignment: usize)
+                         -> Result<usize, StorageError> {
+        let index = match self.entries.iter().position(|entry| entry.is_none()) {
+            Some(index) => index,
+            None => return Err(StorageError::NoSpace),
+        };
+        let alignment = if alignment == 0 { 1 } else { alignment };
+        let end_of_last = self.entries
+            .iter()
+            .filter_map(|entry| entry.map(|e| e.end_block()))
+            .max()
+            .unwrap_or(1);
+        let start_block = (end_of_last + alignment - 1) / alignment * alignment;
+        if start_block >= self.number_of_blocks {
+            return Err(StorageError::NoSpace);
+        }
+        let block_count = block_count.unwrap_or(self.number_of_blocks - start_block);
+        if block_count > self.number_of_blocks - start_block {
+            return Err(StorageError::NoSpace);
+        }
+        self.set_entry(index,
+                       Some(MbrPartitionEntry::new(partition_type, start_block, block_count)))?;
+        Ok(index)
+    }
+
+    /// marks the partition at index as the one to boot from, the others are cleared
+    pub fn set_bootable(&mut self, index: usize) -> Result<(), StorageError> {
+        if self.entry(index).is_none() {
+            return Err(StorageError::NotFound);
+        }
+        for (i, entry) in self.entries.iter_mut().enumerate() {
+            if let Some(ref mut entry) = *entry {
+                entry.bootable = i == index;
+            }
+        }
+        Ok(())
+    }
+
+    /// writes block 0, the boot code of a valid MBR is kept
+    pub fn write<D>(&self, block_device: &D) -> Result<(), StorageError>
+        where D: BlockDevice + ?Sized,
+              D::Error: Into<StorageError>
+    {
+        if !(block_device.block_size() >= 512 && block_device.block_size() % 512 == 0) {
+            return Err(StorageError::InvalidBlockSize);
+        }
+        let mut mbr = error::read_blocks(block_device, 0, 1)?;
+        if two_bytes_at_offset(&mbr, SIGNATURE_OFFSET) != 0xAA55 {
+            for byte in mbr.iter_mut() {
+                *byte = 0;
+            }
+        }
+        set_four_bytes_at_offset(&mut mbr, DISK_SIGNATURE_OFFSET, self.disk_signature);
+        set_two_bytes_at_offset(&mut mbr, DISK_SIGNATURE_OFFSET + 4, 0);
+        for (i, entry) in self.entries.iter().enumerate() {
+            let offset = PARTITION_TABLE_OFFSET + i * 16;
+            let bytes = match *entry {
+                Some(ref entry) => entry.to_bytes(),
+                None => [0; 16],
+            };
+            mbr[offset..offset + 16].copy_from_slice(&bytes);
+        }
+        set_two_bytes_at_offset(&mut mbr, SIGNATURE_OFFSET, 0xAA55);
+        block_device.write(0, &mbr).map_err(Into::into)?;
+        block_device.flush().map_err(Into::into)
+    }
+}
+
+/// a disk signature out of something that differs between cards and runs,
+/// e.g. the CID of the card followed by the system tick count
+/// 0 means "no signature" to some systems and is never returned
+pub fn disk_signature(seed: &[u8]) -> u32 {
+    match crc32::crc32(seed) {
+        0 => 1,
+        signature => signature,
+    }
+}
+
+/// head, sector and cylinder as stored in an entry, saturated for large disks
+fn chs(lba: usize) -> [u8; 3] {
+    let cylinder = lba / (HEADS * SECTORS_PER_TRACK);
+    if cylinder > MAX_CYLINDER {
+        return [0xFE, 0xFF, 0xFF];
+    }
+    let head = lba / SECTORS_PER_TRACK % HEADS;
+    let sector = lba % SECTORS_PER_TRACK + 1;
+    [head as u8, (sector as u8) | ((cylinder >> 2) as u8 & 0xC0), cylinder as u8]
 }
@@ -1,5 +1,22 @@
+pub mod append_writer;
+pub mod attributes;
+pub mod block_cache;
+pub mod crc32;
+pub mod date_time;
 pub mod directory_entry;
+pub mod error;
+pub mod exfat_device_driver;
 pub mod fat32_device_driver;
+pub mod fat_cache;
+pub mod file;
+#[cfg(feature = "std")]
+pub mod file_block_device;
+pub mod format;
+pub mod fsck;
 pub mod get_bytes;
+pub mod gpt_device_driver;
 pub mod mbr_device_driver;
+pub mod mount;
 pub mod partition;
+pub mod ram_disk;
+pub mod read_dir;
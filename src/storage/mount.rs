@@ -0,0 +1,181 @@
+use block_device::BlockDevice;
+use super::error::{self, StorageError};
+use super::exfat_device_driver::ExFatDeviceDriver;
+use super::fat32_device_driver::Fat32DeviceDriver;
+use super::get_bytes::*;
+use super::gpt_device_driver::{self, GptDeviceDriver};
+use super::mbr_device_driver::MbrDeviceDriver;
+use super::partition::Partition;
+
+const SIGNATURE_OFFSET: usize = 0x01FE;
+const JUMP_OFFSET: usize = 0x00;
+const FILE_SYSTEM_NAME_OFFSET: usize = 0x03; //8
+const BYTE_PER_SECTOR_OFFSET: usize = 0x0B;
+const SECTORS_PER_CLUSTER_OFFSET: usize = 0x0D;
+const NUMBER_OF_RESERVED_SECTORS_OFFSET: usize = 0x0E;
+const NUMBER_OF_FATS_OFFSET: usize = 0x10;
+
+const FAT_PARTITION_TYPES: [u8; 6] = [0x01, 0x04, 0x06, 0x0B, 0x0C, 0x0E];
+//shared with NTFS, the boot sector tells them apart
+const EXFAT_PARTITION_TYPE: u8 = 0x07;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileSystemType {
+    /// FAT12, FAT16 or FAT32
+    Fat,
+    ExFat,
+}
+
+pub enum FileSystem<'a> {
+    Fat(Fat32DeviceDriver<'a>),
+    ExFat(ExFatDeviceDriver<'a>),
+}
+
+/// the first supported file system found by mount(...)
+/// the driver borrows the partition owned by the volume, see file_system()
+pub struct Volume<'a, D: BlockDevice + ?Sized + 'a> {
+    partition: Partition<'a, D>,
+    file_system_type: FileSystemType,
+}
+
+impl<'a, D> Volume<'a, D>
+    where D: BlockDevice + ?Sized + 'a,
+          D::Error: Into<StorageError>
+{
+    pub fn partition(&self) -> &Partition<'a, D> {
+        &self.partition
+    }
+
+    pub fn file_system_type(&self) -> FileSystemType {
+        self.file_system_type
+    }
+
+    /// the driver borrows the volume mutably, there is never a second one at the same time:
+    /// each FAT driver keeps its own free cluster count and would write it over the other's
+    pub fn file_system(&mut self) -> Result<FileSystem, StorageError> {
+        open_file_system(&self.partition, self.file_system_type)
+    }
+}
+
+/// finds the first supported file system on block_device
+/// block_device can have a GPT, an MBR or no partition table at all (superfloppy)
+/// a partition whose boot sector the driver refuses is skipped, its error is returned
+/// if no other partition can be mounted
+pub fn mount<'a, D>(block_device: &'a D) -> Result<Volume<'a, D>, StorageError>
+    where D: BlockDevice + ?Sized + 'a,
+          D::Error: Into<StorageError>
+{
+    if !(block_device.block_size() >= 512 && block_device.block_size() % 512 == 0) {
+        return Err(StorageError::InvalidBlockSize);
+    }
+    let block = error::read_blocks(block_device, 0, 1)?;
+    if two_bytes_at_offset(&block, SIGNATURE_OFFSET) != 0xAA55 {
+        return Err(StorageError::BadSignature);
+    }
+
+    // a boot sector instead of a partition table
+    if detect_file_system(&block).is_some() {
+        let whole = Partition::with_range(block_device, 0, 0, block_device.number_of_blocks());
+        if let Some(volume) = volume_of_partition(whole)? {
+            return Ok(volume);
+        }
+    }
+
+    let mut first_error = None;
+    if gpt_device_driver::is_protective_mbr(block_device)? {
+        let gpt = GptDeviceDriver::new(block_device)?;
+        if gpt.partition_entries().is_empty() {
+            return Err(StorageError::NoPartition);
+        }
+        for i in 0..gpt.partition_entries().len() {
+            if let Some(partition) = gpt.partition(i) {
+                match volume_of_partition(partition) {
+                    Ok(Some(volume)) => return Ok(volume),
+                    Ok(None) => {}
+                    Err(e) => first_error = first_error.or(Some(e)),
+                }
+            }
+        }
+        return Err(first_error.unwrap_or(StorageError::UnsupportedFileSystem));
+    }
+
+    let mbr = MbrDeviceDriver::new(block_device)?;
+    let first_type = match mbr.partitions().first() {
+        Some(p) => p.get_partition_type(),
+        None => return Err(StorageError::NoPartition),
+    };
+    for partition in mbr.partitions() {
+        let partition_type = partition.get_partition_type();
+        if !FAT_PARTITION_TYPES.contains(&partition_type) &&
+           partition_type != EXFAT_PARTITION_TYPE {
+            continue;
+        }
+        let copy = Partition::with_range(block_device,
+                                         partition_type,
+                                         partition.get_start_block(),
+                                         partition.number_of_blocks());
+        match volume_of_partition(copy) {
+            Ok(Some(volume)) => return Ok(volume),
+            Ok(None) => {}
+            Err(e) => first_error = first_error.or(Some(e)),
+        }
+    }
+    Err(first_error.unwrap_or(StorageError::UnsupportedPartitionType(first_type)))
+}
+
+/// None if the partition holds no file system a driver knows, e.g. NTFS
+/// a FAT partition type or a detected boot sector is opened by the driver,
+/// its error is returned if it fails
+fn volume_of_partition<'a, D>(partition: Partition<'a, D>)
+                              -> Result<Option<Volume<'a, D>>, StorageError>
+    where D: BlockDevice + ?Sized + 'a,
+          D::Error: Into<StorageError>
+{
+    let block = error::read_blocks(&partition, 0, 1)?;
+    let detected = if two_bytes_at_offset(&block, SIGNATURE_OFFSET) == 0xAA55 {
+        detect_file_system(&block)
+    } else {
+        None
+    };
+    let file_system_type = match detected {
+        Some(file_system_type) => file_system_type,
+        None if FAT_PARTITION_TYPES.contains(&partition.get_partition_type()) => {
+            FileSystemType::Fat
+        }
+        None => return Ok(None),
+    };
+    open_file_system(&partition, file_system_type)?;
+    Ok(Some(Volume {
+        partition: partition,
+        file_system_type: file_system_type,
+    }))
+}
+
+fn open_file_system<'a>(block_device: &'a BlockDevice<Error = StorageError>,
+                        file_system_type: FileSystemType)
+                        -> Result<FileSystem<'a>, StorageError> {
+    Ok(match file_system_type {
+        FileSystemType::Fat => FileSystem::Fat(Fat32DeviceDriver::new(block_device)?),
+        FileSystemType::ExFat => FileSystem::ExFat(ExFatDeviceDriver::new(block_device)?),
+    })
+}
+
+/// recognizes exFAT by its name and FAT by a plausible BIOS parameter block
+pub fn detect_file_system(boot_sector: &[u8]) -> Option<FileSystemType> {
+    if &boot_sector[FILE_SYSTEM_NAME_OFFSET..FILE_SYSTEM_NAME_OFFSET + 8] == b"EXFAT   " {
+        return Some(FileSystemType::ExFat);
+    }
+
+    let jump = boot_sector[JUMP_OFFSET];
+    let byte_per_sector = two_bytes_at_offset(boot_sector, BYTE_PER_SECTOR_OFFSET);
+    let sectors_per_cluster = boot_sector[SECTORS_PER_CLUSTER_OFFSET];
+    let number_of_reserved_sectors = two_bytes_at_offset(boot_sector,
+                                                         NUMBER_OF_RESERVED_SECTORS_OFFSET);
+    let number_of_fats = boot_sector[NUMBER_OF_FATS_OFFSET];
+    if (jump == 0xEB || jump == 0xE9) && byte_per_sector >= 512 && byte_per_sector <= 4096 &&
+       byte_per_sector.is_power_of_two() && sectors_per_cluster.is_power_of_two() &&
+       number_of_reserved_sectors != 0 && number_of_fats != 0 {
+        return Some(FileSystemType::Fat);
+    }
+    None
+}
@@ -1,6 +1,6 @@
 use block_device::BlockDevice;
+use super::error::StorageError;
 use super::get_bytes::*;
-use collections::vec::*;
 
 //const CHS_FIRST_SECTOR_OFFSET: usize = 0x01;
 const TYPE_OFFSET: usize = 0x04;
@@ -8,18 +8,22 @@ const TYPE_OFFSET: usize = 0x04;
 const LBA_FIRST_SECTOR_OFFSET: usize = 0x08;
 const LBA_NUMBER_OF_SECTORS_OFFSET: usize = 0x0C;
 
-pub struct Partition<'a> {
-    block_device: &'a BlockDevice,
+/// a range of blocks on block_device
+/// the errors of block_device are converted, a partition always fails with StorageError
+pub struct Partition<'a, D: BlockDevice + ?Sized + 'a> {
+    block_device: &'a D,
     partition_type: u8,
     start_block: usize,
     block_count: usize,
 }
 
-impl<'a> Partition<'a> {
+impl<'a, D: BlockDevice + ?Sized + 'a> Partition<'a, D> {
     // note: start_block is the offset on block_device
-    pub fn new(block_device: &'a BlockDevice, partition_entry: &[u8]) -> Partition<'a> {
+    pub fn new(block_device: &'a D,
+               partition_entry: &[u8])
+               -> Result<Partition<'a, D>, StorageError> {
         if partition_entry.len() != 16 {
-            panic!("16");
+            return Err(StorageError::OutOfRange);
         }
 
         let partition_type = partition_entry[TYPE_OFFSET];
@@ -27,6 +31,20 @@ impl<'a> Partition<'a> {
         let block_count = four_bytes_at_offset(partition_entry, LBA_NUMBER_OF_SECTORS_OFFSET) as
                           usize;
 
+        Ok(Partition {
+            block_device: block_device,
+            partition_type: partition_type,
+            start_block: start_block,
+            block_count: block_count,
+        })
+    }
+
+    /// for partitions not described by an MBR entry, e.g. GPT partitions
+    pub fn with_range(block_device: &'a D,
+                      partition_type: u8,
+                      start_block: usize,
+                      block_count: usize)
+                      -> Partition<'a, D> {
         Partition {
             block_device: block_device,
             partition_type: partition_type,
@@ -38,17 +56,44 @@ impl<'a> Partition<'a> {
     pub fn get_partition_type(&self) -> u8 {
         self.partition_type
     }
+
+    pub fn get_start_block(&self) -> usize {
+        self.start_block
+    }
+
+    /// whole blocks inside the partition only
+    fn check_range(&self, lba: usize, length: usize) -> Result<(), StorageError> {
+        let block_size = self.block_device.block_size();
+        if length % block_size != 0 || lba + length / block_size > self.block_count {
+            return Err(StorageError::OutOfRange);
+        }
+        Ok(())
+    }
 }
 
-impl<'a> BlockDevice for Partition<'a> {
-    fn read_blocks(&self, offset: usize, number: usize) -> Vec<u8> {
+impl<'a, D> BlockDevice for Partition<'a, D>
+    where D: BlockDevice + ?Sized + 'a,
+          D::Error: Into<StorageError>
+{
+    type Error = StorageError;
+
+    /// reads and writes beyond the end of the partition are rejected
+    fn read(&self, lba: usize, buffer: &mut [u8]) -> Result<(), StorageError> {
+        self.check_range(lba, buffer.len())?;
+        self.block_device
+            .read(self.start_block + lba, buffer)
+            .map_err(Into::into)
+    }
+
+    fn write(&self, lba: usize, buffer: &[u8]) -> Result<(), StorageError> {
+        self.check_range(lba, buffer.len())?;
         self.block_device
-            .read_blocks(self.start_block + offset, number)
+            .write(self.start_block + lba, buffer)
+            .map_err(Into::into)
     }
 
-    #[allow(unused_variables)]
-    fn write_blocks(&self, offset: usize, blocks: &[u8]) -> Result<usize, ()> {
-        unimplemented!();
+    fn flush(&self) -> Result<(), StorageError> {
+        self.block_device.flush().map_err(Into::into)
     }
 
     fn number_of_blocks(&self) -> usize {
@@ -0,0 +1,79 @@
+use block_device::BlockDevice;
+use super::error::StorageError;
+use core::marker::PhantomData;
+use core::ptr;
+
+const BLOCK_SIZE: usize = 512;
+
+/// block device in memory, e.g. a region of the SDRAM
+/// its content is lost on reset, it is meant for scratch volumes and staging files
+pub struct RamDisk<'a> {
+    start: *mut u8,
+    number_of_blocks: usize,
+    memory: PhantomData<&'a mut [u8]>,
+}
+
+impl RamDisk<'static> {
+    /// the region [start; start + size) must not be used by anything else,
+    /// e.g. it is taken from SDRAM_START..SDRAM_END behind the LCD section
+    /// a partial block at the end is not used
+    pub unsafe fn new(start: usize, size: usize) -> RamDisk<'static> {
+        RamDisk {
+            start: start as *mut u8,
+            number_of_blocks: size / BLOCK_SIZE,
+            memory: PhantomData,
+        }
+    }
+}
+
+impl<'a> RamDisk<'a> {
+    /// borrows memory, e.g. a buffer on the heap
+    pub fn from_slice(memory: &'a mut [u8]) -> RamDisk<'a> {
+        RamDisk {
+            start: memory.as_mut_ptr(),
+            number_of_blocks: memory.len() / BLOCK_SIZE,
+            memory: PhantomData,
+        }
+    }
+
+    /// whole blocks inside the disk only, returns the address of block lba
+    fn address(&self, lba: usize, length: usize) -> Result<*mut u8, StorageError> {
+        if length % BLOCK_SIZE != 0 || lba + length / BLOCK_SIZE > self.number_of_blocks {
+            return Err(StorageError::OutOfRange);
+        }
+        Ok(unsafe { self.start.offset((lba * BLOCK_SIZE) as isize) })
+    }
+}
+
+impl<'a> BlockDevice for RamDisk<'a> {
+    type Error = StorageError;
+
+    fn read(&self, lba: usize, buffer: &mut [u8]) -> Result<(), StorageError> {
+        let address = self.address(lba, buffer.len())?;
+        unsafe {
+            ptr::copy_nonoverlapping(address, buffer.as_mut_ptr(), buffer.len());
+        }
+        Ok(())
+    }
+
+    fn write(&self, lba: usize, buffer: &[u8]) -> Result<(), StorageError> {
+        let address = self.address(lba, buffer.len())?;
+        unsafe {
+            ptr::copy_nonoverlapping(buffer.as_ptr(), address, buffer.len());
+        }
+        Ok(())
+    }
+
+    /// nothing is buffered
+    fn flush(&self) -> Result<(), StorageError> {
+        Ok(())
+    }
+
+    fn number_of_blocks(&self) -> usize {
+        self.number_of_blocks
+    }
+
+    fn block_size(&self) -> usize {
+        BLOCK_SIZE
+    }
+}
@@ -0,0 +1,105 @@
+use super::attributes::Attributes;
+use super::directory_entry::*;
+use super::error::StorageError;
+use super::fat32_device_driver::Fat32DeviceDriver;
+use collections::vec::*;
+
+/// iterator over the entries of a directory, see Fat32DeviceDriver::read_dir
+/// deleted entries as well as "." and ".." are skipped
+/// the cluster chain is read one cluster at a time
+/// after an error the iteration ends
+/// entries with one of the attributes given to hide(...) are skipped as well
+pub struct ReadDir<'a, 'b: 'a> {
+    driver: &'a Fat32DeviceDriver<'b>,
+    cluster: usize,
+    buffer: Vec<u8>,
+    offset: usize,
+    long_name: LongNameBuilder,
+    is_finished: bool,
+    hidden: Attributes,
+}
+
+impl<'a, 'b: 'a> ReadDir<'a, 'b> {
+    pub fn new(driver: &'a Fat32DeviceDriver<'b>, first_cluster: usize) -> ReadDir<'a, 'b> {
+        ReadDir {
+            driver: driver,
+            cluster: first_cluster,
+            buffer: Vec::new(),
+            offset: 0,
+            long_name: LongNameBuilder::new(),
+            is_finished: false,
+            hidden: Attributes::empty(),
+        }
+    }
+
+    /// e.g. hide(attributes::HIDDEN | attributes::SYSTEM) lists what a file manager would
+    pub fn hide(mut self, attributes: Attributes) -> ReadDir<'a, 'b> {
+        self.hidden = attributes;
+        self
+    }
+
+    /// reads the next cluster of the chain into the buffer
+    /// the fixed root directory of FAT12/16 is read at once
+    /// returns false at the end of the chain
+    fn next_cluster(&mut self) -> Result<bool, StorageError> {
+        if self.driver.is_fixed_root_directory(self.cluster) {
+            if !self.buffer.is_empty() {
+                return Ok(false);
+            }
+            self.buffer = self.driver.read_fixed_root_directory()?;
+            self.offset = 0;
+            return Ok(!self.buffer.is_empty());
+        }
+        if !self.buffer.is_empty() {
+            self.cluster = match self.driver.next_cluster(self.cluster)? {
+                Some(cluster) => cluster,
+                None => return Ok(false),
+            };
+        }
+        if self.buffer.is_empty() {
+            self.buffer.resize(self.driver.cluster_size(), 0);
+        }
+        self.driver.read_cluster_data_region(self.cluster, &mut self.buffer)?;
+        self.offset = 0;
+        Ok(true)
+    }
+}
+
+impl<'a, 'b: 'a> Iterator for ReadDir<'a, 'b> {
+    type Item = Result<DirectoryEntry, StorageError>;
+
+    fn next(&mut self) -> Option<Result<DirectoryEntry, StorageError>> {
+        while !self.is_finished {
+            if self.offset >= self.buffer.len() {
+                match self.next_cluster() {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        self.is_finished = true;
+                        break;
+                    }
+                    Err(e) => {
+                        self.is_finished = true;
+                        return Some(Err(e));
+                    }
+                }
+            }
+            let directory_entry = &self.buffer[self.offset..self.offset + 32];
+            self.offset += 32;
+
+            match directory_entry[0] {
+                0x00 => self.is_finished = true,
+                0xE5 => self.long_name.clear(),
+                b'.' => self.long_name.clear(),
+                _ if is_long_name_entry(directory_entry) => self.long_name.push(directory_entry),
+                _ => {
+                    let long_name = self.long_name.take(directory_entry);
+                    match DirectoryEntry::with_long_name(directory_entry, long_name) {
+                        Ok(ref entry) if entry.attributes().intersects(self.hidden) => {}
+                        result => return Some(result),
+                    }
+                }
+            }
+        }
+        None
+    }
+}